@@ -0,0 +1,348 @@
+//! Provides sprite animation clips played from [`Atlas`]es.
+//!
+//! Each [`Atlas`] may contain [`AtlasAnimation`] clips, which are sequences of its sprites along
+//! with how long each of them is shown. These are either gathered from numbered sprites (e.g.,
+//! `walk_0.png` through `walk_7.png` make up the `walk` clip), or defined explicitly in the atlas
+//! file; see [`AtlasFile::animations`](crate::loader::AtlasFile::animations).
+//!
+//! Entities with [`AnimatedAtlasEntry`] play a clip through [`animate_atlas_entries`], which keeps
+//! their [`AtlasCaches`](crate::atlas::AtlasCaches) pointing at the current frame and sends
+//! [`AtlasAnimationEvent`]s whenever a clip loops or finishes.
+
+use std::path::PathBuf;
+
+use bevy::{asset::ReflectAsset, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::atlas::{Atlas, AtlasEntries};
+
+/// A sequence of [`Atlas`] sprites, added to the atlas as a labeled asset with label
+/// `"animation-{path}"`.
+#[derive(Asset, Reflect, Clone, Debug)]
+#[reflect(Asset, Debug)]
+pub struct AtlasAnimation {
+    /// The frames, in order.
+    pub frames: Vec<AtlasFrame>,
+    /// What happens when the last frame ends.
+    pub mode: AnimationMode,
+}
+
+impl AtlasAnimation {
+    /// The total duration of one pass through all frames, in seconds.
+    #[inline]
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|frame| frame.duration).sum()
+    }
+}
+
+/// A single frame of an [`AtlasAnimation`].
+#[derive(Reflect, Clone, Debug)]
+#[reflect(Debug)]
+pub struct AtlasFrame {
+    /// The sprite path within the [`Atlas`].
+    pub sprite: PathBuf,
+    /// How long this frame is shown, in seconds.
+    pub duration: f32,
+}
+
+/// Defines what an [`AtlasAnimation`] does when its last frame ends.
+#[derive(Serialize, Deserialize, Reflect, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[reflect(Default, Debug, PartialEq, Hash)]
+pub enum AnimationMode {
+    /// Stops at the last frame.
+    Once,
+    /// Starts over from the first frame.
+    #[default]
+    Loop,
+    /// Plays backwards to the first frame, then forwards again, and so on.
+    PingPong,
+}
+
+/// Plays an [`AtlasAnimation`] from an [`Atlas`], caching the current frame's sprite like
+/// [`AtlasEntry`](crate::atlas::AtlasEntry) does.
+#[derive(Component, Reflect, AtlasEntries, Clone, Debug)]
+#[reflect(Debug)]
+pub struct AnimatedAtlasEntry {
+    /// Handle to the [`Atlas`].
+    #[atlas]
+    pub atlas: Handle<Atlas>,
+    /// Playback speed multiplier.
+    pub speed: f32,
+    /// Whether the animation is currently advancing.
+    pub playing: bool,
+    animation: PathBuf,
+    frame: usize,
+    elapsed: f32,
+    backwards: bool,
+    #[entry]
+    sprite: PathBuf,
+}
+
+impl AnimatedAtlasEntry {
+    /// Creates a new [`AnimatedAtlasEntry`] playing the given animation path from its first frame.
+    #[inline]
+    pub fn new(atlas: Handle<Atlas>, animation: impl Into<PathBuf>) -> Self {
+        Self {
+            atlas,
+            speed: 1.,
+            playing: true,
+            animation: animation.into(),
+            frame: 0,
+            elapsed: 0.,
+            backwards: false,
+            sprite: PathBuf::new(),
+        }
+    }
+
+    /// Sets the playback speed multiplier.
+    #[inline]
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// The animation path within the [`Atlas`].
+    #[inline]
+    pub fn animation(&self) -> &PathBuf {
+        &self.animation
+    }
+
+    /// The index of the current frame.
+    #[inline]
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// The sprite path of the current frame, or empty if the animation hasn't been resolved yet.
+    #[inline]
+    pub fn sprite(&self) -> &PathBuf {
+        &self.sprite
+    }
+
+    /// Switches to another animation, restarting from its first frame. Does nothing if it's
+    /// already the current animation.
+    #[inline]
+    pub fn set_animation(&mut self, animation: impl Into<PathBuf>) {
+        let animation = animation.into();
+        if self.animation != animation {
+            self.animation = animation;
+            self.restart()
+        }
+    }
+
+    /// Restarts the current animation from its first frame and resumes playing.
+    #[inline]
+    pub fn restart(&mut self) {
+        self.frame = 0;
+        self.elapsed = 0.;
+        self.backwards = false;
+        self.playing = true;
+    }
+
+    /// Advances through the frames of `animation`, which must have at least one, by `delta`
+    /// seconds scaled by the [speed](Self::speed). Calls `event` for each time it loops or
+    /// finishes along the way.
+    fn advance(&mut self, animation: &AtlasAnimation, delta: f32, mut event: impl FnMut(AtlasAnimationEventKind)) {
+        let len = animation.frames.len();
+        self.frame = self.frame.min(len - 1);
+
+        // Don't spin forever on animations that don't take any time.
+        if !self.playing || animation.duration() <= 0. {
+            return
+        }
+
+        self.elapsed += delta * self.speed;
+        while self.playing && self.elapsed >= animation.frames[self.frame].duration {
+            self.elapsed -= animation.frames[self.frame].duration;
+
+            let kind = match (animation.mode, self.backwards) {
+                (AnimationMode::Once, ..) if self.frame + 1 == len => {
+                    self.elapsed = 0.;
+                    self.playing = false;
+                    Some(AtlasAnimationEventKind::Finished)
+                }
+                // A single frame played back and forth is the same as looping it.
+                (AnimationMode::Loop, ..) | (AnimationMode::PingPong, ..) if len == 1 => {
+                    Some(AtlasAnimationEventKind::Looped)
+                }
+                (AnimationMode::Loop, ..) if self.frame + 1 == len => {
+                    self.frame = 0;
+                    Some(AtlasAnimationEventKind::Looped)
+                }
+                (AnimationMode::PingPong, false) if self.frame + 1 == len => {
+                    self.frame -= 1;
+                    self.backwards = true;
+                    (self.frame == 0).then_some(AtlasAnimationEventKind::Looped)
+                }
+                (AnimationMode::PingPong, true) if self.frame == 0 => {
+                    self.frame = 1;
+                    self.backwards = false;
+                    None
+                }
+                (AnimationMode::PingPong, true) => {
+                    self.frame -= 1;
+                    (self.frame == 0).then_some(AtlasAnimationEventKind::Looped)
+                }
+                _ => {
+                    self.frame += 1;
+                    None
+                }
+            };
+
+            if let Some(kind) = kind {
+                event(kind)
+            }
+        }
+    }
+}
+
+/// Sent by [`animate_atlas_entries`] when an [`AnimatedAtlasEntry`] reaches the end of its clip.
+#[derive(Event, Reflect, Copy, Clone, Debug, PartialEq, Eq)]
+#[reflect(Debug, PartialEq)]
+pub struct AtlasAnimationEvent {
+    /// The entity playing the animation.
+    pub entity: Entity,
+    /// What happened.
+    pub kind: AtlasAnimationEventKind,
+}
+
+/// See [`AtlasAnimationEvent`].
+#[derive(Reflect, Copy, Clone, Debug, PartialEq, Eq)]
+#[reflect(Debug, PartialEq)]
+pub enum AtlasAnimationEventKind {
+    /// A [looping](AnimationMode::Loop) or [ping-ponging](AnimationMode::PingPong) animation
+    /// completed a full cycle and starts over.
+    Looped,
+    /// A [one-shot](AnimationMode::Once) animation reached the end of its last frame and stopped.
+    Finished,
+}
+
+/// Advances [`AnimatedAtlasEntry`]s and points them at their current frame's sprite, so that
+/// [`AtlasCaches`](crate::atlas::AtlasCaches) is updated accordingly.
+pub fn animate_atlas_entries(
+    time: Res<Time>,
+    atlases: Res<Assets<Atlas>>,
+    animations: Res<Assets<AtlasAnimation>>,
+    mut query: Query<(Entity, &mut AnimatedAtlasEntry)>,
+    mut events: EventWriter<AtlasAnimationEvent>,
+) {
+    let delta = time.delta_secs();
+    for (e, mut entry) in &mut query {
+        let Some(animation) = atlases
+            .get(&entry.atlas)
+            .and_then(|atlas| atlas.animations.get(&entry.animation))
+            .and_then(|handle| animations.get(handle))
+        else {
+            continue
+        };
+
+        if animation.frames.is_empty() {
+            continue
+        }
+
+        // Bypass change detection; only switching sprites should trigger a cache lookup.
+        let state = entry.bypass_change_detection();
+        state.advance(animation, delta, |kind| {
+            events.write(AtlasAnimationEvent { entity: e, kind });
+        });
+
+        let sprite = &animation.frames[state.frame].sprite;
+        if state.sprite != *sprite {
+            state.sprite.clone_from(sprite);
+            entry.set_changed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(mode: AnimationMode, durations: &[f32]) -> AtlasAnimation {
+        AtlasAnimation {
+            frames: durations
+                .iter()
+                .enumerate()
+                .map(|(i, &duration)| AtlasFrame {
+                    sprite: PathBuf::from(i.to_string()),
+                    duration,
+                })
+                .collect(),
+            mode,
+        }
+    }
+
+    fn step(entry: &mut AnimatedAtlasEntry, animation: &AtlasAnimation, delta: f32) -> Vec<AtlasAnimationEventKind> {
+        let mut events = Vec::new();
+        entry.advance(animation, delta, |kind| events.push(kind));
+        events
+    }
+
+    #[test]
+    fn once_finishes() {
+        let animation = clip(AnimationMode::Once, &[0.5, 0.5]);
+        let mut entry = AnimatedAtlasEntry::new(Handle::default(), "clip");
+
+        assert!(step(&mut entry, &animation, 0.75).is_empty());
+        assert_eq!(entry.frame, 1);
+        assert_eq!(step(&mut entry, &animation, 0.5), [AtlasAnimationEventKind::Finished]);
+        assert_eq!(entry.frame, 1);
+        assert!(!entry.playing);
+        assert!(step(&mut entry, &animation, 10.).is_empty());
+    }
+
+    #[test]
+    fn large_delta_loops_several_times() {
+        let animation = clip(AnimationMode::Loop, &[0.25, 0.25]);
+        let mut entry = AnimatedAtlasEntry::new(Handle::default(), "clip");
+
+        assert_eq!(step(&mut entry, &animation, 1.625), [AtlasAnimationEventKind::Looped; 3]);
+        assert_eq!(entry.frame, 0);
+        assert_eq!(entry.elapsed, 0.125);
+    }
+
+    #[test]
+    fn ping_pong_bounces() {
+        let animation = clip(AnimationMode::PingPong, &[1., 1., 1.]);
+        let mut entry = AnimatedAtlasEntry::new(Handle::default(), "clip");
+
+        let mut frames = Vec::new();
+        let mut events = Vec::new();
+        for _ in 0..8 {
+            events.extend(step(&mut entry, &animation, 1.));
+            frames.push(entry.frame);
+        }
+
+        assert_eq!(frames, [1, 2, 1, 0, 1, 2, 1, 0]);
+        assert_eq!(events, [AtlasAnimationEventKind::Looped; 2]);
+    }
+
+    #[test]
+    fn one_frame_ping_pong() {
+        let animation = clip(AnimationMode::PingPong, &[1.]);
+        let mut entry = AnimatedAtlasEntry::new(Handle::default(), "clip");
+
+        assert_eq!(step(&mut entry, &animation, 3.5), [AtlasAnimationEventKind::Looped; 3]);
+        assert_eq!(entry.frame, 0);
+        assert!(entry.playing);
+    }
+
+    #[test]
+    fn zero_duration_frames() {
+        let animation = clip(AnimationMode::Loop, &[0., 1., 0.]);
+        let mut entry = AnimatedAtlasEntry::new(Handle::default(), "clip");
+
+        // Zero-duration frames are skipped over instead of shown.
+        assert!(step(&mut entry, &animation, 0.).is_empty());
+        assert_eq!(entry.frame, 1);
+        assert_eq!(step(&mut entry, &animation, 1.), [AtlasAnimationEventKind::Looped]);
+        assert_eq!(entry.frame, 1);
+
+        // Animations that take no time at all stay put rather than spinning forever.
+        let animation = clip(AnimationMode::Loop, &[0., 0.]);
+        let mut entry = AnimatedAtlasEntry::new(Handle::default(), "clip");
+        assert!(step(&mut entry, &animation, 1.).is_empty());
+        assert_eq!(entry.frame, 0);
+    }
+}
@@ -16,12 +16,13 @@
 //! See the `examples/atlas` for a full example.
 
 use std::{
+    collections::BTreeMap,
     hash::Hash,
     path::{Path, PathBuf},
 };
 
 use bevy::{
-    asset::ReflectAsset,
+    asset::{AssetPath, ReflectAsset},
     ecs::{component::Tick, system::SystemChangeTick},
     platform::collections::{Equivalent, HashMap},
     prelude::*,
@@ -30,6 +31,8 @@ use derive_more::{Display, Error, From};
 pub use hephae_atlas_derive::AtlasEntries;
 use smallvec::SmallVec;
 
+use crate::animation::AtlasAnimation;
+
 /// A list of textures packed into one large texture. See the [module-level](crate::atlas)
 /// documentation for more specific information on how to integrate this into your rendering
 /// framework.
@@ -40,6 +43,15 @@ pub struct Atlas {
     pub pages: Vec<(Handle<TextureAtlasLayout>, Handle<Image>)>,
     /// Maps sprite paths to their corresponding atlas page and layout.
     pub sprites: HashMap<PathBuf, AtlasSprite>,
+    /// The ratio of area taken up by sprites (including their padding) to the page area, for each
+    /// page. Useful for comparing [packers](crate::packer::AtlasPacker) on real sprite sets.
+    pub efficiency: Vec<f32>,
+    /// Maps animation paths to their [`AtlasAnimation`] clips.
+    pub animations: HashMap<PathBuf, Handle<AtlasAnimation>>,
+    /// Directories searched for sprites by [globs](crate::loader::TextureAtlasEntry::Glob) and
+    /// [scans](crate::loader::TextureAtlasEntry::Scan), watched by
+    /// [`AtlasWatchPlugin`](crate::AtlasWatchPlugin).
+    pub directories: Vec<AssetPath<'static>>,
 }
 
 #[derive(Error, From, Debug, Display)]
@@ -71,10 +83,24 @@ impl Atlas {
         Ok(AtlasInfo {
             page: entry.atlas_page.id(),
             page_size: layout.size,
+            layer: entry.layer,
+            pivot: entry.meta.pivot.unwrap_or(entry.original_size.as_vec2() / 2.),
             rect,
+            original_size: entry.original_size,
+            trim_offset: entry.trim_offset,
+            rotated: entry.rotated,
             nine_slices: entry.nine_slices,
         })
     }
+
+    /// Gets the [metadata](SpriteMeta) of the given sprite entry.
+    #[inline]
+    pub fn get_meta(&self, path: &(impl Hash + Equivalent<PathBuf> + ?Sized)) -> Result<&SpriteMeta, AtlasEntryError> {
+        self.sprites
+            .get(path)
+            .map(|entry| &entry.meta)
+            .ok_or(AtlasEntryError::SpriteNotFound)
+    }
 }
 
 /// An individual sprite found in an [`Atlas`].
@@ -85,21 +111,74 @@ pub struct AtlasSprite {
     pub atlas: TextureAtlas,
     /// The texture atlas image handle, works in tandem with `bevy::sprite`.
     pub atlas_page: Handle<Image>,
+    /// The array layer of [`atlas_page`](Self::atlas_page) the sprite resides in, if the atlas
+    /// stores its pages in a single texture array. Zero otherwise. See
+    /// [`AtlasFile::array`](crate::loader::AtlasFile::array).
+    pub layer: u32,
+    /// The size of the sprite before being [trimmed](crate::loader::AtlasFile::trim).
+    pub original_size: UVec2,
+    /// Where the top-left corner of the trimmed sprite lies within the untrimmed sprite. Zero if
+    /// the sprite isn't trimmed.
+    pub trim_offset: UVec2,
+    /// Whether the sprite is stored rotated 90° clockwise in its page, as the packer may do to fit
+    /// sprites tighter. If so, its layout rectangle has its width and height swapped. See
+    /// [`AtlasFile::rotate`](crate::loader::AtlasFile::rotate).
+    pub rotated: bool,
     /// Optional nine-slice cuts for this sprite.
     pub nine_slices: Option<NineSliceCuts>,
+    /// Gameplay metadata authored alongside the sprite.
+    pub meta: SpriteMeta,
 }
 
-/// Defines horizontal and vertical slashes that split a sprite into nine patches.
-#[derive(Reflect, Copy, Clone, Debug)]
+/// Gameplay metadata of a sprite, e.g. where it's anchored, what it collides with, and what it's
+/// made of. Authored either in a `.meta.ron` file next to the sprite image, if the atlas enables
+/// [sidecars](crate::loader::AtlasFile::sidecars), or inline in its atlas entry; see
+/// [`SpriteMetaFile`](crate::loader::SpriteMetaFile).
+///
+/// Positions are in pixels with "+Y = down", relative to the top-left corner of the untrimmed
+/// sprite, excluding any 9-patch markers.
+#[derive(Asset, Reflect, Clone, Debug, Default, PartialEq)]
+#[reflect(Asset, Debug, Default)]
+pub struct SpriteMeta {
+    /// The point the sprite is anchored at. `None` means the center of the untrimmed sprite.
+    pub pivot: Option<Vec2>,
+    /// The vertices of a collision polygon, in order. Empty if the sprite has no hitbox.
+    pub hitbox: Vec<Vec2>,
+    /// Arbitrary key/value tags.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Defines horizontal and vertical slashes that split a sprite into nine patches. The cuts are
+/// relative to the sprite itself, excluding any 9-patch markers.
+#[derive(Reflect, Copy, Clone, Debug, PartialEq, Eq)]
 #[reflect(Debug)]
 pub struct NineSliceCuts {
     /// The leftmost vertical cut. Pixels that `x < left` are considered the left side edge.
     pub left: u32,
-    /// The rightmost vertical cut. Pixels that `x > right` are considered the right side edge.
+    /// The rightmost vertical cut. Pixels that `x >= right` are considered the right side edge.
+    pub right: u32,
+    /// The topmost horizontal cut. Pixels that `y < top` are considered the top side edge.
+    pub top: u32,
+    /// The bottommost horizontal cut. Pixels that `y >= bottom` are considered the bottom side
+    /// edge.
+    pub bottom: u32,
+    /// Where content laid inside the sprite should be inset from its edges, as defined by the
+    /// bottom and right markers of 9-patch images.
+    pub content_padding: Option<ContentPadding>,
+}
+
+/// Insets of the area content should be laid in within a nine-sliced sprite, e.g. the label of a
+/// button, in pixels.
+#[derive(Reflect, Copy, Clone, Debug, PartialEq, Eq)]
+#[reflect(Debug)]
+pub struct ContentPadding {
+    /// The inset from the left edge.
+    pub left: u32,
+    /// The inset from the right edge.
     pub right: u32,
-    /// The topmost vertical cut. Pixels that `y < top` are considered the top side edge.
+    /// The inset from the top edge.
     pub top: u32,
-    /// The bottommost vertical cut. Pixels that `y > bottom` are considered the bottom side edge.
+    /// The inset from the bottom edge.
     pub bottom: u32,
 }
 
@@ -112,15 +191,27 @@ pub trait AtlasEntries: Component {
 }
 
 /// Caches information provided by [`Atlas::get_info`].
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct AtlasInfo {
     /// The page image [`AssetId`], useful for vertex pipeline keys.
     pub page: AssetId<Image>,
     /// The page size, so you don't have to query the image size again.
     pub page_size: UVec2,
+    /// See [`AtlasSprite::layer`]. Pass this along the UV coordinates, e.g. as a vertex attribute,
+    /// to sample array pages.
+    pub layer: u32,
+    /// See [`SpriteMeta::pivot`], defaulted to the center of the untrimmed sprite. The rest of the
+    /// metadata may be looked up with [`Atlas::get_meta`].
+    pub pivot: Vec2,
     /// Where the sprite resides within the page. Note that images are "+Y = down" so you might have
-    /// to flip the V coordinate.
+    /// to flip the V coordinate, and that the sprite may be [rotated](Self::rotated) within it.
     pub rect: URect,
+    /// See [`AtlasSprite::original_size`].
+    pub original_size: UVec2,
+    /// See [`AtlasSprite::trim_offset`].
+    pub trim_offset: UVec2,
+    /// See [`AtlasSprite::rotated`].
+    pub rotated: bool,
     /// The nine-slicing information, if any. Useful for tiling and UI.
     pub nine_slices: Option<NineSliceCuts>,
 }
@@ -132,12 +223,47 @@ impl AtlasInfo {
             uuid: AssetId::<Image>::DEFAULT_UUID,
         },
         page_size: UVec2::ZERO,
+        layer: 0,
+        pivot: Vec2::ZERO,
         rect: URect {
             min: UVec2::ZERO,
             max: UVec2::ZERO,
         },
+        original_size: UVec2::ZERO,
+        trim_offset: UVec2::ZERO,
+        rotated: false,
         nine_slices: None,
     };
+
+    /// The size of the sprite as displayed, i.e., the size of [`rect`](Self::rect) with its width
+    /// and height swapped back if [rotated](Self::rotated).
+    #[inline]
+    pub fn size(&self) -> UVec2 {
+        let size = self.rect.size();
+        if self.rotated { size.yx() } else { size }
+    }
+
+    /// Maps a point within the sprite to its page UV coordinates, accounting for
+    /// [rotation](Self::rotated). `(0, 0)` is the bottom-left corner of the sprite as displayed,
+    /// and `(1, 1)` is the top-right corner.
+    #[inline]
+    pub fn uv(&self, point: Vec2) -> Vec2 {
+        let page = self.page_size.max(UVec2::ONE).as_vec2();
+        let (min, max) = (self.rect.min.as_vec2() / page, self.rect.max.as_vec2() / page);
+
+        if self.rotated {
+            vec2(min.x.lerp(max.x, point.y), min.y.lerp(max.y, point.x))
+        } else {
+            vec2(min.x.lerp(max.x, point.x), max.y.lerp(min.y, point.y))
+        }
+    }
+
+    /// Returns the region the trimmed sprite occupies within the untrimmed sprite, in pixels with
+    /// "+Y = down". For untrimmed sprites, this spans the whole sprite.
+    #[inline]
+    pub fn trim_rect(&self) -> URect {
+        URect::from_corners(self.trim_offset, self.trim_offset + self.size())
+    }
 }
 
 impl Default for AtlasInfo {
@@ -240,46 +366,49 @@ pub(crate) fn update_caches<T: AtlasEntries>(
     layouts: Res<Assets<TextureAtlasLayout>>,
     changes: Res<AtlasChanges>,
     tick: SystemChangeTick,
-    mut query: Query<(Entity, &T, &mut AtlasCaches)>,
+    mut query: Query<(Entity, Ref<T>, &mut AtlasCaches)>,
 ) {
     let last = tick.last_run();
     let this = tick.this_run();
-
-    if !changes.last_change.is_newer_than(last, this) {
-        return
-    }
+    let has_changes = changes.last_change.is_newer_than(last, this);
 
     for (e, entries, mut caches) in &mut query {
-        let mut len = 0;
-        for (i, (atlas, path)) in entries.entries().enumerate() {
-            let info = match caches.get_mut(i) {
-                Some(info) => info,
-                None => {
-                    caches.push(AtlasInfo::DEFAULT);
-                    &mut caches[i]
-                }
-            };
+        // Entries that changed themselves (e.g., newly spawned, or their paths modified) have to be
+        // looked up again regardless of whether their atlases changed.
+        let entries_changed = entries.is_changed();
+        if !has_changes && !entries_changed {
+            continue
+        }
 
+        let mut infos = SmallVec::<[AtlasInfo; 4]>::new();
+        for (i, (atlas, path)) in entries.entries().enumerate() {
             let Some(atlas) = changes
                 .changes
                 .get(&atlas)
-                .and_then(|&tick| tick.is_newer_than(last, this).then_some(atlases.get(atlas)?))
+                .and_then(|&tick| (entries_changed || tick.is_newer_than(last, this)).then_some(atlases.get(atlas)?))
             else {
-                *info = AtlasInfo::DEFAULT;
+                infos.push(if entries_changed || !changes.changes.contains_key(&atlas) {
+                    AtlasInfo::DEFAULT
+                } else {
+                    caches.get(i).copied().unwrap_or(AtlasInfo::DEFAULT)
+                });
+
                 continue
             };
 
-            match atlas.get_info(&layouts, path) {
-                Ok(res) => *info = res,
+            infos.push(match atlas.get_info(&layouts, path) {
+                Ok(info) => info,
                 Err(error) => {
-                    *info = AtlasInfo::DEFAULT;
-                    error!("Couldn't update `AtlasCaches` for {e}: {error}")
+                    error!("Couldn't update `AtlasCaches` for {e}: {error}");
+                    AtlasInfo::DEFAULT
                 }
-            }
-
-            len = i;
+            });
         }
 
-        caches.truncate(len + 1);
+        // Only write if anything actually differs, so that systems reacting to `Changed<AtlasCaches>`
+        // (e.g., tilemaps invalidating their chunks) don't redo their work needlessly.
+        if caches.caches != infos {
+            caches.caches = infos
+        }
     }
 }
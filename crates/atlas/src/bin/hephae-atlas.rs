@@ -0,0 +1,297 @@
+//! Packs `.atlas.ron` files outside of an app, the same way
+//! [`AtlasLoader`](hephae_atlas::loader::AtlasLoader) does, writing the page images and a report
+//! of where each sprite ended up. Useful to validate atlases in build scripts; the process exits
+//! with a failure code if the atlas couldn't be packed, e.g. because a sprite doesn't fit in the
+//! maximum page size.
+//!
+//! Sprite images are read straight off the file system, so only PNG images are supported. Pages are
+//! written the same way [`encode_page`](hephae_atlas::process::encode_page) encodes them, i.e. only
+//! their full-size level, with `rgba16_float` pages holding the bits of their half-floats. Layers
+//! of [array](hephae_atlas::loader::AtlasFile::array) atlases are written as separate page images.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use bevy::{
+    asset::{AssetPath, RenderAssetUsages, ron, ron::ser::PrettyConfig},
+    prelude::*,
+    tasks::block_on,
+};
+use hephae_atlas::{
+    atlas::SpriteMeta,
+    loader::{
+        AtlasFile, AtlasSources, PackedPage, SpriteMetaFile, TextureAtlasSettings, collect_sprites, pack_sprites,
+        resolve_animations,
+    },
+    process::encode_page,
+};
+use image::ImageFormat;
+use serde::Serialize;
+
+const USAGE: &str = "\
+Usage: hephae-atlas <ATLAS_FILE> [OPTIONS]
+
+Packs an `.atlas.ron` file, writing `<NAME>.page-<N>.png` page images and a `<NAME>.report.ron`
+report of the pages and sprites.
+
+Options:
+  -o, --out <DIR>         Where to write the pages and report [default: next to the atlas file]
+      --check             Only print the report, without writing anything
+      --init-width <PX>   The initial page width [default: 1024]
+      --init-height <PX>  The initial page height [default: 1024]
+      --max-width <PX>    The maximum page width [default: 4096]
+      --max-height <PX>   The maximum page height [default: 4096]
+  -h, --help              Print this message";
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+struct Args {
+    atlas: PathBuf,
+    out: Option<PathBuf>,
+    check: bool,
+    settings: TextureAtlasSettings,
+}
+
+fn parse_args() -> Result<Option<Args>, Box<dyn Error>> {
+    let mut atlas = None;
+    let mut out = None;
+    let mut check = false;
+    let mut settings = TextureAtlasSettings::default();
+
+    let mut args = env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-h" | "--help") => return Ok(None),
+            Some("-o" | "--out") => out = Some(PathBuf::from(args.next().ok_or("missing value for `--out`")?)),
+            Some("--check") => check = true,
+            Some(flag @ ("--init-width" | "--init-height" | "--max-width" | "--max-height")) => {
+                let value = args
+                    .next()
+                    .and_then(|value| value.to_str()?.parse::<u32>().ok())
+                    .ok_or_else(|| format!("expected a number of pixels for `{flag}`"))?;
+
+                *match flag {
+                    "--init-width" => &mut settings.init_width,
+                    "--init-height" => &mut settings.init_height,
+                    "--max-width" => &mut settings.max_width,
+                    _ => &mut settings.max_height,
+                } = value;
+            }
+            Some(flag) if flag.starts_with('-') => return Err(format!("unknown option `{flag}`\n\n{USAGE}").into()),
+            _ if atlas.is_none() => atlas = Some(PathBuf::from(arg)),
+            _ => return Err(format!("only one atlas file may be given\n\n{USAGE}").into()),
+        }
+    }
+
+    let Some(atlas) = atlas else { return Err(format!("missing atlas file\n\n{USAGE}").into()) };
+    Ok(Some(Args {
+        atlas,
+        out,
+        check,
+        settings,
+    }))
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let Some(Args {
+        atlas,
+        out,
+        check,
+        settings,
+    }) = parse_args()?
+    else {
+        println!("{USAGE}");
+        return Ok(())
+    };
+
+    let bytes = fs::read(&atlas).map_err(|e| format!("Atlas file '{}' couldn't be read: {e}", atlas.display()))?;
+    let file = ron::de::from_bytes::<AtlasFile>(&bytes)?;
+
+    let root = atlas.parent().unwrap_or(Path::new(""));
+    let sprites = block_on(collect_sprites(
+        &mut FileSources { root: root.to_owned() },
+        &file,
+        &AssetPath::from(""),
+    ))?;
+
+    let pages = pack_sprites(&file, &settings, sprites)?;
+    let animations = resolve_animations(
+        &file,
+        pages.iter().flat_map(|page| page.sprites.iter().map(|(name, ..)| name)),
+    )?;
+
+    let file_name = atlas.file_name().unwrap_or_default().to_string_lossy();
+    let name = file_name.strip_suffix(".atlas.ron").unwrap_or(&file_name);
+
+    let mut report = Report {
+        pages: Vec::with_capacity(pages.len()),
+        sprites: BTreeMap::new(),
+        animations: animations
+            .into_iter()
+            .map(|(path, animation)| (path, animation.frames.into_iter().map(|frame| frame.sprite).collect()))
+            .collect(),
+    };
+
+    for (index, page) in pages.iter().enumerate() {
+        report.pages.push(PageReport {
+            file: format!("{name}.page-{index}.png"),
+            size: page.layout.size.into(),
+            occupancy: page.efficiency,
+            sprites: page.sprites.len(),
+        });
+
+        for (name, sprite) in &page.sprites {
+            let rect = page.layout.textures[sprite.atlas.index];
+            report.sprites.insert(name.clone(), SpriteReport {
+                page: index,
+                rect: (rect.min.x, rect.min.y, rect.max.x, rect.max.y),
+                rotated: sprite.rotated,
+                original_size: sprite.original_size.into(),
+                trim_offset: sprite.trim_offset.into(),
+                nine_slices: sprite.nine_slices.map(|cuts| (cuts.left, cuts.right, cuts.top, cuts.bottom)),
+                content_padding: sprite
+                    .nine_slices
+                    .and_then(|cuts| cuts.content_padding)
+                    .map(|padding| (padding.left, padding.right, padding.top, padding.bottom)),
+                meta: (&sprite.meta).into(),
+            });
+        }
+    }
+
+    let report_ron = ron::ser::to_string_pretty(&report, PrettyConfig::default())?;
+    if check {
+        println!("{report_ron}");
+        return Ok(())
+    }
+
+    let out = out.unwrap_or_else(|| root.to_owned());
+    fs::create_dir_all(&out)?;
+
+    for (PackedPage { image, .. }, PageReport { file, .. }) in pages.iter().zip(&report.pages) {
+        fs::write(out.join(file), encode_page(image, 0)?)?;
+    }
+
+    let report_path = out.join(format!("{name}.report.ron"));
+    fs::write(&report_path, report_ron)?;
+
+    for PageReport {
+        file,
+        size: (width, height),
+        occupancy,
+        sprites,
+    } in &report.pages
+    {
+        println!(
+            "{file}: {width}x{height}, {:.1}% occupied by {sprites} sprites",
+            occupancy * 100.
+        );
+    }
+
+    println!(
+        "Packed {} sprites into {} pages; report written to '{}'",
+        report.sprites.len(),
+        report.pages.len(),
+        report_path.display()
+    );
+
+    Ok(())
+}
+
+/// [`AtlasSources`] reading sprites straight off the file system, with asset paths relative to
+/// `root`.
+struct FileSources {
+    root: PathBuf,
+}
+
+impl AtlasSources for FileSources {
+    type Error = Box<dyn Error>;
+
+    async fn list_directory(&mut self, dir: &AssetPath<'static>) -> Result<Vec<AssetPath<'static>>, Self::Error> {
+        fn walk(root: &Path, dir: &Path, accum: &mut Vec<AssetPath<'static>>) -> io::Result<()> {
+            for entry in fs::read_dir(root.join(dir))? {
+                let path = dir.join(entry?.file_name());
+                if root.join(&path).is_dir() {
+                    walk(root, &path, accum)?
+                } else {
+                    accum.push(AssetPath::from(path))
+                }
+            }
+
+            Ok(())
+        }
+
+        let mut files = Vec::new();
+        walk(&self.root, dir.path(), &mut files)
+            .map_err(|e| format!("Directory '{}' couldn't be read: {e}", self.root.join(dir.path()).display()))?;
+
+        Ok(files)
+    }
+
+    async fn is_image(&mut self, path: &AssetPath<'static>) -> bool {
+        ImageFormat::from_path(path.path()).is_ok_and(|format| format.reading_enabled())
+    }
+
+    async fn load_image(&mut self, name: &Path, path: &AssetPath<'static>) -> Result<Image, Self::Error> {
+        let image = image::open(self.root.join(path.path()))
+            .map_err(|e| format!("Texture '{}' failed to load: {e}", name.display()))?;
+
+        Ok(Image::from_dynamic(image, true, RenderAssetUsages::all()))
+    }
+
+    async fn load_meta(&mut self, name: &Path, path: &AssetPath<'static>) -> Result<Option<SpriteMeta>, Self::Error> {
+        // Sidecar metadata is optional, so only a missing file is fine.
+        let bytes = match fs::read(self.root.join(path.path())) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Metadata of texture '{}' failed to load: {e}", name.display()).into()),
+        };
+
+        let meta = SpriteMetaFile::from_bytes(&bytes)
+            .map_err(|e| format!("Metadata of texture '{}' failed to load: {e}", name.display()))?;
+
+        Ok(Some(meta.into()))
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    pages: Vec<PageReport>,
+    sprites: BTreeMap<PathBuf, SpriteReport>,
+    animations: BTreeMap<PathBuf, Vec<PathBuf>>,
+}
+
+#[derive(Serialize)]
+struct PageReport {
+    file: String,
+    size: (u32, u32),
+    occupancy: f32,
+    sprites: usize,
+}
+
+#[derive(Serialize)]
+struct SpriteReport {
+    page: usize,
+    rect: (u32, u32, u32, u32),
+    rotated: bool,
+    original_size: (u32, u32),
+    trim_offset: (u32, u32),
+    nine_slices: Option<(u32, u32, u32, u32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_padding: Option<(u32, u32, u32, u32)>,
+    #[serde(skip_serializing_if = "SpriteMetaFile::is_empty")]
+    meta: SpriteMetaFile,
+}
@@ -0,0 +1,359 @@
+//! Provides [`DynamicAtlas`], an [`Atlas`] whose sprites may be inserted and removed at runtime.
+//!
+//! Loaded atlases are immutable, which doesn't suit images that only exist at runtime, e.g.
+//! generated portraits or images imported by users. [`DynamicAtlas`] owns an [`Atlas`] asset and
+//! allocates sprites into its pages through [`guillotiere`], growing the last page up to the
+//! maximum size and adding new pages as needed. Sprites are written into the existing page
+//! [`Image`]s, and since the [`Atlas`] and its layouts are modified in place, entities looking
+//! sprites up from it have their [`AtlasCaches`](crate::atlas::AtlasCaches) updated the same way
+//! as when a loaded atlas is hot-reloaded.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    asset::RenderAssetUsages,
+    platform::{collections::HashMap, hash::FixedHasher},
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use derive_more::{Display, Error};
+use guillotiere::{AllocId, AtlasAllocator, size2};
+
+use crate::{
+    atlas::{Atlas, AtlasSprite},
+    loader::{AtlasFile, TextureAtlasSettings},
+};
+
+/// Errors that may arise when inserting sprites into a [`DynamicAtlas`].
+#[derive(Error, Debug, Display)]
+pub enum DynamicAtlasError {
+    /// Error that arises when an image is larger than the maximum size of the atlas page.
+    #[display("Image '{}' is too large: [{actual_width}, {actual_height}] > [{max_width}, {max_height}]", path.display())]
+    TooLarge {
+        /// The sprite lookup key.
+        path: PathBuf,
+        /// The maximum width of the atlas page. See [`TextureAtlasSettings::max_width`].
+        max_width: u32,
+        /// The maximum width of the atlas page. See [`TextureAtlasSettings::max_height`].
+        max_height: u32,
+        /// The width of the erroneous image, including the padding.
+        actual_width: u32,
+        /// The height of the erroneous image, including the padding.
+        actual_height: u32,
+    },
+    /// Error that arises when the image couldn't be converted into
+    /// [`TextureFormat::Rgba8UnormSrgb`].
+    #[display("Image '{}' has an unsupported format: {format:?}", path.display())]
+    UnsupportedFormat {
+        /// The sprite lookup key.
+        path: PathBuf,
+        /// The invalid texture format.
+        format: TextureFormat,
+    },
+    /// Error that arises when the [`Atlas`], or one of its page images or layouts, was removed from
+    /// its [`Assets`] or has its pixel data dropped.
+    #[display("The dynamic atlas or one of its pages is missing")]
+    MissingAsset,
+}
+
+/// An [`Atlas`] whose sprites may be inserted and removed at runtime. See the
+/// [module-level](crate::dynamic) documentation for more information.
+///
+/// Page images are kept in both the main and render world, since sprites are written into them
+/// incrementally. The sprites are neither trimmed, rotated, nor nine-sliced.
+pub struct DynamicAtlas {
+    atlas: Handle<Atlas>,
+    settings: TextureAtlasSettings,
+    padding: u32,
+    pages: Vec<DynamicPage>,
+    sprites: HashMap<PathBuf, DynamicSprite>,
+}
+
+struct DynamicPage {
+    alloc: AtlasAllocator,
+    layout: AssetId<TextureAtlasLayout>,
+    image: AssetId<Image>,
+    /// Layout indices of removed sprites, to be reused.
+    free: Vec<usize>,
+    used: u64,
+}
+
+struct DynamicSprite {
+    page: usize,
+    id: AllocId,
+    index: usize,
+    area: u64,
+}
+
+impl DynamicAtlas {
+    /// Creates an empty [`DynamicAtlas`], adding its [`Atlas`] asset. Pages start at the initial
+    /// size of `settings` and grow up to its maximum size.
+    pub fn new(atlases: &mut Assets<Atlas>, settings: TextureAtlasSettings) -> Self {
+        Self {
+            atlas: atlases.add(Atlas {
+                pages: Vec::new(),
+                sprites: HashMap::with_hasher(FixedHasher),
+                efficiency: Vec::new(),
+                animations: HashMap::with_hasher(FixedHasher),
+                directories: Vec::new(),
+            }),
+            settings,
+            padding: AtlasFile::default_padding(),
+            pages: Vec::new(),
+            sprites: HashMap::with_hasher(FixedHasher),
+        }
+    }
+
+    /// Sets how far away the edges of one sprite to another and to the page boundaries, in pixels.
+    /// Only affects sprites inserted afterwards. See [`AtlasFile::padding`].
+    #[inline]
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// The [`Atlas`] handle, to be used for [`AtlasEntries`](crate::atlas::AtlasEntries).
+    #[inline]
+    pub fn atlas(&self) -> &Handle<Atlas> {
+        &self.atlas
+    }
+
+    /// Whether a sprite with the given lookup key is in the atlas.
+    #[inline]
+    pub fn contains(&self, path: &Path) -> bool {
+        self.sprites.contains_key(path)
+    }
+
+    /// Writes an image into the atlas under the given lookup key, replacing the sprite previously
+    /// inserted with the same key, if any. The previous sprite is kept if inserting fails.
+    pub fn insert(
+        &mut self,
+        path: impl Into<PathBuf>,
+        image: &Image,
+        atlases: &mut Assets<Atlas>,
+        layouts: &mut Assets<TextureAtlasLayout>,
+        images: &mut Assets<Image>,
+    ) -> Result<AtlasSprite, DynamicAtlasError> {
+        let path = path.into();
+        let Some(image) = image.convert(TextureFormat::Rgba8UnormSrgb) else {
+            return Err(DynamicAtlasError::UnsupportedFormat {
+                path,
+                format: image.texture_descriptor.format,
+            })
+        };
+
+        let size = image.size();
+        let alloc_size = (size + 2 * self.padding).max(UVec2::ONE);
+        let max_size = uvec2(self.settings.max_width, self.settings.max_height);
+        if alloc_size.x > max_size.x || alloc_size.y > max_size.y {
+            return Err(DynamicAtlasError::TooLarge {
+                path,
+                max_width: max_size.x,
+                max_height: max_size.y,
+                actual_width: alloc_size.x,
+                actual_height: alloc_size.y,
+            })
+        }
+
+        let request = size2(alloc_size.x as i32, alloc_size.y as i32);
+        let found = self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .find_map(|(i, page)| Some((i, page.alloc.allocate(request)?)));
+
+        let (page_index, allocation) = match found {
+            Some(found) => found,
+            None => 'grow: {
+                // Grow the last page before resorting to a new one, like the loader does.
+                if let Some(last) = self.pages.len().checked_sub(1) {
+                    let mut page_size = page_size(&self.pages[last].alloc);
+                    while page_size != max_size {
+                        page_size = (page_size * 2).min(max_size);
+                        self.grow_page(last, page_size, layouts, images)?;
+
+                        if let Some(allocation) = self.pages[last].alloc.allocate(request) {
+                            break 'grow (last, allocation)
+                        }
+                    }
+                }
+
+                let mut page_size = uvec2(self.settings.init_width, self.settings.init_height).min(max_size);
+                while page_size.x < alloc_size.x || page_size.y < alloc_size.y {
+                    page_size = (page_size * 2).min(max_size);
+                }
+
+                let Some(atlas) = atlases.get_mut(&self.atlas) else { return Err(DynamicAtlasError::MissingAsset) };
+
+                let extent = Extent3d {
+                    width: page_size.x,
+                    height: page_size.y,
+                    depth_or_array_layers: 1,
+                };
+
+                let image = images.add(Image::new_fill(
+                    extent,
+                    TextureDimension::D2,
+                    &[0; 4],
+                    TextureFormat::Rgba8UnormSrgb,
+                    RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+                ));
+                let layout = layouts.add(TextureAtlasLayout::new_empty(page_size));
+
+                let mut alloc = AtlasAllocator::new(size2(page_size.x as i32, page_size.y as i32));
+                let Some(allocation) = alloc.allocate(request) else {
+                    unreachable!("an empty page fits anything up to its size")
+                };
+
+                self.pages.push(DynamicPage {
+                    alloc,
+                    layout: layout.id(),
+                    image: image.id(),
+                    free: Vec::new(),
+                    used: 0,
+                });
+
+                atlas.pages.push((layout, image));
+                atlas.efficiency.push(0.);
+                (self.pages.len() - 1, allocation)
+            }
+        };
+
+        // Only free the replaced sprite once the new one has its space, so that it survives failures.
+        self.remove(&path, atlases, layouts);
+
+        let min = uvec2(allocation.rectangle.min.x as u32, allocation.rectangle.min.y as u32);
+        let rect = URect::from_corners(min + self.padding, min + self.padding + size);
+
+        let page = &mut self.pages[page_index];
+        let Some(page_image) = images.get_mut(page.image) else {
+            return Err(DynamicAtlasError::MissingAsset)
+        };
+        let page_width = page_image.width() as usize;
+        let (Some(dst), Some(src)) = (page_image.data.as_mut(), image.data.as_ref()) else {
+            return Err(DynamicAtlasError::MissingAsset)
+        };
+
+        // Clear the whole allocation first, since it may be reused from a removed sprite.
+        for y in min.y..min.y + alloc_size.y {
+            dst[(y as usize * page_width + min.x as usize) * 4..][..alloc_size.x as usize * 4].fill(0);
+        }
+
+        let row = size.x as usize * 4;
+        for y in 0..size.y as usize {
+            dst[((rect.min.y as usize + y) * page_width + rect.min.x as usize) * 4..][..row]
+                .copy_from_slice(&src[y * row..][..row]);
+        }
+
+        let Some(layout) = layouts.get_mut(page.layout) else {
+            return Err(DynamicAtlasError::MissingAsset)
+        };
+        let index = match page.free.pop() {
+            Some(index) => {
+                layout.textures[index] = rect;
+                index
+            }
+            None => layout.add_texture(rect),
+        };
+
+        let area = alloc_size.x as u64 * alloc_size.y as u64;
+        page.used += area;
+
+        self.sprites.insert(path.clone(), DynamicSprite {
+            page: page_index,
+            id: allocation.id,
+            index,
+            area,
+        });
+
+        let Some(atlas) = atlases.get_mut(&self.atlas) else { return Err(DynamicAtlasError::MissingAsset) };
+        let (layout, page_image) = &atlas.pages[page_index];
+        let sprite = AtlasSprite {
+            atlas: TextureAtlas {
+                layout: layout.clone_weak(),
+                index,
+            },
+            atlas_page: page_image.clone_weak(),
+            layer: 0,
+            original_size: size,
+            trim_offset: UVec2::ZERO,
+            rotated: false,
+            nine_slices: None,
+            meta: default(),
+        };
+
+        atlas.efficiency[page_index] = efficiency(page);
+        atlas.sprites.insert(path, sprite.clone());
+        Ok(sprite)
+    }
+
+    /// Removes a sprite from the atlas, freeing its space for subsequent insertions. Returns the
+    /// removed sprite, if any.
+    pub fn remove(
+        &mut self,
+        path: &Path,
+        atlases: &mut Assets<Atlas>,
+        layouts: &mut Assets<TextureAtlasLayout>,
+    ) -> Option<AtlasSprite> {
+        let DynamicSprite { page, id, index, area } = self.sprites.remove(path)?;
+        let dynamic_page = &mut self.pages[page];
+        dynamic_page.alloc.deallocate(id);
+        dynamic_page.free.push(index);
+        dynamic_page.used -= area;
+
+        if let Some(layout) = layouts.get_mut(dynamic_page.layout) {
+            layout.textures[index] = URect::default();
+        }
+
+        let atlas = atlases.get_mut(&self.atlas)?;
+        atlas.efficiency[page] = efficiency(dynamic_page);
+        atlas.sprites.remove(path)
+    }
+
+    /// Grows a page to a new size, keeping its contents.
+    fn grow_page(
+        &mut self,
+        index: usize,
+        size: UVec2,
+        layouts: &mut Assets<TextureAtlasLayout>,
+        images: &mut Assets<Image>,
+    ) -> Result<(), DynamicAtlasError> {
+        let page = &mut self.pages[index];
+        page.alloc.grow(size2(size.x as i32, size.y as i32));
+
+        let Some(image) = images.get_mut(page.image) else { return Err(DynamicAtlasError::MissingAsset) };
+        let old_size = image.size();
+        let Some(old) = image.data.as_ref() else { return Err(DynamicAtlasError::MissingAsset) };
+
+        let mut data = vec![0; size.x as usize * size.y as usize * 4];
+        let row = old_size.x as usize * 4;
+        for y in 0..old_size.y as usize {
+            data[y * size.x as usize * 4..][..row].copy_from_slice(&old[y * row..][..row]);
+        }
+
+        image.data = Some(data);
+        image.texture_descriptor.size = Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        };
+
+        let Some(layout) = layouts.get_mut(page.layout) else {
+            return Err(DynamicAtlasError::MissingAsset)
+        };
+        layout.size = size;
+        Ok(())
+    }
+}
+
+#[inline]
+fn page_size(alloc: &AtlasAllocator) -> UVec2 {
+    let size = alloc.size().cast::<u32>();
+    uvec2(size.width, size.height)
+}
+
+#[inline]
+fn efficiency(page: &DynamicPage) -> f32 {
+    let size = page_size(&page.alloc);
+    (page.used as f64 / (size.x as u64 * size.y as u64).max(1) as f64) as f32
+}
@@ -3,20 +3,48 @@
 #![doc = include_str!("../README.md")]
 #![cfg_attr(doc, deny(missing_docs))]
 
-use bevy::{app::PluginGroupBuilder, asset::AssetEvents, image::TextureAtlasPlugin, prelude::*};
+use bevy::{
+    app::PluginGroupBuilder, asset::AssetEvents, image::TextureAtlasPlugin, prelude::*, render::view::VisibilitySystems,
+};
 use hephae_utils::prelude::*;
 
+#[cfg(feature = "process")]
+use crate::process::{AtlasProcessor, AtlasSaver, ProcessedAtlasLoader};
 use crate::{
-    atlas::{Atlas, AtlasCaches, AtlasChanges, AtlasEntries, AtlasEntry, detect_changes, update_caches},
-    loader::AtlasLoader,
+    animation::{
+        AnimatedAtlasEntry, AnimationMode, AtlasAnimation, AtlasAnimationEvent, AtlasAnimationEventKind, AtlasFrame,
+        animate_atlas_entries,
+    },
+    atlas::{Atlas, AtlasCaches, AtlasChanges, AtlasEntries, AtlasEntry, SpriteMeta, detect_changes, update_caches},
+    loader::{AtlasLoader, SpriteMetaLoader, WatchedDirectories, set_directory_server, watch_atlas_directories},
+    sheet::{AsepriteLoader, TexturePackerLoader},
+    tilemap::{Tile, TileSprite, Tilemap, animate_tilemaps, invalidate_tilemaps, sync_tilemap_chunks},
+    trail::{Trail, TrailSprites, update_trails},
 };
 
+pub mod animation;
 pub mod atlas;
+pub mod dynamic;
 pub mod loader;
+pub mod packer;
+#[cfg(feature = "process")]
+pub mod process;
+pub mod sheet;
+pub mod tilemap;
+pub mod trail;
 
 /// Common imports for [`hephae_atlas`](crate).
 pub mod prelude {
-    pub use crate::atlas::{Atlas, AtlasCaches, AtlasEntries, AtlasEntry, AtlasInfo, AtlasSprite, NineSliceCuts};
+    pub use crate::{
+        animation::{AnimatedAtlasEntry, AnimationMode, AtlasAnimation, AtlasAnimationEvent, AtlasAnimationEventKind},
+        atlas::{
+            Atlas, AtlasCaches, AtlasEntries, AtlasEntry, AtlasInfo, AtlasSprite, ContentPadding, NineSliceCuts, SpriteMeta,
+        },
+        dynamic::DynamicAtlas,
+        packer::AtlasPacker,
+        tilemap::{Tile, TileSprite, Tilemap, TilemapChunk, TilemapDrawer},
+        trail::{Trail, TrailDrawer, TrailPoints, TrailSprites, TrailUvMode},
+    };
 }
 
 plugin_conf! {
@@ -47,10 +75,28 @@ plugin_def! {
                         app.add_plugins(TextureAtlasPlugin);
                     }
 
+                    set_directory_server(app.world().resource::<AssetServer>().clone());
                     app.init_resource::<AtlasChanges>()
                         .init_asset::<Atlas>()
                         .register_asset_reflect::<Atlas>()
+                        .init_asset::<AtlasAnimation>()
+                        .register_asset_reflect::<AtlasAnimation>()
+                        .init_asset::<SpriteMeta>()
+                        .register_asset_reflect::<SpriteMeta>()
+                        .add_event::<AtlasAnimationEvent>()
                         .register_asset_loader(AtlasLoader)
+                        .register_asset_loader(AsepriteLoader)
+                        .register_asset_loader(TexturePackerLoader)
+                        .register_asset_loader(SpriteMetaLoader)
+                        .register_type::<AtlasFrame>()
+                        .register_type::<AnimationMode>()
+                        .register_type::<AnimatedAtlasEntry>()
+                        .register_type::<AtlasAnimationEvent>()
+                        .register_type::<AtlasAnimationEventKind>()
+                        .register_type::<Trail>()
+                        .register_type::<TrailSprites>()
+                        .register_type::<Tile>()
+                        .register_type::<TileSprite>()
                         .configure_sets(
                             PostUpdate,
                             (
@@ -62,14 +108,53 @@ plugin_def! {
                         )
                         .add_systems(
                             PostUpdate,
-                            detect_changes.in_set(HephaeAtlasCacheSystem::AssetChanges),
+                            (
+                                detect_changes.in_set(HephaeAtlasCacheSystem::AssetChanges),
+                                animate_atlas_entries
+                                    .after(HephaeAtlasCacheSystem::AssetChanges)
+                                    .before(HephaeAtlasCacheSystem::UpdateCaches),
+                                update_trails
+                                    .in_set(VisibilitySystems::CalculateBounds)
+                                    .after(HephaeAtlasCacheSystem::UpdateCaches),
+                                (
+                                    animate_tilemaps,
+                                    invalidate_tilemaps.after(HephaeAtlasCacheSystem::UpdateCaches),
+                                    sync_tilemap_chunks.before(TransformSystem::TransformPropagate),
+                                ),
+                            ),
                         );
+
+                    #[cfg(feature = "process")]
+                    app.register_asset_loader(ProcessedAtlasLoader)
+                        .register_asset_processor::<AtlasProcessor>(AtlasSaver.into())
+                        .set_default_asset_processor::<AtlasProcessor>("atlas.ron");
                 })
-                .add(AtlasEntryPlugin::<AtlasEntry>::default()),
+                .add(AtlasEntryPlugin::<AtlasEntry>::default())
+                .add(AtlasEntryPlugin::<AnimatedAtlasEntry>::default())
+                .add(AtlasEntryPlugin::<TrailSprites>::default())
+                .add(AtlasEntryPlugin::<Tilemap>::default()),
         )
     }
 }
 
+plugin_def! {
+    /// Reloads atlases when files are added to or removed from the directories their
+    /// [globs](loader::TextureAtlasEntry::Glob) and [scans](loader::TextureAtlasEntry::Scan) search,
+    /// which Bevy doesn't do on its own. Only does anything while the [`AssetServer`] is watching for
+    /// changes, and isn't part of [`AtlasPlugin`], since it's meant for development.
+    ///
+    /// This works by loading each of those directories as a [`LoadedFolder`](bevy::asset::LoadedFolder),
+    /// which loads every asset inside them a second time as a standalone asset and keeps it loaded,
+    /// e.g. every sprite image is also uploaded to the GPU as its own texture.
+    pub struct AtlasWatchPlugin;
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WatchedDirectories>().add_systems(
+            PostUpdate,
+            watch_atlas_directories.in_set(HephaeAtlasCacheSystem::AssetChanges),
+        );
+    }
+}
+
 /// Labels assigned to [`update_atlas_cache`].
 #[derive(SystemSet, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum HephaeAtlasCacheSystem {
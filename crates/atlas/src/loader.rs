@@ -5,6 +5,12 @@
 //! (
 //!     padding: 4,
 //!     bleeding: 4,
+//!     packer: guillotine,
+//!     trim: false,
+//!     rotate: false,
+//!     mipmaps: 1,
+//!     format: rgba8_unorm_srgb,
+//!     array: false,
 //!     usages: (
 //!         main: false,
 //!         render: true,
@@ -14,38 +20,65 @@
 //!         ("some-dir-relative-to-atlas-file", [
 //!             "some-file-inside-the-dir.png",
 //!         ]),
+//!         (path: "some-file-with-overrides.png", trim: true),
+//!         (path: "some-panel.png", slice: (4, 4, 4, 4)),
+//!         (path: "some-effect.png", settings: (premultiply: true)),
+//!         (path: "some-character.png", meta: (pivot: (16, 32), tags: {"kind": "player"})),
+//!         ("some-dir-with-overrides", ["icon.png"], (padding: 0, bleeding: 0, group: "icons")),
+//!         "some-glob/**/*.png",
+//!         (glob: "another-glob/*.png", exclude: ["*_old.png"]),
+//!         (dir: "some-dir-to-include-entirely", exclude: ["unused/**"]),
 //!     ],
+//!     frame_duration: 0.1,
+//!     animations: {
+//!         "some-animation": (
+//!             frames: ["some-file-inside-the-dir", ("some-file-with-overrides", 0.5)],
+//!             mode: once,
+//!         ),
+//!     },
 //! )
 //! ```
 
 use std::{
-    io,
+    any::TypeId,
+    array,
+    borrow::Cow,
+    collections::BTreeMap,
+    io, mem,
     path::{Path, PathBuf},
+    sync::{Arc, LazyLock, PoisonError, RwLock},
 };
 
 use bevy::{
     asset::{
-        AssetLoader, AssetPath, LoadContext, LoadDirectError, ParseAssetPathError, RenderAssetUsages, io::Reader, ron,
-        ron::error::SpannedError,
+        AssetLoadError, AssetLoader, AssetPath, AssetServerMode, LoadContext, LoadDirectError, LoadedFolder,
+        ParseAssetPathError, RenderAssetUsages,
+        io::{AssetReaderError, ErasedAssetReader, MissingAssetSourceError, Reader},
+        ron,
+        ron::{error::SpannedError, extensions::Extensions},
     },
     image::TextureFormatPixelInfo,
-    platform::{collections::HashMap, hash::FixedHasher},
+    platform::{
+        collections::{HashMap, HashSet, hash_map::Entry},
+        hash::FixedHasher,
+    },
     prelude::*,
-    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension},
+    tasks::{ConditionalSendFuture, futures_lite::StreamExt},
 };
 use derive_more::{Display, Error, From};
-use guillotiere::{
-    AllocId, AtlasAllocator, Change, ChangeList,
-    euclid::{Box2D, Size2D},
-    size2,
-};
+use half::f16;
 use serde::{
     Deserialize, Deserializer, Serialize, Serializer,
     de::{Error as DeError, MapAccess, Visitor},
     ser::SerializeStruct,
 };
 
-use crate::atlas::{Atlas, AtlasSprite, NineSliceCuts};
+use crate::{
+    animation::{AnimationMode, AtlasAnimation, AtlasFrame},
+    atlas::{Atlas, AtlasSprite, ContentPadding, NineSliceCuts, SpriteMeta},
+    packer::{AtlasPacker, RectPacker},
+};
 
 /// Asset file representation of [`Atlas`].
 ///
@@ -65,6 +98,42 @@ pub struct AtlasFile {
     /// quite reach the edge of the vertices.
     #[serde(default = "AtlasFile::default_bleeding")]
     pub bleeding: u32,
+    /// The algorithm used to pack sprites into pages. Defaults to [`AtlasPacker::Guillotine`].
+    #[serde(default)]
+    pub packer: AtlasPacker,
+    /// Whether to crop sprites to the bounding box of their non-transparent pixels before packing.
+    /// The original size and the crop offset are kept in [`AtlasSprite`], so the sprites may still
+    /// be placed as if untrimmed. May be overridden per [entry](TextureAtlasEntry::Sprite).
+    /// Nine-slice sprites are never trimmed.
+    #[serde(default)]
+    pub trim: bool,
+    /// Whether the packer may rotate sprites 90° clockwise when that fits them tighter, e.g. for
+    /// tall and thin sprites. Rotated sprites are flagged with [`AtlasSprite::rotated`], which
+    /// renderers have to account for when mapping UVs.
+    #[serde(default)]
+    pub rotate: bool,
+    /// How many mip levels each page has, including the full-size one, to mitigate aliasing of
+    /// zoomed-out sprites. The levels are generated by repeatedly halving the page. Padding and
+    /// bleeding are scaled by `2^(mipmaps - 1)` and sprites are aligned to that, so that
+    /// neighboring sprites don't bleed into each other at lower levels. Defaults to `1`, i.e.
+    /// no mipmaps.
+    #[serde(default = "AtlasFile::default_mipmaps")]
+    pub mipmaps: u32,
+    /// The texture format of the pages. Defaults to [`AtlasFormat::Rgba8UnormSrgb`].
+    #[serde(default)]
+    pub format: AtlasFormat,
+    /// Whether to store all pages in a single texture array, one layer per page, instead of one
+    /// texture each. Every page is then made as large as the largest one, and sprites tell which
+    /// layer they're in with [`AtlasSprite::layer`]. This lets sprites across pages share a bind
+    /// group, at the cost of requiring shaders to sample a `texture_2d_array`.
+    #[serde(default)]
+    pub array: bool,
+    /// Whether to look for [sidecar metadata](SpriteMetaFile) files next to each sprite image.
+    /// Off by default, since that's an extra read for every sprite. Existing sidecar files are load
+    /// dependencies, so editing them reloads the atlas, but a sidecar file created later is only
+    /// picked up once the atlas reloads for another reason, e.g. one of its images changing.
+    #[serde(default)]
+    pub sidecars: bool,
     #[serde(
         default = "AtlasFile::default_usages",
         serialize_with = "AtlasFile::serialize_usages",
@@ -74,6 +143,17 @@ pub struct AtlasFile {
     pub usages: RenderAssetUsages,
     /// File entries relative to the atlas configuration file.
     pub entries: Vec<TextureAtlasEntry>,
+    /// How long each frame of an animation is shown, in seconds, unless specified otherwise.
+    #[serde(default = "AtlasFile::default_frame_duration")]
+    pub frame_duration: f32,
+    /// Explicitly defined animations, keyed by their lookup path. Frames are sprite paths, the
+    /// same ones used to look sprites up in [`Atlas::sprites`].
+    ///
+    /// Additionally, sprites whose names end with an underscore followed by a number (e.g.,
+    /// `walk_0` through `walk_7`) are gathered into a looping animation named after the part
+    /// before the underscore (e.g., `walk`), unless an explicit animation with that name exists.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub animations: BTreeMap<String, AtlasAnimationFile>,
 }
 
 impl AtlasFile {
@@ -89,6 +169,18 @@ impl AtlasFile {
         4
     }
 
+    /// Default frame duration of animations is 0.1 seconds.
+    #[inline]
+    pub const fn default_frame_duration() -> f32 {
+        0.1
+    }
+
+    /// Default mip level count of texture atlas pages is 1, i.e. no mipmaps.
+    #[inline]
+    pub const fn default_mipmaps() -> u32 {
+        1
+    }
+
     /// Default usage of texture atlas pages is [RenderAssetUsages::RENDER_WORLD].
     #[inline]
     pub const fn default_usages() -> RenderAssetUsages {
@@ -151,14 +243,346 @@ impl AtlasFile {
     }
 }
 
+/// The texture format of [`Atlas`] pages. Sprites are converted to it before packing.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AtlasFormat {
+    /// 8-bit sRGB color with alpha, [`TextureFormat::Rgba8UnormSrgb`]. Suits most sprites.
+    #[default]
+    Rgba8UnormSrgb,
+    /// 8-bit linear color with alpha, [`TextureFormat::Rgba8Unorm`]. Suits data textures, e.g.
+    /// normal maps.
+    Rgba8Unorm,
+    /// A single 8-bit linear channel, [`TextureFormat::R8Unorm`]. Suits masks and distance fields.
+    /// Sprites are stored as their luminance multiplied by their alpha, so both grayscale images
+    /// and white shapes on transparent backgrounds convert as expected.
+    R8Unorm,
+    /// 16-bit floating-point linear color with alpha, [`TextureFormat::Rgba16Float`]. Suits HDR
+    /// sprites.
+    Rgba16Float,
+}
+
+impl AtlasFormat {
+    /// The corresponding texture format.
+    #[inline]
+    pub const fn texture_format(self) -> TextureFormat {
+        match self {
+            Self::Rgba8UnormSrgb => TextureFormat::Rgba8UnormSrgb,
+            Self::Rgba8Unorm => TextureFormat::Rgba8Unorm,
+            Self::R8Unorm => TextureFormat::R8Unorm,
+            Self::Rgba16Float => TextureFormat::Rgba16Float,
+        }
+    }
+
+    /// The atlas format corresponding to a texture format, if any.
+    #[inline]
+    pub const fn from_texture_format(format: TextureFormat) -> Option<Self> {
+        match format {
+            TextureFormat::Rgba8UnormSrgb => Some(Self::Rgba8UnormSrgb),
+            TextureFormat::Rgba8Unorm => Some(Self::Rgba8Unorm),
+            TextureFormat::R8Unorm => Some(Self::R8Unorm),
+            TextureFormat::Rgba16Float => Some(Self::Rgba16Float),
+            _ => None,
+        }
+    }
+
+    /// Reads a pixel as linear RGBA. [`R8Unorm`](Self::R8Unorm) pixels are read as opaque gray.
+    fn read(self, pixel: &[u8]) -> [f32; 4] {
+        static SRGB_TO_LINEAR: LazyLock<[f32; 256]> =
+            LazyLock::new(|| array::from_fn(|value| Srgba::gamma_function(value as f32 / 255.)));
+
+        match self {
+            Self::Rgba8UnormSrgb => [
+                SRGB_TO_LINEAR[pixel[0] as usize],
+                SRGB_TO_LINEAR[pixel[1] as usize],
+                SRGB_TO_LINEAR[pixel[2] as usize],
+                pixel[3] as f32 / 255.,
+            ],
+            Self::Rgba8Unorm => array::from_fn(|channel| pixel[channel] as f32 / 255.),
+            Self::R8Unorm => {
+                let value = pixel[0] as f32 / 255.;
+                [value, value, value, 1.]
+            }
+            Self::Rgba16Float => {
+                array::from_fn(|channel| f16::from_le_bytes([pixel[channel * 2], pixel[channel * 2 + 1]]).to_f32())
+            }
+        }
+    }
+
+    /// Writes a pixel from linear RGBA. [`R8Unorm`](Self::R8Unorm) pixels only take the red
+    /// channel.
+    fn write(self, [red, green, blue, alpha]: [f32; 4], pixel: &mut [u8]) {
+        let unorm = |value: f32| (value.clamp(0., 1.) * 255.).round() as u8;
+        match self {
+            Self::Rgba8UnormSrgb => pixel.copy_from_slice(&[
+                unorm(Srgba::gamma_function_inverse(red)),
+                unorm(Srgba::gamma_function_inverse(green)),
+                unorm(Srgba::gamma_function_inverse(blue)),
+                unorm(alpha),
+            ]),
+            Self::Rgba8Unorm => pixel.copy_from_slice(&[unorm(red), unorm(green), unorm(blue), unorm(alpha)]),
+            Self::R8Unorm => pixel[0] = unorm(red),
+            Self::Rgba16Float => {
+                for (channel, value) in [red, green, blue, alpha].into_iter().enumerate() {
+                    pixel[channel * 2..][..2].copy_from_slice(&f16::from_f32(value).to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
 /// A [Atlas] file entry. May either be a file or a directory containing files.
+///
+/// Glob patterns support `*` to match any part of a file or directory name, `?` to match any
+/// single character, and `**` as a whole path component to match any number of nested
+/// directories. Exclude patterns without a `/` are matched against file names alone, and otherwise
+/// against the path relative to where the entry starts searching.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum TextureAtlasEntry {
-    /// Defines a relative path to an [Image] file.
+    /// Defines a relative path to an [Image] file. If the path contains `*` or `?`, it's treated
+    /// as a [glob](Self::Glob) pattern instead.
     File(String),
-    /// Defines a directory relative to the current one, filled with more entries.
-    Directory(String, Vec<TextureAtlasEntry>),
+    /// Defines a directory relative to the current one, filled with more entries. The optional
+    /// settings apply to every entry inside, unless overridden.
+    Directory(String, Vec<TextureAtlasEntry>, #[serde(default)] EntrySettings),
+    /// Defines a relative path to an [Image] file, overriding some of the atlas-wide settings.
+    Sprite {
+        /// The relative path to the [Image] file.
+        path: String,
+        /// Overrides [`AtlasFile::trim`].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trim: Option<bool>,
+        /// Declares [nine-slice cuts](crate::atlas::NineSliceCuts) by how thick the left, right,
+        /// top, and bottom edges are, in pixels, e.g. `slice: (4, 4, 4, 4)`. Overrides the cuts
+        /// read off 9-patch markers, and prevents the sprite from being trimmed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        slice: Option<(u32, u32, u32, u32)>,
+        /// Overrides some of the packing settings.
+        #[serde(default, skip_serializing_if = "EntrySettings::is_empty")]
+        settings: EntrySettings,
+        /// Declares [metadata](SpriteMeta) of the sprite, overriding that of its sidecar
+        /// `.meta.ron` file field by field.
+        #[serde(default, skip_serializing_if = "SpriteMetaFile::is_empty")]
+        meta: SpriteMetaFile,
+    },
+    /// Includes every [Image] file whose path relative to the current directory matches a glob
+    /// pattern, except for those matching any of the exclude patterns.
+    Glob {
+        /// The glob pattern, relative to the current directory.
+        glob: String,
+        /// Glob patterns of files to leave out.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        exclude: Vec<String>,
+        /// Overrides some of the packing settings of every included file.
+        #[serde(default, skip_serializing_if = "EntrySettings::is_empty")]
+        settings: EntrySettings,
+    },
+    /// Includes every [Image] file in a directory relative to the current one, including nested
+    /// directories, except for those matching any of the exclude patterns.
+    Scan {
+        /// The directory to include.
+        dir: String,
+        /// Glob patterns of files to leave out, relative to [`dir`](Self::Scan::dir).
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        exclude: Vec<String>,
+        /// Overrides some of the packing settings of every included file.
+        #[serde(default, skip_serializing_if = "EntrySettings::is_empty")]
+        settings: EntrySettings,
+    },
+}
+
+/// Per-entry overrides of the packing settings, e.g. `(padding: 0, group: "hud")`. Settings of
+/// directory, glob, and scan entries apply to every sprite they include, and are overridden by
+/// those of nested entries.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntrySettings {
+    /// Overrides [`AtlasFile::padding`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<u32>,
+    /// Overrides [`AtlasFile::bleeding`], e.g. to bleed tiling textures further.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bleeding: Option<u32>,
+    /// Whether to multiply the color of the sprites by their alpha before packing, e.g. for
+    /// additive effects. Defaults to `false`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub premultiply: Option<bool>,
+    /// Pins the sprites to the same page as every other sprite in the group of the same name, so
+    /// that they may be batched together. A group has to fit in a single page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl EntrySettings {
+    /// Whether no settings are overridden.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Layers `overrides` on top of these settings.
+    pub fn merge(&self, overrides: &Self) -> Self {
+        Self {
+            padding: overrides.padding.or(self.padding),
+            bleeding: overrides.bleeding.or(self.bleeding),
+            premultiply: overrides.premultiply.or(self.premultiply),
+            group: overrides.group.clone().or_else(|| self.group.clone()),
+        }
+    }
+}
+
+/// Asset file representation of [`SpriteMeta`], e.g.
+/// `(pivot: (8, 16), hitbox: [(0, 0), (16, 0), (8, 16)], tags: {"material": "wood"})`.
+///
+/// With [`AtlasFile::sidecars`] enabled, sprites read theirs off a `.meta.ron` file next to their
+/// image, named after their lookup key, e.g. `ui/button.meta.ron` for `ui/button.9.png`.
+/// [Sprite entries](TextureAtlasEntry::Sprite) may also declare it inline.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SpriteMetaFile {
+    /// See [`SpriteMeta::pivot`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pivot: Option<(f32, f32)>,
+    /// See [`SpriteMeta::hitbox`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hitbox: Vec<(f32, f32)>,
+    /// See [`SpriteMeta::tags`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl SpriteMetaFile {
+    /// Parses a `.meta.ron` sidecar file, which may leave out the `Some(..)` around the pivot.
+    #[inline]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SpannedError> {
+        ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_bytes(bytes)
+    }
+
+    /// Whether no metadata is declared.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Layers these declarations on top of `meta`, e.g. inline metadata on top of that of the
+    /// sidecar file. Tags are merged, with these taking precedence.
+    pub fn apply(self, meta: &mut SpriteMeta) {
+        let Self { pivot, hitbox, tags } = self;
+        if let Some(pivot) = pivot {
+            meta.pivot = Some(pivot.into());
+        }
+
+        if !hitbox.is_empty() {
+            meta.hitbox = hitbox.into_iter().map(Vec2::from).collect();
+        }
+
+        meta.tags.extend(tags);
+    }
+}
+
+impl From<SpriteMetaFile> for SpriteMeta {
+    #[inline]
+    fn from(SpriteMetaFile { pivot, hitbox, tags }: SpriteMetaFile) -> Self {
+        Self {
+            pivot: pivot.map(Vec2::from),
+            hitbox: hitbox.into_iter().map(Vec2::from).collect(),
+            tags,
+        }
+    }
+}
+
+impl From<&SpriteMeta> for SpriteMetaFile {
+    #[inline]
+    fn from(SpriteMeta { pivot, hitbox, tags }: &SpriteMeta) -> Self {
+        Self {
+            pivot: pivot.map(Vec2::into),
+            hitbox: hitbox.iter().copied().map(Vec2::into).collect(),
+            tags: tags.clone(),
+        }
+    }
+}
+
+/// Dedicated [`AssetLoader`] to load [`SpriteMeta`] sidecar files, with `.meta.ron` extension. See
+/// [`SpriteMetaFile`] for the format.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SpriteMetaLoader;
+impl AssetLoader for SpriteMetaLoader {
+    type Asset = SpriteMeta;
+    type Settings = ();
+    type Error = TextureAtlasError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _: &Self::Settings,
+        _: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        Ok(SpriteMetaFile::from_bytes(&bytes)?.into())
+    }
+
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["meta.ron"]
+    }
+}
+
+/// Asset file representation of [`AtlasAnimation`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AtlasAnimationFile {
+    /// The frames, in order.
+    pub frames: Vec<AtlasFrameFile>,
+    /// What happens when the last frame ends. Defaults to [`AnimationMode::Loop`].
+    #[serde(default)]
+    pub mode: AnimationMode,
+}
+
+/// Asset file representation of [`AtlasFrame`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum AtlasFrameFile {
+    /// A sprite path, shown for [`AtlasFile::frame_duration`].
+    Sprite(String),
+    /// A sprite path and how long it's shown, in seconds.
+    Timed(String, f32),
+}
+
+impl TextureAtlasEntry {
+    /// Splits a [glob](Self::Glob) pattern into the deepest directory that doesn't contain any
+    /// wildcards, and the rest of the pattern relative to that directory. Only the former has to be
+    /// searched through.
+    pub fn split_glob(glob: &str) -> (&str, &str) {
+        let mut dir = 0;
+        for (i, ..) in glob.match_indices('/') {
+            if glob[dir..i].contains(['*', '?']) {
+                break
+            }
+
+            dir = i + 1;
+        }
+
+        (glob[..dir].trim_end_matches('/'), &glob[dir..])
+    }
+
+    /// Whether a path relative to where a [glob](Self::Glob) or [scan](Self::Scan) entry starts
+    /// searching is included by its pattern and not by any of its exclude patterns. Scans use the
+    /// `**/*` pattern.
+    pub fn matches(pattern: &str, exclude: &[String], relative: &Path) -> bool {
+        let excluded = exclude.iter().any(|exclude| {
+            if exclude.contains('/') {
+                glob_match(&exclude.split('/').collect::<Vec<_>>(), relative)
+            } else {
+                relative
+                    .file_name()
+                    .is_some_and(|name| wildcard(exclude, &name.to_string_lossy()))
+            }
+        });
+
+        !excluded && glob_match(&pattern.split('/').collect::<Vec<_>>(), relative)
+    }
 }
 
 impl<T: ToString> From<T> for TextureAtlasEntry {
@@ -211,8 +635,8 @@ pub enum TextureAtlasError {
         /// The height of the erroneous texture.
         actual_height: u32,
     },
-    /// Error that arises when the texture couldn't be converted into
-    /// [`TextureFormat::Rgba8UnormSrgb`].
+    /// Error that arises when the texture couldn't be converted into the atlas' page
+    /// [format](AtlasFile::format).
     #[display("Texture '{}' has an unsupported format: {format:?}", path.display())]
     UnsupportedFormat {
         /// The sprite lookup key.
@@ -226,7 +650,48 @@ pub enum TextureAtlasError {
         /// The sprite lookup key.
         path: PathBuf,
         /// The error that arises when trying to load the texture.
-        error: LoadDirectError,
+        error: Box<LoadDirectError>,
+    },
+    /// Error that arises when the sidecar [metadata](SpriteMetaFile) of a texture couldn't be
+    /// loaded, other than because there is none.
+    #[display("Metadata of texture '{}' failed to load: {error}", path.display())]
+    #[from(skip)]
+    InvalidMeta {
+        /// The sprite lookup key.
+        path: PathBuf,
+        /// The error that arises when trying to load the metadata.
+        error: Box<LoadDirectError>,
+    },
+    /// Error that arises when a directory couldn't be read while resolving
+    /// [globs](TextureAtlasEntry::Glob) or [scans](TextureAtlasEntry::Scan).
+    #[display("Directory '{}' couldn't be read: {error}", path.display())]
+    InvalidDirectory {
+        /// The directory path.
+        path: PathBuf,
+        /// The error that arises when trying to read the directory.
+        error: AssetReaderError,
+    },
+    /// Error that arises when the asset source of a directory doesn't exist.
+    #[display("{_0}")]
+    MissingSource(#[from] MissingAssetSourceError),
+    /// Error that arises when a [group](EntrySettings::group) of sprites doesn't fit in a single
+    /// page.
+    #[display("Sprite group '{group}' doesn't fit in a single page of at most [{max_width}, {max_height}]")]
+    GroupTooLarge {
+        /// The group name.
+        group: String,
+        /// The maximum width of a page.
+        max_width: u32,
+        /// The maximum height of a page.
+        max_height: u32,
+    },
+    /// Error that arises when an animation refers to a sprite that isn't in the atlas.
+    #[display("Animation '{}' refers to a missing sprite '{}'", animation.display(), sprite.display())]
+    MissingFrame {
+        /// The animation lookup key.
+        animation: PathBuf,
+        /// The sprite lookup key that wasn't found.
+        sprite: PathBuf,
     },
     /// Error that arises when a texture has an invalid path string.
     #[display("{_0}")]
@@ -246,15 +711,32 @@ pub enum TextureAtlasError {
 /// throw [`TextureAtlasError`] for erroneous assets.
 ///
 /// This asset loader adds each texture atlas entry as a "load dependency." As much, coupled with a
-/// file system watcher, mutating these input image files will cause reprocessing of the atlas.
+/// file system watcher, mutating these input image files will cause reprocessing of the atlas. This
+/// includes files found through [globs](TextureAtlasEntry::Glob) and
+/// [scans](TextureAtlasEntry::Scan), which are searched for through the asset source's directory
+/// reader, as well as the sidecar [metadata](SpriteMetaFile) files of the sprites that have one if
+/// [enabled](AtlasFile::sidecars).
 ///
 /// This asset loader also adds layouts and images as labelled assets with label `"page-{i}"` and
 /// `"layout-{i}"` (without the brackets). Therefore, doing (for example)
 /// `server.load::<Image>("sprites.atlas.ron#page-0")` is possible and will return the 0th page
 /// image of the atlas, provided the atlas actually has a 0th page (which it won't only if there are
-/// no sprites at all!).
+/// no sprites at all!). [Array](AtlasFile::array) atlases have their single page image labeled
+/// `"pages"` instead. Animations are similarly added with label `"animation-{path}"`.
+///
+/// Bevy doesn't reload an asset when a file is added to or removed from a directory it depends on,
+/// so the directories searched by globs and scans are recorded in [`Atlas::directories`] for
+/// [`AtlasWatchPlugin`](crate::AtlasWatchPlugin) to watch.
+///
+/// With the `process` feature, this asset loader also packs atlases inside Bevy's asset processor;
+/// see [`process`](crate::process).
+///
+/// [`LoadContext`] doesn't expose the reader it loads through, so globs and scans list directories
+/// through the [`AssetServer`] of the app [`AtlasPlugin`](crate::AtlasPlugin) was added to, and
+/// fail to load without it.
 #[derive(Debug, Copy, Clone, Default)]
 pub struct AtlasLoader;
+
 impl AssetLoader for AtlasLoader {
     type Asset = Atlas;
     type Settings = TextureAtlasSettings;
@@ -266,331 +748,1509 @@ impl AssetLoader for AtlasLoader {
         settings: &Self::Settings,
         load_context: &mut LoadContext<'_>,
     ) -> Result<Self::Asset, Self::Error> {
-        let &Self::Settings {
-            init_width,
-            init_height,
-            max_width,
-            max_height,
-        } = settings;
-
         let mut bytes = Vec::new();
         reader.read_to_end(&mut bytes).await?;
 
-        let AtlasFile {
-            padding,
-            bleeding,
-            usages,
-            entries: file_entries,
-        } = ron::de::from_bytes(&bytes)?;
-
+        let file = ron::de::from_bytes::<AtlasFile>(&bytes)?;
         drop(bytes);
-        let pad = padding as usize;
-        let bleed = (bleeding as usize).min(pad);
-
-        async fn collect(
-            prefix: &Path,
-            entry: TextureAtlasEntry,
-            base: &AssetPath<'_>,
-            load_context: &mut LoadContext<'_>,
-            accum: &mut Vec<(PathBuf, Image, bool)>,
-        ) -> Result<(), TextureAtlasError> {
-            match entry {
-                TextureAtlasEntry::File(path) => {
-                    let path = base.resolve(&path)?;
-                    let Some(name) = path.path().file_stem() else { return Ok(()) };
-
-                    let mut name = name.to_string_lossy().into_owned();
-                    let has_nine_slice = if let Some((split, "9")) = name.rsplit_once('.') {
-                        name = String::from(split);
-                        true
-                    } else {
-                        false
-                    };
-
-                    let path_buf = path.resolve_embed(&name)?.path().strip_prefix(prefix).unwrap().to_owned();
-                    let src = match load_context.loader().immediate().load::<Image>(&path).await {
-                        Err(error) => return Err(TextureAtlasError::InvalidImage { path: path_buf, error }),
-                        Ok(src) => src,
-                    }
-                    .take();
 
-                    accum.push((path_buf, src, has_nine_slice));
+        let base = load_context.asset_path().parent().unwrap_or_else(|| AssetPath::from(""));
+        let mut sources = LoaderSources {
+            load_context,
+            directories: Vec::new(),
+        };
+
+        let entries = collect_sprites(&mut sources, &file, &base).await?;
+        let directories = sources.directories;
+
+        let mut output_atlas = Atlas {
+            pages: Vec::new(),
+            sprites: HashMap::with_hasher(FixedHasher),
+            efficiency: Vec::new(),
+            animations: HashMap::with_hasher(FixedHasher),
+            directories,
+        };
+
+        let mut pages = pack_sprites(&file, settings, entries)?;
+
+        // Array atlases have all of their pages share one texture.
+        let array = file.array.then(|| {
+            let array = stack_pages(pages.iter_mut().map(|page| mem::take(&mut page.image)));
+            load_context.add_labeled_asset("pages".into(), array)
+        });
+
+        for PackedPage {
+            image,
+            layout,
+            sprites,
+            efficiency,
+        } in pages
+        {
+            let page_num = output_atlas.pages.len();
+            let page = match &array {
+                Some(array) => array.clone(),
+                None => load_context.add_labeled_asset(format!("page-{page_num}"), image),
+            };
+            let layout = load_context.add_labeled_asset(format!("layout-{page_num}"), layout);
+
+            for (name, mut sprite) in sprites {
+                sprite.atlas.layout = layout.clone_weak();
+                sprite.atlas_page = page.clone_weak();
+                output_atlas.sprites.insert(name, sprite);
+            }
+
+            output_atlas.pages.push((layout, page));
+            output_atlas.efficiency.push(efficiency);
+        }
+
+        for (path, animation) in resolve_animations(&file, output_atlas.sprites.keys())? {
+            let handle = load_context.add_labeled_asset(format!("animation-{}", path.display()), animation);
+            output_atlas.animations.insert(path, handle);
+        }
+
+        Ok(output_atlas)
+    }
+
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["atlas.ron"]
+    }
+}
+
+/// Access to the files [`collect_sprites`] resolves [`TextureAtlasEntry`]s against. [`AtlasLoader`]
+/// reads them as load dependencies of the atlas, while the `hephae-atlas` CLI reads them straight
+/// off the file system.
+pub trait AtlasSources {
+    /// The error type, which [`TextureAtlasError`]s of the entries themselves are converted into.
+    type Error: From<TextureAtlasError>;
+
+    /// Recursively lists every file under `dir`, in any order. Called for each directory searched
+    /// by [globs](TextureAtlasEntry::Glob) and [scans](TextureAtlasEntry::Scan).
+    fn list_directory(
+        &mut self,
+        dir: &AssetPath<'static>,
+    ) -> impl ConditionalSendFuture<Output = Result<Vec<AssetPath<'static>>, Self::Error>>;
+
+    /// Whether `path` is an image [`load_image`](Self::load_image) can read. Globs and scans only
+    /// include such files.
+    fn is_image(&mut self, path: &AssetPath<'static>) -> impl ConditionalSendFuture<Output = bool>;
+
+    /// Loads the image at `path` of the sprite with lookup key `name`.
+    fn load_image(
+        &mut self,
+        name: &Path,
+        path: &AssetPath<'static>,
+    ) -> impl ConditionalSendFuture<Output = Result<Image, Self::Error>>;
+
+    /// Loads the sidecar [metadata](SpriteMetaFile) at `path` of the sprite with lookup key `name`,
+    /// returning `None` if there is no such file.
+    fn load_meta(
+        &mut self,
+        name: &Path,
+        path: &AssetPath<'static>,
+    ) -> impl ConditionalSendFuture<Output = Result<Option<SpriteMeta>, Self::Error>>;
+}
+
+/// Resolves the [entries](AtlasFile::entries) of an atlas file in the directory `base` into the
+/// sprites to [pack](pack_sprites), reading them through `sources`. Sprite lookup keys are relative
+/// to `base`. Sprites found by globs and scans are sorted by path, so they get packed in a stable
+/// order.
+pub async fn collect_sprites<S: AtlasSources>(
+    sources: &mut S,
+    file: &AtlasFile,
+    base: &AssetPath<'static>,
+) -> Result<Vec<SpriteSource>, S::Error> {
+    async fn collect<S: AtlasSources>(
+        sources: &mut S,
+        file: &AtlasFile,
+        prefix: &Path,
+        entry: &TextureAtlasEntry,
+        base: &AssetPath<'_>,
+        settings: &EntrySettings,
+        accum: &mut Vec<SpriteSource>,
+    ) -> Result<(), S::Error> {
+        let (path, trim, slice, settings, meta) = match entry {
+            TextureAtlasEntry::File(path) if path.contains(['*', '?']) => {
+                let entry = TextureAtlasEntry::Glob {
+                    glob: path.clone(),
+                    exclude: Vec::new(),
+                    settings: EntrySettings::default(),
+                };
+
+                return Box::pin(collect(sources, file, prefix, &entry, base, settings, accum)).await
+            }
+            TextureAtlasEntry::File(path) => (path, file.trim, None, settings.clone(), SpriteMetaFile::default()),
+            TextureAtlasEntry::Sprite {
+                path,
+                trim,
+                slice,
+                settings: overrides,
+                meta,
+            } => (
+                path,
+                trim.unwrap_or(file.trim),
+                *slice,
+                settings.merge(overrides),
+                meta.clone(),
+            ),
+            TextureAtlasEntry::Directory(dir, entries, overrides) => {
+                let base = base.resolve(dir).map_err(TextureAtlasError::from)?;
+                let settings = settings.merge(overrides);
+                for entry in entries {
+                    Box::pin(collect(sources, file, prefix, entry, &base, &settings, accum)).await?
                 }
-                TextureAtlasEntry::Directory(dir, paths) => {
-                    let base = base.resolve(&dir)?;
-                    for path in paths {
-                        Box::pin(collect(prefix, path, &base, load_context, accum)).await?
-                    }
+
+                return Ok(())
+            }
+            TextureAtlasEntry::Glob {
+                glob,
+                exclude,
+                settings: overrides,
+            } => {
+                let (dir, pattern) = TextureAtlasEntry::split_glob(glob);
+                let root = base.resolve(dir).map_err(TextureAtlasError::from)?;
+                let settings = settings.merge(overrides);
+                for path in search(sources, &root, pattern, exclude).await? {
+                    collect_file(
+                        sources,
+                        file,
+                        prefix,
+                        path,
+                        file.trim,
+                        None,
+                        &settings,
+                        SpriteMetaFile::default(),
+                        accum,
+                    )
+                    .await?
                 }
+
+                return Ok(())
             }
+            TextureAtlasEntry::Scan {
+                dir,
+                exclude,
+                settings: overrides,
+            } => {
+                let root = base.resolve(dir).map_err(TextureAtlasError::from)?;
+                let settings = settings.merge(overrides);
+                for path in search(sources, &root, "**/*", exclude).await? {
+                    collect_file(
+                        sources,
+                        file,
+                        prefix,
+                        path,
+                        file.trim,
+                        None,
+                        &settings,
+                        SpriteMetaFile::default(),
+                        accum,
+                    )
+                    .await?
+                }
 
-            Ok(())
+                return Ok(())
+            }
+        };
+
+        let path = base.resolve(path).map_err(TextureAtlasError::from)?;
+        collect_file(sources, file, prefix, path, trim, slice, &settings, meta, accum).await
+    }
+
+    async fn collect_file<S: AtlasSources>(
+        sources: &mut S,
+        file: &AtlasFile,
+        prefix: &Path,
+        path: AssetPath<'static>,
+        trim: bool,
+        slice: Option<(u32, u32, u32, u32)>,
+        settings: &EntrySettings,
+        inline_meta: SpriteMetaFile,
+        accum: &mut Vec<SpriteSource>,
+    ) -> Result<(), S::Error> {
+        let Some((name, nine_slice)) = SpriteSource::key(path.path()) else { return Ok(()) };
+
+        let name = name.strip_prefix(prefix).map(Path::to_owned).unwrap_or(name);
+        let image = sources.load_image(&name, &path).await?;
+
+        let mut meta = SpriteMeta::default();
+        if file.sidecars {
+            let file_name = name.file_name().unwrap_or_default().to_string_lossy();
+            let meta_path = path
+                .resolve_embed(&format!("{file_name}.meta.ron"))
+                .map_err(TextureAtlasError::from)?;
+
+            if let Some(loaded) = sources.load_meta(&name, &meta_path).await? {
+                meta = loaded
+            }
         }
 
-        let prefix = load_context.asset_path().parent().unwrap_or_else(|| AssetPath::from(""));
+        inline_meta.apply(&mut meta);
+
+        let sprite = SpriteSource::new(name, image, file.format, nine_slice, trim && slice.is_none())?
+            .with_settings(settings.clone())
+            .with_meta(meta);
+        accum.push(match slice {
+            Some(slice) => sprite.with_slice(slice),
+            None => sprite,
+        });
 
-        let mut entries = Vec::new();
-        for file_entry in file_entries {
-            collect(prefix.path(), file_entry, &prefix, load_context, &mut entries).await?;
+        Ok(())
+    }
+
+    /// Lists the files under `root` whose relative paths are [matched](TextureAtlasEntry::matches)
+    /// by `pattern` and `exclude`, and which are images.
+    async fn search<S: AtlasSources>(
+        sources: &mut S,
+        root: &AssetPath<'static>,
+        pattern: &str,
+        exclude: &[String],
+    ) -> Result<Vec<AssetPath<'static>>, S::Error> {
+        let mut files = sources.list_directory(root).await?;
+        files.sort_by(|a, b| a.path().cmp(b.path()));
+
+        let mut paths = Vec::with_capacity(files.len());
+        for file in files {
+            let Ok(relative) = file.path().strip_prefix(root.path()) else { continue };
+            if TextureAtlasEntry::matches(pattern, exclude, relative) && sources.is_image(&file).await {
+                paths.push(file)
+            }
         }
 
-        entries.sort_by_key(|&(.., ref texture, has_nine_slice)| {
-            let UVec2 { mut x, mut y } = texture.size();
-            if has_nine_slice {
-                x = x.saturating_sub(2);
-                y = y.saturating_sub(2);
+        Ok(paths)
+    }
+
+    let mut sprites = Vec::new();
+    for entry in &file.entries {
+        collect(
+            sources,
+            file,
+            base.path(),
+            entry,
+            base,
+            &EntrySettings::default(),
+            &mut sprites,
+        )
+        .await?
+    }
+
+    Ok(sprites)
+}
+
+/// An atlas page packed by [`pack_sprites`].
+#[derive(Debug, Clone)]
+pub struct PackedPage {
+    /// The page image, in the [format](AtlasFile::format) of the atlas.
+    pub image: Image,
+    /// Where each sprite resides within the page.
+    pub layout: TextureAtlasLayout,
+    /// The sprites in this page, ordered the same as [`layout`](Self::layout)'s textures. Their
+    /// [`atlas`](AtlasSprite::atlas) layout and [`atlas_page`](AtlasSprite::atlas_page) handles
+    /// are left invalid for the caller to assign.
+    pub sprites: Vec<(PathBuf, AtlasSprite)>,
+    /// The ratio of area taken up by sprites (including their padding) to the page area.
+    pub efficiency: f32,
+}
+
+/// Packs sprites into atlas pages according to the packing-related fields of an [`AtlasFile`],
+/// i.e. everything but [`entries`](AtlasFile::entries) and the animations. This is what
+/// [`AtlasLoader`] does after collecting the sprites, exposed to pack atlases outside of an app.
+pub fn pack_sprites(
+    file: &AtlasFile,
+    settings: &TextureAtlasSettings,
+    entries: Vec<SpriteSource>,
+) -> Result<Vec<PackedPage>, TextureAtlasError> {
+    let &AtlasFile {
+        padding,
+        bleeding,
+        packer: packer_kind,
+        rotate,
+        mipmaps,
+        format,
+        array,
+        usages,
+        ..
+    } = file;
+
+    let &TextureAtlasSettings {
+        init_width,
+        init_height,
+        max_width,
+        max_height,
+    } = settings;
+
+    // Sprites are aligned to the texels of the smallest mip level, with their padding and bleeding
+    // scaled to match.
+    let mipmaps = mipmaps.clamp(1, 16);
+    let align = 1 << (mipmaps - 1);
+    let padding_of = |source: &SpriteSource| source.settings.padding.unwrap_or(padding) * align;
+
+    // The size each sprite takes up in a page, including the padding.
+    let alloc_size = |source: &SpriteSource| {
+        let size = source.rect.size() + 2 * padding_of(source);
+        uvec2(size.x.next_multiple_of(align), size.y.next_multiple_of(align))
+    };
+
+    // Sprites in the same group are packed as one unit, so that they end up in the same page.
+    let mut units = Vec::<Vec<SpriteSource>>::with_capacity(entries.len());
+    let mut groups = HashMap::<String, usize>::default();
+    for entry in entries {
+        let Some(group) = entry.settings.group.clone() else {
+            units.push(vec![entry]);
+            continue
+        };
+
+        match groups.entry(group) {
+            Entry::Occupied(unit) => units[*unit.get()].push(entry),
+            Entry::Vacant(unit) => {
+                unit.insert(units.len());
+                units.push(vec![entry]);
             }
+        }
+    }
 
-            2 * (x + y)
-        });
+    // Sorted such that the sprite to be packed first is at the back, with units ordered by their
+    // first sprite.
+    for unit in &mut units {
+        unit.sort_by(|a, b| packer_kind.order(alloc_size(a), alloc_size(b)));
+    }
 
-        let mut output_atlas = Atlas {
-            pages: Vec::new(),
-            sprites: HashMap::with_hasher(FixedHasher),
+    let unit_size = |unit: &Vec<SpriteSource>| unit.last().map_or(UVec2::ZERO, alloc_size);
+    units.sort_by(|a, b| packer_kind.order(unit_size(a), unit_size(b)));
+
+    let mut pages = Vec::new();
+    let mut push_page = |placed: Vec<(URect, bool, SpriteSource)>, packer: RectPacker| {
+        let UVec2 {
+            x: page_width,
+            y: page_height,
+        } = packer.size();
+
+        let pixel_size = format.texture_format().pixel_size();
+        let mut sprites = Vec::with_capacity(placed.len());
+        let mut layout = TextureAtlasLayout {
+            size: uvec2(page_width, page_height),
+            textures: Vec::with_capacity(placed.len()),
         };
 
-        let mut push_page = |ids: HashMap<AllocId, (PathBuf, Image, bool)>, packer: AtlasAllocator| {
-            let Size2D {
-                width: page_width,
-                height: page_height,
-                ..
-            } = packer.size().to_u32();
+        let mut data = vec![0; page_width as usize * page_height as usize * pixel_size];
+        for (
+            rect,
+            rotated,
+            SpriteSource {
+                name,
+                mut image,
+                rect: src_rect,
+                original_size,
+                trim_offset,
+                nine_slices,
+                settings,
+                meta,
+            },
+        ) in placed
+        {
+            let padding = settings.padding.unwrap_or(padding) * align;
+            let pad = padding as usize;
+            let bleed = ((settings.bleeding.unwrap_or(bleeding) * align) as usize).min(pad);
+
+            // Leave out the slack from aligning the sprite.
+            let size = src_rect.size() + 2 * padding;
+            let rect = URect::from_corners(rect.min, rect.min + if rotated { size.yx() } else { size });
+
+            if settings.premultiply == Some(true) {
+                premultiply(&mut image, format);
+            }
 
-            let pixel_size = TextureFormat::Rgba8UnormSrgb.pixel_size();
-            let mut sprites = Vec::with_capacity(ids.len());
-            let mut data = vec![0; page_width as usize * page_height as usize * pixel_size];
+            let (min_x, min_y) = (rect.min.x as usize, rect.min.y as usize);
+            let raw = image.data.as_ref().unwrap();
+
+            // Rotated sprites are copied from a clockwise-rotated copy of their source region instead.
+            let rotated_data;
+            let (texture, stride, src_x, src_y) = if rotated {
+                rotated_data = rotate_clockwise(&image, src_rect, pixel_size);
+                (&rotated_data[..], src_rect.height() as usize, 0, 0)
+            } else {
+                let (src_x, src_y) = (src_rect.min.x as usize, src_rect.min.y as usize);
+                (&raw[..], image.width() as usize, src_x, src_y)
+            };
 
-            for (id, (name, texture, has_nine_slice)) in ids {
-                let Box2D { min, max } = packer[id].to_usize();
-                let nine_offset = usize::from(has_nine_slice);
+            let rect_width = rect.width() as usize;
+            let rect_height = rect.height() as usize;
 
-                let Some(texture) = texture.convert(TextureFormat::Rgba8UnormSrgb) else {
-                    return Err(TextureAtlasError::UnsupportedFormat {
-                        path: name,
-                        format: texture.texture_descriptor.format,
-                    });
-                };
+            let src_row = rect_width - 2 * pad;
+            let src_pos = |x, y| ((src_y + y) * stride + (src_x + x)) * pixel_size;
 
-                let texture = texture.data.as_ref().unwrap();
+            let dst_row = page_width as usize;
+            let dst_pos = |x, y| ((min_y + y) * dst_row + (min_x + x)) * pixel_size;
 
-                let rect_width = max.x - min.x;
-                let rect_height = max.y - min.y;
+            // Set topleft-wards bleeding to topleft pixel and topright-wards bleeding to topright pixel. This
+            // is so that the subsequent bleeding operation may just use a split-off copy.
+            for bleed_x in 0..bleed {
+                data[dst_pos(pad - bleed_x - 1, pad - bleed)..][..pixel_size]
+                    .copy_from_slice(&texture[src_pos(0, 0)..][..pixel_size]);
 
-                let src_row = rect_width - 2 * pad;
-                let src_pos = |x, y| ((y + nine_offset) * (src_row + nine_offset) + (x + nine_offset)) * pixel_size;
+                data[dst_pos(rect_width - pad + bleed_x, pad - bleed)..][..pixel_size]
+                    .copy_from_slice(&texture[src_pos(src_row - 1, 0)..][..pixel_size]);
+            }
 
-                let dst_row = page_width as usize;
-                let dst_pos = |x, y| ((min.y + y) * dst_row + (min.x + x)) * pixel_size;
+            // Copy top-most edge to bleed upwards.
+            data[dst_pos(pad, pad - bleed)..][..src_row * pixel_size]
+                .copy_from_slice(&texture[src_pos(0, 0)..][..src_row * pixel_size]);
+            for bleed_y in 1..bleed {
+                let split = dst_pos(pad - bleed, pad - bleed + bleed_y);
+                let (src, dst) = data.split_at_mut(split);
+
+                let count = (src_row + 2 * bleed) * pixel_size;
+                dst[..count].copy_from_slice(&src[split - dst_row * pixel_size..][..count]);
+            }
+
+            // Copy the actual image, while performing sideways bleeding.
+            for y in 0..rect_height - 2 * pad {
+                let count = src_row * pixel_size;
+                data[dst_pos(pad, pad + y)..][..count].copy_from_slice(&texture[src_pos(0, y)..][..count]);
 
-                // Set topleft-wards bleeding to topleft pixel and topright-wards bleeding to topright pixel. This
-                // is so that the subsequent bleeding operation may just use a split-off copy.
                 for bleed_x in 0..bleed {
-                    data[dst_pos(pad - bleed_x - 1, pad - bleed)..][..pixel_size]
-                        .copy_from_slice(&texture[src_pos(0, 0)..][..pixel_size]);
+                    data[dst_pos(pad - bleed_x - 1, pad + y)..][..pixel_size]
+                        .copy_from_slice(&texture[src_pos(0, y)..][..pixel_size]);
 
-                    data[dst_pos(rect_width - pad + bleed_x, pad - bleed)..][..pixel_size]
-                        .copy_from_slice(&texture[src_pos(src_row - 1, 0)..][..pixel_size]);
+                    data[dst_pos(rect_width - pad + bleed_x, pad + y)..][..pixel_size]
+                        .copy_from_slice(&texture[src_pos(src_row - 1, y)..][..pixel_size]);
                 }
+            }
 
-                // Copy top-most edge to bleed upwards.
-                data[dst_pos(pad, pad - bleed)..][..src_row * pixel_size]
-                    .copy_from_slice(&texture[src_pos(0, 0)..][..src_row * pixel_size]);
-                for bleed_y in 1..bleed {
-                    let split = dst_pos(pad - bleed, pad - bleed + bleed_y);
-                    let (src, dst) = data.split_at_mut(split);
+            // Copy the bottom-most edge to bleed downwards.
+            for bleed_y in 0..bleed {
+                let split = dst_pos(pad - bleed, rect_height - pad + bleed_y);
+                let (src, dst) = data.split_at_mut(split);
 
-                    let count = (src_row + 2 * bleed) * pixel_size;
-                    dst[..count].copy_from_slice(&src[split - dst_row * pixel_size..][..count]);
-                }
+                let count = (src_row + 2 * bleed) * pixel_size;
+                dst[..count].copy_from_slice(&src[split - dst_row * pixel_size..][..count]);
+            }
 
-                // Copy the actual image, while performing sideways bleeding.
-                for y in 0..rect_height - 2 * pad {
-                    let count = src_row * pixel_size;
-                    data[dst_pos(pad, pad + y)..][..count].copy_from_slice(&texture[src_pos(0, y)..][..count]);
+            // Finally, insert to the sprite list.
+            sprites.push((name, AtlasSprite {
+                // `atlas` and `atlas_page` are to be assigned by the caller.
+                atlas: TextureAtlas {
+                    layout: Handle::Weak(AssetId::invalid()),
+                    index: layout.textures.len(),
+                },
+                atlas_page: Handle::Weak(AssetId::invalid()),
+                layer: if array { pages.len() as u32 } else { 0 },
+                original_size,
+                trim_offset,
+                rotated,
+                nine_slices,
+                meta,
+            }));
+
+            layout.textures.push(URect {
+                min: rect.min + padding,
+                max: rect.max - padding,
+            });
+        }
 
-                    for bleed_x in 0..bleed {
-                        data[dst_pos(pad - bleed_x - 1, pad + y)..][..pixel_size]
-                            .copy_from_slice(&texture[src_pos(0, y)..][..pixel_size]);
+        pages.push(PackedPage {
+            image: Image::new(
+                Extent3d {
+                    width: page_width,
+                    height: page_height,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                data,
+                format.texture_format(),
+                usages,
+            ),
+            layout,
+            sprites,
+            efficiency: packer.efficiency(),
+        });
+    };
+
+    let init_size = uvec2(init_width, init_height).min(uvec2(max_width, max_height));
+    let max_size = uvec2(max_width, max_height);
+
+    'pages: while !units.is_empty() {
+        let mut packer = RectPacker::new(packer_kind, init_size);
+        let mut placed = Vec::<(URect, bool, SpriteSource)>::new();
+
+        'units: while let Some(unit) = units.pop() {
+            // Allocates the whole unit in a copy of the packer, so nothing is left behind if only
+            // some of it fits.
+            let allocate = |packer: &RectPacker| {
+                let mut packer = packer.clone();
+                let rects = unit
+                    .iter()
+                    .rev()
+                    .map(|entry| packer.allocate_oriented(alloc_size(entry), rotate))
+                    .collect::<Option<Vec<_>>>()?;
+
+                Some((packer, rects))
+            };
 
-                        data[dst_pos(rect_width - pad + bleed_x, pad + y)..][..pixel_size]
-                            .copy_from_slice(&texture[src_pos(src_row - 1, y)..][..pixel_size]);
-                    }
-                }
+            if let Some((allocated, rects)) = allocate(&packer) {
+                placed.extend(
+                    rects
+                        .into_iter()
+                        .zip(unit.into_iter().rev())
+                        .map(|((rect, rotated), entry)| (rect, rotated, entry)),
+                );
+                packer = allocated;
+                continue
+            }
 
-                // Copy the bottom-most edge to bleed downwards.
-                for bleed_y in 0..bleed {
-                    let split = dst_pos(pad - bleed, rect_height - pad + bleed_y);
-                    let (src, dst) = data.split_at_mut(split);
+            // Grow the page and repack everything from scratch, keeping the previous layout in case
+            // even the maximum size doesn't fit.
+            let mut page_size = packer.size();
+            while page_size != max_size {
+                page_size = (page_size * 2).min(max_size);
+
+                // Already-placed sprites keep their orientation, only the new ones may be rotated.
+                let mut grown = RectPacker::new(packer_kind, page_size);
+                let Some(rects) = placed
+                    .iter()
+                    .map(|(rect, ..)| rect.size())
+                    .map(|size| grown.allocate(size))
+                    .collect::<Option<Vec<_>>>()
+                else {
+                    continue
+                };
 
-                    let count = (src_row + 2 * bleed) * pixel_size;
-                    dst[..count].copy_from_slice(&src[split - dst_row * pixel_size..][..count]);
+                let Some((grown, new_rects)) = allocate(&grown) else { continue };
+                for ((rect, ..), new_rect) in placed.iter_mut().zip(rects) {
+                    *rect = new_rect;
                 }
 
-                // Finally, insert to the sprite map.
-                sprites.push((
-                    name,
-                    URect {
-                        min: uvec2(min.x as u32 + padding, min.y as u32 + padding),
-                        max: uvec2(max.x as u32 - padding, max.y as u32 - padding),
-                    },
-                    // `atlas` and `atlas_page` are to be initialized when all sprites are inserted.
-                    AtlasSprite {
-                        atlas: TextureAtlas {
-                            layout: Handle::Weak(AssetId::invalid()),
-                            index: 0,
-                        },
-                        atlas_page: Handle::Weak(AssetId::invalid()),
-                        nine_slices: has_nine_slice.then(|| {
-                            let mut cuts = NineSliceCuts {
-                                left: 0,
-                                right: 0,
-                                top: src_row as u32,
-                                bottom: rect_height as u32 - 2 * padding,
-                            };
-
-                            let mut found_left = false;
-                            for x in 1..src_row + 1 {
-                                let alpha = texture[x * pixel_size + 3];
-                                if !found_left && alpha >= 127 {
-                                    found_left = true;
-                                    cuts.left = x as u32;
-                                } else if found_left && alpha < 127 {
-                                    cuts.right = x as u32;
-                                    break
-                                }
-                            }
-
-                            let mut found_top = false;
-                            for y in 1..rect_height - 2 * pad + 1 {
-                                let alpha = texture[y * (src_row + nine_offset) * pixel_size + 3];
-                                if !found_top && alpha >= 127 {
-                                    found_top = true;
-                                    cuts.top = y as u32;
-                                } else if found_top && alpha < 127 {
-                                    cuts.bottom = y as u32;
-                                    break
-                                }
-                            }
-
-                            cuts
-                        }),
-                    },
-                ));
+                placed.extend(
+                    new_rects
+                        .into_iter()
+                        .zip(unit.into_iter().rev())
+                        .map(|((rect, rotated), entry)| (rect, rotated, entry)),
+                );
+                packer = grown;
+                continue 'units
             }
 
-            let page_num = output_atlas.pages.len();
-            let page = load_context.add_labeled_asset(
-                format!("page-{page_num}"),
-                Image::new(
-                    Extent3d {
-                        width: page_width,
-                        height: page_height,
-                        depth_or_array_layers: 1,
+            if packer.is_empty() {
+                let mut unit = unit;
+                let entry = unit.pop().unwrap();
+                return Err(match entry.settings.group {
+                    Some(group) if !unit.is_empty() => TextureAtlasError::GroupTooLarge {
+                        group,
+                        max_width,
+                        max_height,
                     },
-                    TextureDimension::D2,
-                    data,
-                    TextureFormat::Rgba8UnormSrgb,
-                    usages,
-                ),
-            );
-
-            // Fill the layout first before turning it into a handle.
-            let mut layout = TextureAtlasLayout {
-                size: uvec2(page_width, page_height),
-                textures: Vec::with_capacity(sprites.len()),
-            };
+                    _ => {
+                        let size = alloc_size(&entry);
+                        TextureAtlasError::TooLarge {
+                            path: entry.name,
+                            max_width,
+                            max_height,
+                            actual_width: size.x,
+                            actual_height: size.y,
+                        }
+                    }
+                })
+            }
+
+            // Re-insert the unit to the back, since we didn't end up packing that one.
+            units.push(unit);
+            push_page(placed, packer);
+            continue 'pages
+        }
 
-            for (.., rect, entry) in &mut sprites {
-                entry.atlas_page = page.clone_weak();
-                entry.atlas.index = layout.textures.len();
-                layout.textures.push(*rect);
+        push_page(placed, packer);
+    }
+
+    // Layers of a texture array all share the same size, so pad the pages to the largest one.
+    if array {
+        let size = pages.iter().fold(UVec2::ZERO, |size, page| size.max(page.layout.size));
+        for page in &mut pages {
+            resize_page(page, size);
+        }
+    }
+
+    for page in &mut pages {
+        generate_mipmaps(&mut page.image, mipmaps);
+    }
+
+    Ok(pages)
+}
+
+/// Extends a page to the bottom-right with transparent pixels.
+fn resize_page(page: &mut PackedPage, size: UVec2) {
+    let old_size = page.layout.size;
+    if old_size == size {
+        return
+    }
+
+    let Some(data) = page.image.data.as_mut() else { return };
+    let pixel_size = page.image.texture_descriptor.format.pixel_size();
+
+    let mut resized = vec![0; size.element_product() as usize * pixel_size];
+    for (src, dst) in data
+        .chunks_exact(old_size.x as usize * pixel_size)
+        .zip(resized.chunks_exact_mut(size.x as usize * pixel_size))
+    {
+        dst[..src.len()].copy_from_slice(src);
+    }
+
+    *data = resized;
+    page.image.texture_descriptor.size = Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: 1,
+    };
+
+    page.efficiency *= old_size.element_product() as f32 / size.element_product().max(1) as f32;
+    page.layout.size = size;
+}
+
+/// Stacks pages of the same size, format, and mip level count into a single texture with one array
+/// layer per page, viewed as a [`D2Array`](TextureViewDimension::D2Array). This is how
+/// [array](AtlasFile::array) atlases store their pages.
+pub fn stack_pages(pages: impl IntoIterator<Item = Image>) -> Image {
+    let mut pages = pages.into_iter();
+    let mut array = pages.next().unwrap_or_default();
+
+    let data = array.data.get_or_insert_default();
+    let mut layers = 1;
+    for page in pages {
+        data.extend(page.data.unwrap_or_default());
+        layers += 1;
+    }
+
+    array.texture_descriptor.size.depth_or_array_layers = layers;
+    array.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..default()
+    });
+
+    array
+}
+
+/// Appends a mip chain to a single-level image in one of the [`AtlasFormat`]s, such that it has
+/// `levels` levels in total, each being a box-filtered half of the previous one. The color is
+/// averaged in linear space. `levels` is capped at however many levels the image size allows.
+pub fn generate_mipmaps(image: &mut Image, levels: u32) {
+    let size = image.size();
+    let levels = levels.clamp(1, u32::BITS - size.max_element().max(1).leading_zeros());
+    if levels == 1 || image.texture_descriptor.mip_level_count != 1 {
+        return
+    }
+
+    let Some(format) = AtlasFormat::from_texture_format(image.texture_descriptor.format) else {
+        return
+    };
+    let Some(data) = image.data.as_mut() else { return };
+    let pixel_size = format.texture_format().pixel_size();
+
+    let mut offset = 0;
+    let mut src_size = size;
+    for _ in 1..levels {
+        let dst_size = (src_size / 2).max(UVec2::ONE);
+        let src = &data[offset..][..src_size.element_product() as usize * pixel_size];
+
+        let mut dst = vec![0; dst_size.element_product() as usize * pixel_size];
+        for (i, pixel) in dst.chunks_exact_mut(pixel_size).enumerate() {
+            let (x, y) = (i as u32 % dst_size.x, i as u32 / dst_size.x);
+
+            let mut sum = [0.; 4];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (src_x, src_y) = ((x * 2 + dx).min(src_size.x - 1), (y * 2 + dy).min(src_size.y - 1));
+                let color = format.read(&src[(src_y * src_size.x + src_x) as usize * pixel_size..][..pixel_size]);
+
+                for (sum, value) in sum.iter_mut().zip(color) {
+                    *sum += value;
+                }
             }
 
-            // Finally turn the layout into a handle, and assign it to sprite entries.
-            let layout = load_context.add_labeled_asset(format!("layout-{page_num}"), layout);
-            for (name, .., mut entry) in sprites {
-                entry.atlas.layout = layout.clone_weak();
-                output_atlas.sprites.insert(name, entry);
+            format.write(sum.map(|sum| sum / 4.), pixel);
+        }
+
+        offset += src.len();
+        src_size = dst_size;
+        data.extend(dst);
+    }
+
+    image.texture_descriptor.mip_level_count = levels;
+}
+
+/// Resolves the animations of an atlas containing the given sprite lookup keys, i.e. the
+/// [explicitly defined](AtlasFile::animations) ones along with those gathered from numbered
+/// sprites.
+pub fn resolve_animations<'a>(
+    file: &AtlasFile,
+    sprites: impl IntoIterator<Item = &'a PathBuf>,
+) -> Result<BTreeMap<PathBuf, AtlasAnimation>, TextureAtlasError> {
+    let sprites = sprites.into_iter().collect::<HashSet<_>>();
+    let mut animations = numbered_animations(sprites.iter().copied(), file.frame_duration);
+
+    for (
+        path,
+        AtlasAnimationFile {
+            frames: file_frames,
+            mode,
+        },
+    ) in &file.animations
+    {
+        let path = PathBuf::from(path);
+        let mut frames = Vec::with_capacity(file_frames.len());
+        for frame in file_frames {
+            let (sprite, duration) = match frame {
+                AtlasFrameFile::Sprite(sprite) => (PathBuf::from(sprite), file.frame_duration),
+                &AtlasFrameFile::Timed(ref sprite, duration) => (PathBuf::from(sprite), duration),
+            };
+
+            if !sprites.contains(&sprite) {
+                return Err(TextureAtlasError::MissingFrame { animation: path, sprite })
             }
 
-            output_atlas.pages.push((layout, page));
-            Ok(())
+            frames.push(AtlasFrame { sprite, duration });
+        }
+
+        animations.insert(path, AtlasAnimation { frames, mode: *mode });
+    }
+
+    Ok(animations)
+}
+
+/// Gathers sprites whose names end with an underscore followed by a number into looping
+/// animations named after the part before the underscore, ordered by their numbers.
+pub(crate) fn numbered_animations<'a>(
+    sprites: impl IntoIterator<Item = &'a PathBuf>,
+    frame_duration: f32,
+) -> BTreeMap<PathBuf, AtlasAnimation> {
+    let mut numbered = BTreeMap::<PathBuf, Vec<(u64, PathBuf)>>::new();
+    for name in sprites {
+        let Some((stem, number)) = name.file_name().and_then(|file| file.to_str()?.rsplit_once('_')) else {
+            continue
         };
+        let Ok(number) = number.parse::<u64>() else { continue };
 
-        'pages: while !entries.is_empty() {
-            let mut packer = AtlasAllocator::new(size2(init_width as i32, init_height as i32));
-            let mut ids = HashMap::<AllocId, (PathBuf, Image, bool)>::with_hasher(FixedHasher);
+        numbered
+            .entry(name.with_file_name(stem))
+            .or_default()
+            .push((number, name.clone()));
+    }
 
-            while let Some((name, texture, has_nine_slice)) = entries.pop() {
-                let UVec2 {
-                    x: mut base_width,
-                    y: mut base_height,
-                } = texture.size();
+    numbered
+        .into_iter()
+        .map(|(path, mut frames)| {
+            frames.sort();
+            (path, AtlasAnimation {
+                frames: frames
+                    .into_iter()
+                    .map(|(_, sprite)| AtlasFrame {
+                        sprite,
+                        duration: frame_duration,
+                    })
+                    .collect(),
+                mode: AnimationMode::Loop,
+            })
+        })
+        .collect()
+}
 
-                if has_nine_slice {
-                    base_width = base_width.saturating_sub(1);
-                    base_height = base_height.saturating_sub(1);
-                }
+/// The [`AssetServer`] that [`AtlasLoader`] lists directories through, set by
+/// [`AtlasPlugin`](crate::AtlasPlugin).
+static DIRECTORY_SERVER: RwLock<Option<AssetServer>> = RwLock::new(None);
 
-                match packer.allocate(size2(
-                    (base_width + 2 * pad as u32) as i32,
-                    (base_height + 2 * pad as u32) as i32,
-                )) {
-                    Some(alloc) => {
-                        ids.insert(alloc.id, (name, texture, has_nine_slice));
-                    }
-                    None => {
-                        let Size2D { width, height, .. } = packer.size();
-                        if width == max_width as i32 && height == max_height as i32 {
-                            if packer.is_empty() {
-                                return Err(TextureAtlasError::TooLarge {
-                                    path: name,
-                                    max_width,
-                                    max_height,
-                                    actual_width: width as u32,
-                                    actual_height: height as u32,
-                                });
-                            } else {
-                                push_page(ids, packer)?;
-
-                                // Re-insert the entry to the back, since we didn't end up packing that one.
-                                entries.push((name, texture, has_nine_slice));
-                                continue 'pages;
-                            }
-                        }
+/// Makes [`AtlasLoader`] list directories through `server`.
+pub(crate) fn set_directory_server(server: AssetServer) {
+    *DIRECTORY_SERVER.write().unwrap_or_else(PoisonError::into_inner) = Some(server)
+}
 
-                        let ChangeList { changes, failures } = packer.resize_and_rearrange(size2(
-                            (width * 2).min(max_width as i32),
-                            (height * 2).min(max_height as i32),
-                        ));
+/// Gets the [`AssetServer`] to list `dir` through.
+fn directory_server(dir: &AssetPath) -> Result<AssetServer, TextureAtlasError> {
+    let server = DIRECTORY_SERVER.read().unwrap_or_else(PoisonError::into_inner).clone();
+    server.ok_or_else(|| TextureAtlasError::InvalidDirectory {
+        path: dir.path().to_owned(),
+        error: AssetReaderError::Io(Arc::new(io::Error::other("directories are listed through `AtlasPlugin`"))),
+    })
+}
 
-                        if !failures.is_empty() {
-                            unreachable!("resizing shouldn't cause rectangles to become unfittable")
-                        }
+/// The [`LoadedFolder`]s loaded for [`Atlas::directories`] by
+/// [`AtlasWatchPlugin`](crate::AtlasWatchPlugin), along with the atlases that searched them.
+#[derive(Resource, Default)]
+pub(crate) struct WatchedDirectories(HashMap<AssetPath<'static>, (Handle<LoadedFolder>, HashSet<AssetId<Atlas>>)>);
+
+/// Loads the [directories](Atlas::directories) of loaded atlases as [`LoadedFolder`]s, and reloads
+/// the atlases when files get added to or removed from them.
+pub(crate) fn watch_atlas_directories(
+    server: Res<AssetServer>,
+    atlases: Res<Assets<Atlas>>,
+    mut watched: ResMut<WatchedDirectories>,
+    mut atlas_events: EventReader<AssetEvent<Atlas>>,
+    mut folder_events: EventReader<AssetEvent<LoadedFolder>>,
+) {
+    if !server.watching_for_changes() {
+        return
+    }
 
-                        let mut id_map = HashMap::with_hasher(FixedHasher);
-                        for Change { old, new } in changes {
-                            let rect = ids.remove(&old.id).unwrap();
-                            id_map.insert(new.id, rect);
-                        }
+    for &event in atlas_events.read() {
+        let (AssetEvent::Added { id } |
+        AssetEvent::Modified { id } |
+        AssetEvent::Removed { id } |
+        AssetEvent::Unused { id }) = event
+        else {
+            continue
+        };
 
-                        if !ids.is_empty() {
-                            unreachable!("resizing should clear all old rectangles")
-                        }
+        // Reloaded atlases may have searched other directories, so start over.
+        for (.., ids) in watched.0.values_mut() {
+            ids.remove(&id);
+        }
 
-                        ids = id_map;
-                    }
+        if let Some(atlas) = atlases.get(id) {
+            for dir in &atlas.directories {
+                let (.., ids) = watched
+                    .0
+                    .entry(dir.clone())
+                    .or_insert_with(|| (server.load_folder(dir.clone()), HashSet::new()));
+                ids.insert(id);
+            }
+        }
+
+        watched.0.retain(|_, (.., ids)| !ids.is_empty());
+    }
+
+    for &event in folder_events.read() {
+        let AssetEvent::Modified { id } = event else { continue };
+        for (folder, ids) in watched.0.values() {
+            if folder.id() != id {
+                continue
+            }
+
+            for &atlas in ids {
+                if let Some(path) = server.get_path(atlas) {
+                    server.reload(path.into_owned())
+                }
+            }
+        }
+    }
+}
+
+/// [`AtlasSources`] of [`AtlasLoader`], loading the sprite images and their sidecar metadata as
+/// load dependencies of the atlas.
+struct LoaderSources<'a, 'ctx> {
+    load_context: &'a mut LoadContext<'ctx>,
+    /// The directories searched by globs and scans, for [`Atlas::directories`].
+    directories: Vec<AssetPath<'static>>,
+}
+
+impl AtlasSources for LoaderSources<'_, '_> {
+    type Error = TextureAtlasError;
+
+    async fn list_directory(&mut self, dir: &AssetPath<'static>) -> Result<Vec<AssetPath<'static>>, Self::Error> {
+        async fn walk(
+            reader: &dyn ErasedAssetReader,
+            dir: &Path,
+            accum: &mut Vec<PathBuf>,
+        ) -> Result<(), TextureAtlasError> {
+            let mut paths = match reader.read_directory(dir).await {
+                Ok(paths) => paths,
+                Err(error) => {
+                    return Err(TextureAtlasError::InvalidDirectory {
+                        path: dir.to_owned(),
+                        error,
+                    })
+                }
+            };
+
+            while let Some(path) = paths.next().await {
+                if reader.is_directory(&path).await.unwrap_or(false) {
+                    Box::pin(walk(reader, &path, accum)).await?
+                } else {
+                    accum.push(path)
                 }
             }
 
-            push_page(ids, packer)?;
+            Ok(())
+        }
+
+        if !self.directories.contains(dir) {
+            self.directories.push(dir.clone())
         }
 
-        Ok(output_atlas)
+        // Prefer listing the unprocessed source even in processed mode, since this loader also runs inside
+        // the asset processor, where the processed directory might not have caught up yet. Only fall
+        // back to the processed directory if the unprocessed one isn't there, e.g. when only processed
+        // assets are shipped.
+        let server = directory_server(dir)?;
+        let source = server.get_source(dir.source())?;
+        let mut files = Vec::new();
+        if let Err(error) = walk(source.reader(), dir.path(), &mut files).await {
+            let (AssetServerMode::Processed, Ok(reader)) = (server.mode(), source.processed_reader()) else {
+                return Err(error)
+            };
+
+            files.clear();
+            walk(reader, dir.path(), &mut files).await?;
+        }
+
+        Ok(files
+            .into_iter()
+            .map(|file| AssetPath::from(file).with_source(dir.source().clone_owned()))
+            .collect())
+    }
+
+    async fn is_image(&mut self, path: &AssetPath<'static>) -> bool {
+        let Some(extension) = path.path().extension() else { return false };
+        let Ok(server) = directory_server(path) else { return false };
+
+        server
+            .get_asset_loader_with_extension(&extension.to_string_lossy())
+            .await
+            .is_ok_and(|loader| loader.asset_type_id() == TypeId::of::<Image>())
+    }
+
+    async fn load_image(&mut self, name: &Path, path: &AssetPath<'static>) -> Result<Image, Self::Error> {
+        match self.load_context.loader().immediate().load::<Image>(path).await {
+            Ok(image) => Ok(image.take()),
+            Err(error) => Err(TextureAtlasError::InvalidImage {
+                path: name.to_owned(),
+                error: Box::new(error),
+            }),
+        }
     }
 
+    async fn load_meta(&mut self, name: &Path, path: &AssetPath<'static>) -> Result<Option<SpriteMeta>, Self::Error> {
+        // Sidecar metadata is optional, so only a missing file is fine.
+        match self.load_context.loader().immediate().load::<SpriteMeta>(path).await {
+            Ok(meta) => Ok(Some(meta.take())),
+            Err(LoadDirectError::LoadError {
+                error: AssetLoadError::AssetReaderError(AssetReaderError::NotFound(..)),
+                ..
+            }) => Ok(None),
+            Err(error) => Err(TextureAtlasError::InvalidMeta {
+                path: name.to_owned(),
+                error: Box::new(error),
+            }),
+        }
+    }
+}
+
+/// Matches the components of `path` against the components of a glob pattern.
+fn glob_match(pattern: &[&str], path: &Path) -> bool {
+    fn matches(pattern: &[&str], path: &[String]) -> bool {
+        match pattern.split_first() {
+            None => path.is_empty(),
+            Some((&"**", rest)) => (0..=path.len()).any(|skip| matches(rest, &path[skip..])),
+            Some((&"", rest)) => matches(rest, path),
+            Some((component, rest)) => path
+                .split_first()
+                .is_some_and(|(name, path)| wildcard(component, name) && matches(rest, path)),
+        }
+    }
+
+    let path = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+
+    matches(pattern, &path)
+}
+
+/// Matches a single file or directory name against a pattern containing `*` and `?` wildcards.
+fn wildcard(pattern: &str, name: &str) -> bool {
+    let mut chars = pattern.chars();
+    match chars.next() {
+        None => name.is_empty(),
+        Some('*') => name
+            .char_indices()
+            .map(|(i, ..)| i)
+            .chain([name.len()])
+            .any(|i| wildcard(chars.as_str(), &name[i..])),
+        Some(c) => {
+            let mut name = name.chars();
+            name.next().is_some_and(|n| c == '?' || c == n) && wildcard(chars.as_str(), name.as_str())
+        }
+    }
+}
+
+/// A sprite waiting to be packed by [`pack_sprites`].
+#[derive(Debug, Clone)]
+pub struct SpriteSource {
+    name: PathBuf,
+    /// The source image, converted to the page format.
+    image: Image,
+    /// The region of `image` that ends up in the atlas page.
+    rect: URect,
+    original_size: UVec2,
+    trim_offset: UVec2,
+    nine_slices: Option<NineSliceCuts>,
+    settings: EntrySettings,
+    meta: SpriteMeta,
+}
+
+impl SpriteSource {
+    /// Derives the sprite lookup key from an image path, along with whether it's a nine-slice
+    /// sprite. The key is the path without its extension, and without the `.9` suffix of nine-slice
+    /// sprites, e.g. `ui/button.9.png` becomes `ui/button`. Returns `None` if the path has no file
+    /// name.
+    pub fn key(path: &Path) -> Option<(PathBuf, bool)> {
+        let name = path.file_stem()?.to_string_lossy();
+        Some(match name.rsplit_once('.') {
+            Some((split, "9")) => (path.with_file_name(split), true),
+            _ => (path.with_file_name(&*name), false),
+        })
+    }
+
+    /// Prepares an image to be packed under the given lookup key, converting it to the page format.
+    /// Trimming and 9-patch markers go by the alpha of the image before it's converted.
+    ///
+    /// Nine-slice sprites are 9-patch images: the markers on their top and left edges define the
+    /// stretchable region, and the markers on their bottom and right edges, if any, define the
+    /// [content padding](NineSliceCuts::content_padding). Images are taken to have bottom and right
+    /// markers only if their bottom row and right column consist of transparent pixels and at least
+    /// one opaque black marker. The markers are skipped, and such sprites are never trimmed.
+    pub fn new(
+        name: PathBuf,
+        image: Image,
+        format: AtlasFormat,
+        nine_slice: bool,
+        trim: bool,
+    ) -> Result<Self, TextureAtlasError> {
+        let source_format = image.texture_descriptor.format;
+        let unsupported = |name: &PathBuf| TextureAtlasError::UnsupportedFormat {
+            path: name.clone(),
+            format: source_format,
+        };
+
+        let size = image.size();
+        let (rect, nine_slices) = {
+            let rgba = match image.texture_descriptor.format {
+                TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm => Cow::Borrowed(&image),
+                _ => Cow::Owned(convert(image.clone(), AtlasFormat::Rgba8UnormSrgb).ok_or_else(|| unsupported(&name))?),
+            };
+
+            if nine_slice {
+                let (rect, cuts) = nine_patch(&rgba);
+                (rect, Some(cuts))
+            } else if trim {
+                let rect = opaque_bounds(&rgba).unwrap_or(URect::from_corners(UVec2::ZERO, UVec2::ONE.min(size)));
+                (rect, None)
+            } else {
+                (URect::from_corners(UVec2::ZERO, size), None)
+            }
+        };
+
+        let Some(image) = convert(image, format) else { return Err(unsupported(&name)) };
+
+        Ok(Self {
+            name,
+            image,
+            rect,
+            original_size: if nine_slice { rect.size() } else { size },
+            trim_offset: if nine_slice { UVec2::ZERO } else { rect.min },
+            nine_slices,
+            settings: EntrySettings::default(),
+            meta: SpriteMeta::default(),
+        })
+    }
+
+    /// Overrides some of the packing settings for this sprite.
     #[inline]
-    fn extensions(&self) -> &[&str] {
-        &["atlas.ron"]
+    pub fn with_settings(mut self, settings: EntrySettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Attaches [metadata](SpriteMeta) to this sprite.
+    #[inline]
+    pub fn with_meta(mut self, meta: SpriteMeta) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    /// Declares the nine-slice cuts by how thick the left, right, top, and bottom edges are, in
+    /// pixels, overriding those read off 9-patch markers. The content padding of 9-patch images is
+    /// kept. If the sprite was trimmed, it's untrimmed, since the cuts are relative to the whole
+    /// image.
+    pub fn with_slice(mut self, (left, right, top, bottom): (u32, u32, u32, u32)) -> Self {
+        if self.nine_slices.is_none() {
+            self.rect = URect::from_corners(UVec2::ZERO, self.image.size());
+            self.trim_offset = UVec2::ZERO;
+        }
+
+        let size = self.rect.size();
+        self.nine_slices = Some(NineSliceCuts {
+            left: left.min(size.x),
+            right: size.x.saturating_sub(right).max(left.min(size.x)),
+            top: top.min(size.y),
+            bottom: size.y.saturating_sub(bottom).max(top.min(size.y)),
+            content_padding: self.nine_slices.and_then(|cuts| cuts.content_padding),
+        });
+
+        self
+    }
+
+    /// The sprite lookup key.
+    #[inline]
+    pub fn name(&self) -> &Path {
+        &self.name
+    }
+}
+
+/// Converts an image to one of the [`AtlasFormat`]s, or returns `None` if its format can't be read.
+fn convert(image: Image, format: AtlasFormat) -> Option<Image> {
+    let texture_format = format.texture_format();
+    match (image.texture_descriptor.format, texture_format) {
+        (from, to) if from == to => return Some(image),
+        // Linear and sRGB 8-bit images only differ in how they're sampled, so keep the bytes as-is.
+        (
+            TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm,
+            TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm,
+        ) => {
+            let mut image = image;
+            image.texture_descriptor.format = texture_format;
+            return Some(image)
+        }
+        _ => {}
+    }
+
+    let width = image.width();
+    let pixel_size = texture_format.pixel_size();
+
+    let mut data = vec![0; image.width() as usize * image.height() as usize * pixel_size];
+    for (i, pixel) in data.chunks_exact_mut(pixel_size).enumerate() {
+        let color = image.get_color_at(i as u32 % width, i as u32 / width).ok()?.to_linear();
+        let rgba = match format {
+            AtlasFormat::R8Unorm => [color.luminance() * color.alpha; 4],
+            _ => color.to_f32_array(),
+        };
+
+        format.write(rgba, pixel);
+    }
+
+    Some(Image::new(
+        Extent3d {
+            width,
+            height: image.height(),
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        texture_format,
+        image.asset_usage,
+    ))
+}
+
+/// Finds the bounding box of the non-transparent pixels of an [`TextureFormat::Rgba8UnormSrgb`]
+/// image, or `None` if it's fully transparent.
+fn opaque_bounds(image: &Image) -> Option<URect> {
+    let width = image.width() as usize;
+    let data = image.data.as_ref()?;
+
+    let mut bounds = None::<URect>;
+    for (i, pixel) in data.chunks_exact(4).enumerate() {
+        if pixel[3] == 0 {
+            continue
+        }
+
+        let pos = uvec2((i % width) as u32, (i / width) as u32);
+        bounds = Some(match bounds {
+            None => URect::from_corners(pos, pos + 1),
+            Some(rect) => URect::from_corners(rect.min.min(pos), rect.max.max(pos + 1)),
+        });
+    }
+
+    bounds
+}
+
+/// Reads the stretchable region and content padding off the markers of a
+/// [`TextureFormat::Rgba8UnormSrgb`] 9-patch image, returning the region inside the markers along
+/// with the cuts relative to it. See [`SpriteSource::new`].
+fn nine_patch(image: &Image) -> (URect, NineSliceCuts) {
+    let size = image.size();
+    let data = image.data.as_deref().unwrap_or_default();
+    let pixel = |x: u32, y: u32| &data[(y as usize * size.x as usize + x as usize) * 4..][..4];
+    let marker = |x, y| pixel(x, y)[3] >= 127;
+
+    // Tell full 9-patches apart from images that only have the top and left markers.
+    let black = |x, y| pixel(x, y) == [0, 0, 0, 255];
+    let border = |x, y| black(x, y) || pixel(x, y)[3] == 0;
+    let full = size.x >= 3 &&
+        size.y >= 3 &&
+        (0..size.x).all(|x| border(x, size.y - 1)) &&
+        (0..size.y).all(|y| border(size.x - 1, y)) &&
+        ((0..size.x).any(|x| black(x, size.y - 1)) || (0..size.y).any(|y| black(size.x - 1, y)));
+
+    let max = if full { size - 1 } else { size };
+    let rect = URect::from_corners(UVec2::ONE.min(size), max);
+
+    // The first run of markers along an edge, relative to the region inside the markers.
+    fn run(markers: impl ExactSizeIterator<Item = bool>) -> Option<(u32, u32)> {
+        let len = markers.len() as u32;
+        let mut markers = markers.zip(0..);
+        let (_, start) = markers.find(|&(marker, _)| marker)?;
+        let end = markers.find(|&(marker, _)| !marker).map_or(len, |(_, i)| i);
+        Some((start, end))
+    }
+
+    let (width, height) = (rect.width(), rect.height());
+    let (left, right) = run((rect.min.x..rect.max.x).map(|x| marker(x, 0))).unwrap_or((0, width));
+    let (top, bottom) = run((rect.min.y..rect.max.y).map(|y| marker(0, y))).unwrap_or((0, height));
+
+    let content_padding = full.then(|| {
+        let (content_left, content_right) =
+            run((rect.min.x..rect.max.x).map(|x| marker(x, size.y - 1))).unwrap_or((left, right));
+        let (content_top, content_bottom) =
+            run((rect.min.y..rect.max.y).map(|y| marker(size.x - 1, y))).unwrap_or((top, bottom));
+
+        ContentPadding {
+            left: content_left,
+            right: width - content_right,
+            top: content_top,
+            bottom: height - content_bottom,
+        }
+    });
+
+    (rect, NineSliceCuts {
+        left,
+        right,
+        top,
+        bottom,
+        content_padding,
+    })
+}
+
+/// Multiplies the color channels of an image in the given format by its alpha, in linear space.
+fn premultiply(image: &mut Image, format: AtlasFormat) {
+    let Some(data) = image.data.as_mut() else { return };
+    for pixel in data.chunks_exact_mut(format.texture_format().pixel_size()) {
+        let [red, green, blue, alpha] = format.read(pixel);
+        format.write([red * alpha, green * alpha, blue * alpha, alpha], pixel);
+    }
+}
+
+/// Copies `rect` of an image with the given pixel size, rotated 90° clockwise. The result has a row
+/// stride of `rect.height()` pixels.
+fn rotate_clockwise(image: &Image, rect: URect, pixel_size: usize) -> Vec<u8> {
+    let stride = image.width() as usize;
+    let data = image.data.as_deref().unwrap_or_default();
+    let (width, height) = (rect.width() as usize, rect.height() as usize);
+    let (min_x, min_y) = (rect.min.x as usize, rect.min.y as usize);
+
+    let mut rotated = vec![0; width * height * pixel_size];
+    for y in 0..width {
+        for x in 0..height {
+            let src = ((min_y + height - 1 - x) * stride + min_x + y) * pixel_size;
+            rotated[(y * height + x) * pixel_size..][..pixel_size].copy_from_slice(&data[src..][..pixel_size]);
+        }
+    }
+
+    rotated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an [`TextureFormat::Rgba8UnormSrgb`] image out of the colors of its pixels.
+    fn rgba(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 4]) -> Image {
+        let pixel = &pixel;
+        let data = (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| pixel(x, y)))
+            .collect::<Vec<_>>();
+
+        Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::all(),
+        )
+    }
+
+    #[test]
+    fn opaque_bounds_trim() {
+        // Opaque pixels at (1, 2) and (3, 1) in a 6x5 image.
+        let image = rgba(6, 5, |x, y| match (x, y) {
+            (1, 2) | (3, 1) => [255, 0, 0, 255],
+            // Fully transparent pixels count as transparent regardless of their color.
+            (5, 4) => [255, 255, 255, 0],
+            _ => [0; 4],
+        });
+
+        assert_eq!(opaque_bounds(&image), Some(URect::new(1, 1, 4, 3)));
+
+        let sprite = SpriteSource::new("sprite".into(), image.clone(), AtlasFormat::Rgba8UnormSrgb, false, true).unwrap();
+        assert_eq!(sprite.rect, URect::new(1, 1, 4, 3));
+        assert_eq!(sprite.original_size, uvec2(6, 5));
+        assert_eq!(sprite.trim_offset, uvec2(1, 1));
+
+        // Untrimmed sprites keep the whole image.
+        let sprite = SpriteSource::new("sprite".into(), image, AtlasFormat::Rgba8UnormSrgb, false, false).unwrap();
+        assert_eq!(sprite.rect, URect::new(0, 0, 6, 5));
+        assert_eq!(sprite.trim_offset, UVec2::ZERO);
+    }
+
+    #[test]
+    fn opaque_bounds_transparent() {
+        let image = rgba(4, 3, |_, _| [0; 4]);
+        assert_eq!(opaque_bounds(&image), None);
+
+        // Fully transparent sprites are trimmed down to a single pixel instead of nothing.
+        let sprite = SpriteSource::new("sprite".into(), image, AtlasFormat::Rgba8UnormSrgb, false, true).unwrap();
+        assert_eq!(sprite.rect, URect::new(0, 0, 1, 1));
+        assert_eq!(sprite.original_size, uvec2(4, 3));
+        assert_eq!(sprite.trim_offset, UVec2::ZERO);
+    }
+
+    #[test]
+    fn rotate_clockwise_pixels() {
+        // Every pixel holds its own coordinates.
+        let image = rgba(5, 4, |x, y| [x as u8, y as u8, 0, 255]);
+        let rotated = rotate_clockwise(&image, URect::new(1, 1, 4, 3), 4)
+            .chunks_exact(4)
+            .map(|pixel| (pixel[0], pixel[1]))
+            .collect::<Vec<_>>();
+
+        // The 3x2 region becomes 2x3, with its top-left pixel in the top-right and its bottom-left
+        // pixel in the top-left.
+        assert_eq!(rotated, [(1, 2), (1, 1), (2, 2), (2, 1), (3, 2), (3, 1)]);
+    }
+
+    /// A 9-patch image with a 5x4 region inside its markers. If either `bottom` or `right` is set,
+    /// the image has a bottom row and right column for content padding markers, with markers on
+    /// the bottom and right edges respectively.
+    fn nine_patch_image(bottom: bool, right: bool) -> Image {
+        let padded = bottom || right;
+        let black = [0, 0, 0, 255];
+        rgba(5 + 1 + padded as u32, 4 + 1 + padded as u32, |x, y| match (x, y) {
+            // Stretch along columns 1..3 and row 1 of the region.
+            (2 | 3, 0) | (0, 2) => black,
+            // Pad content by 1 on the left and right, and 0 on the top and 2 on the bottom.
+            (2..=4, 5) if bottom => black,
+            (6, 1 | 2) if right => black,
+            (0, _) | (_, 0) => [0; 4],
+            (6, _) | (_, 5) if padded => [0; 4],
+            _ => [200, 100, 50, 255],
+        })
+    }
+
+    #[test]
+    fn nine_patch_padding() {
+        let (rect, cuts) = nine_patch(&nine_patch_image(true, true));
+        assert_eq!(rect, URect::new(1, 1, 6, 5));
+        assert_eq!(cuts, NineSliceCuts {
+            left: 1,
+            right: 3,
+            top: 1,
+            bottom: 2,
+            content_padding: Some(ContentPadding {
+                left: 1,
+                right: 1,
+                top: 0,
+                bottom: 2,
+            }),
+        });
+
+        // The markers are skipped, and the region inside them isn't trimmed.
+        let sprite = SpriteSource::new(
+            "panel".into(),
+            nine_patch_image(true, true),
+            AtlasFormat::Rgba8UnormSrgb,
+            true,
+            true,
+        )
+        .unwrap();
+        assert_eq!(sprite.rect, URect::new(1, 1, 6, 5));
+        assert_eq!(sprite.original_size, uvec2(5, 4));
+        assert_eq!(sprite.trim_offset, UVec2::ZERO);
+        assert_eq!(sprite.nine_slices, Some(cuts));
+    }
+
+    #[test]
+    fn nine_patch_missing_padding() {
+        // Without bottom and right markers, the image is all stretch markers and content.
+        let (rect, cuts) = nine_patch(&nine_patch_image(false, false));
+        assert_eq!(rect, URect::new(1, 1, 6, 5));
+        assert_eq!(cuts, NineSliceCuts {
+            left: 1,
+            right: 3,
+            top: 1,
+            bottom: 2,
+            content_padding: None,
+        });
+
+        // With only bottom markers, the vertical content padding follows the stretchable region.
+        let (rect, cuts) = nine_patch(&nine_patch_image(true, false));
+        assert_eq!(rect, URect::new(1, 1, 6, 5));
+        assert_eq!(
+            cuts.content_padding,
+            Some(ContentPadding {
+                left: 1,
+                right: 1,
+                top: 1,
+                bottom: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn split_glob() {
+        assert_eq!(TextureAtlasEntry::split_glob("icons/ui/*.png"), ("icons/ui", "*.png"));
+        assert_eq!(TextureAtlasEntry::split_glob("icons/**/ui/*.png"), ("icons", "**/ui/*.png"));
+        assert_eq!(TextureAtlasEntry::split_glob("**/*.png"), ("", "**/*.png"));
+        assert_eq!(TextureAtlasEntry::split_glob("icon_?.png"), ("", "icon_?.png"));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard("*.png", "icon.png"));
+        assert!(wildcard("*", ""));
+        assert!(wildcard("icon_?.png", "icon_1.png"));
+        assert!(!wildcard("icon_?.png", "icon_.png"));
+        assert!(!wildcard("icon_?.png", "icon_10.png"));
+        assert!(!wildcard("*.png", "icon.jpg"));
+    }
+
+    #[test]
+    fn glob_depth() {
+        // `**` matches any number of directories, including none.
+        let pattern = ["**", "*.png"];
+        assert!(glob_match(&pattern, Path::new("icon.png")));
+        assert!(glob_match(&pattern, Path::new("a/icon.png")));
+        assert!(glob_match(&pattern, Path::new("a/b/c/icon.png")));
+        assert!(!glob_match(&pattern, Path::new("a/b/c/icon.jpg")));
+
+        let pattern = ["a", "**", "b", "*"];
+        assert!(glob_match(&pattern, Path::new("a/b/icon.png")));
+        assert!(glob_match(&pattern, Path::new("a/x/y/b/icon.png")));
+        assert!(!glob_match(&pattern, Path::new("a/icon.png")));
+
+        // `*` stays within a single component.
+        let pattern = ["*.png"];
+        assert!(glob_match(&pattern, Path::new("icon.png")));
+        assert!(!glob_match(&pattern, Path::new("a/icon.png")));
+        assert!(!glob_match(&["*"], Path::new("a/icon.png")));
+    }
+
+    #[test]
+    fn exclude_precedence() {
+        let exclude = ["*_old.png".to_owned(), "unused/**".to_owned()];
+        assert!(TextureAtlasEntry::matches("**/*", &exclude, Path::new("a/icon.png")));
+
+        // Excludes without a `/` match file names at any depth.
+        assert!(!TextureAtlasEntry::matches("**/*", &exclude, Path::new("icon_old.png")));
+        assert!(!TextureAtlasEntry::matches("**/*", &exclude, Path::new("a/b/icon_old.png")));
+
+        // Excludes with a `/` match the whole relative path.
+        assert!(!TextureAtlasEntry::matches("**/*", &exclude, Path::new("unused/icon.png")));
+        assert!(!TextureAtlasEntry::matches("**/*", &exclude, Path::new("unused/a/icon.png")));
+        assert!(TextureAtlasEntry::matches("**/*", &exclude, Path::new("a/unused/icon.png")));
+
+        // Excludes win even over patterns that match the exact path.
+        assert!(!TextureAtlasEntry::matches(
+            "icon_old.png",
+            &exclude,
+            Path::new("icon_old.png")
+        ));
     }
 }
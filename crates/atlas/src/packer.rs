@@ -0,0 +1,408 @@
+//! Rectangle packing algorithms used to lay out sprites in [`Atlas`](crate::atlas::Atlas) pages.
+//!
+//! Which algorithm fits best depends heavily on the sprite set, so [`AtlasLoader`] lets the atlas
+//! file choose through its [`packer`](crate::loader::AtlasFile::packer) field and reports the
+//! resulting [efficiency](crate::atlas::Atlas::efficiency) of each page for comparison.
+//!
+//! [`AtlasLoader`]: crate::loader::AtlasLoader
+
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+use guillotiere::{AtlasAllocator, euclid::Box2D, size2};
+use serde::{Deserialize, Serialize};
+
+/// Selects the algorithm used to pack sprites into atlas pages.
+#[derive(Serialize, Deserialize, Reflect, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[reflect(Default, Debug, PartialEq, Hash)]
+pub enum AtlasPacker {
+    /// Guillotine packing through [`guillotiere`]. Fast and decent for similarly-sized sprites.
+    #[default]
+    Guillotine,
+    /// Maximal rectangles with the best short side fit heuristic. Usually the tightest, but the
+    /// slowest for large sprite sets.
+    MaxRects,
+    /// Bottom-left skyline packing. A good middle ground between speed and density.
+    Skyline,
+    /// Packs sprites in rows of similar heights. Very fast, and tight for sprites sharing a height,
+    /// e.g. font glyphs or animation frames.
+    Shelf,
+}
+
+impl AtlasPacker {
+    /// Compares two sprite sizes such that the greater one should be packed first.
+    #[inline]
+    pub fn order(self, a: UVec2, b: UVec2) -> Ordering {
+        match self {
+            Self::Guillotine | Self::MaxRects | Self::Skyline => (a.x + a.y).cmp(&(b.x + b.y)),
+            Self::Shelf => (a.y, a.x).cmp(&(b.y, b.x)),
+        }
+    }
+}
+
+/// A single page being packed by an [`AtlasPacker`].
+#[derive(Clone)]
+pub struct RectPacker {
+    size: UVec2,
+    used: u64,
+    state: PackerState,
+}
+
+#[derive(Clone)]
+enum PackerState {
+    Guillotine(AtlasAllocator),
+    MaxRects(Vec<URect>),
+    Skyline(Vec<(u32, u32, u32)>),
+    Shelf { shelves: Vec<(u32, u32, u32)>, top: u32 },
+}
+
+impl RectPacker {
+    /// Creates an empty page of the given size.
+    pub fn new(packer: AtlasPacker, size: UVec2) -> Self {
+        Self {
+            size,
+            used: 0,
+            state: match packer {
+                AtlasPacker::Guillotine => PackerState::Guillotine(AtlasAllocator::new(size2(size.x as i32, size.y as i32))),
+                AtlasPacker::MaxRects => PackerState::MaxRects(vec![URect::from_corners(UVec2::ZERO, size)]),
+                AtlasPacker::Skyline => PackerState::Skyline(vec![(0, 0, size.x)]),
+                AtlasPacker::Shelf => PackerState::Shelf {
+                    shelves: Vec::new(),
+                    top: 0,
+                },
+            },
+        }
+    }
+
+    /// The page size.
+    #[inline]
+    pub const fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    /// Whether nothing has been allocated yet.
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.used == 0
+    }
+
+    /// The ratio of allocated area to the page area, in `[0, 1]`.
+    #[inline]
+    pub fn efficiency(&self) -> f32 {
+        (self.used as f64 / (self.size.x as u64 * self.size.y as u64).max(1) as f64) as f32
+    }
+
+    /// Allocates a rectangle of the given size, returning `None` if it doesn't fit anywhere.
+    #[inline]
+    pub fn allocate(&mut self, size: UVec2) -> Option<URect> {
+        self.allocate_oriented(size, false).map(|(rect, ..)| rect)
+    }
+
+    /// Like [`allocate`](Self::allocate), but if `rotate` is `true`, the rectangle may be rotated
+    /// by 90° when that fits better. The returned flag tells whether it was, in which case the
+    /// returned rectangle has its width and height swapped. [`AtlasPacker::Guillotine`] can't score
+    /// placements, so it only rotates rectangles that don't fit otherwise.
+    pub fn allocate_oriented(&mut self, size: UVec2, rotate: bool) -> Option<(URect, bool)> {
+        let fits = |size: UVec2| size.x > 0 && size.y > 0 && size.x <= self.size.x && size.y <= self.size.y;
+        let orientations = [(size, false), (size.yx(), true)];
+        let orientations = &orientations[..if rotate && size.x != size.y { 2 } else { 1 }];
+
+        let page = self.size;
+        let (min, size, rotated) = match &mut self.state {
+            PackerState::Guillotine(allocator) => {
+                orientations
+                    .iter()
+                    .filter(|&&(size, ..)| fits(size))
+                    .find_map(|&(size, rotated)| {
+                        let Box2D { min, .. } = allocator.allocate(size2(size.x as i32, size.y as i32))?.rectangle;
+                        Some((uvec2(min.x as u32, min.y as u32), size, rotated))
+                    })?
+            }
+            PackerState::MaxRects(free) => {
+                let (target, size, rotated) = orientations
+                    .iter()
+                    .filter(|&&(size, ..)| fits(size))
+                    .filter_map(|&(size, rotated)| {
+                        let (score, target) = max_rects_find(free, size)?;
+                        Some((score, target, size, rotated))
+                    })
+                    .min_by_key(|&(score, ..)| score)
+                    .map(|(_, target, size, rotated)| (target, size, rotated))?;
+
+                max_rects_place(free, URect::from_corners(target, target + size));
+                (target, size, rotated)
+            }
+            PackerState::Skyline(skyline) => {
+                let ((index, y), size, rotated) = orientations
+                    .iter()
+                    .filter(|&&(size, ..)| fits(size))
+                    .filter_map(|&(size, rotated)| {
+                        let (score, found) = skyline_find(skyline, page, size)?;
+                        Some((score, found, size, rotated))
+                    })
+                    .min_by_key(|&(score, ..)| score)
+                    .map(|(_, found, size, rotated)| (found, size, rotated))?;
+
+                (skyline_place(skyline, index, y, size), size, rotated)
+            }
+            PackerState::Shelf { shelves, top } => {
+                let (shelf, size, rotated) = orientations
+                    .iter()
+                    .filter(|&&(size, ..)| fits(size))
+                    .filter_map(|&(size, rotated)| {
+                        let (score, shelf) = shelf_find(shelves, *top, page, size)?;
+                        Some((score, shelf, size, rotated))
+                    })
+                    .min_by_key(|&(score, ..)| score)
+                    .map(|(_, shelf, size, rotated)| (shelf, size, rotated))?;
+
+                (shelf_place(shelves, top, shelf, size), size, rotated)
+            }
+        };
+
+        self.used += size.x as u64 * size.y as u64;
+        Some((URect::from_corners(min, min + size), rotated))
+    }
+}
+
+/// Finds the free rectangle with the best short side fit, breaking ties with the long side.
+fn max_rects_find(free: &[URect], size: UVec2) -> Option<((u32, u32), UVec2)> {
+    free.iter()
+        .filter(|rect| rect.width() >= size.x && rect.height() >= size.y)
+        .map(|rect| {
+            let (dx, dy) = (rect.width() - size.x, rect.height() - size.y);
+            ((dx.min(dy), dx.max(dy)), rect.min)
+        })
+        .min_by_key(|&(score, ..)| score)
+}
+
+fn max_rects_place(free: &mut Vec<URect>, placed: URect) {
+    let mut split = Vec::new();
+    free.retain(|&rect| {
+        if rect.intersect(placed).is_empty() {
+            return true
+        }
+
+        if placed.min.x > rect.min.x {
+            split.push(URect::new(rect.min.x, rect.min.y, placed.min.x, rect.max.y));
+        }
+        if placed.max.x < rect.max.x {
+            split.push(URect::new(placed.max.x, rect.min.y, rect.max.x, rect.max.y));
+        }
+        if placed.min.y > rect.min.y {
+            split.push(URect::new(rect.min.x, rect.min.y, rect.max.x, placed.min.y));
+        }
+        if placed.max.y < rect.max.y {
+            split.push(URect::new(rect.min.x, placed.max.y, rect.max.x, rect.max.y));
+        }
+
+        false
+    });
+
+    free.append(&mut split);
+
+    // Prune free rectangles that are fully contained in another.
+    let mut i = 0;
+    while i < free.len() {
+        let rect = free[i];
+        if free
+            .iter()
+            .enumerate()
+            .any(|(j, &other)| i != j && other.contains(rect.min) && other.contains(rect.max) && (other != rect || j < i))
+        {
+            free.swap_remove(i);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Finds the skyline segment to place on. Each segment is `(x, y, width)`, where `y` is the lowest
+/// free row above that segment. Prefers positions that keep the skyline the lowest, then narrower
+/// segments to reduce waste.
+fn skyline_find(skyline: &[(u32, u32, u32)], page: UVec2, size: UVec2) -> Option<((u32, u32), (usize, u32))> {
+    let mut best = None::<((u32, u32), (usize, u32))>;
+    for (i, &(x, _, width)) in skyline.iter().enumerate() {
+        if x + size.x > page.x {
+            break
+        }
+
+        let mut y = 0;
+        let mut covered = 0;
+        for &(_, seg_y, seg_width) in &skyline[i..] {
+            if covered >= size.x {
+                break
+            }
+
+            y = y.max(seg_y);
+            covered += seg_width;
+        }
+
+        if y + size.y > page.y {
+            continue
+        }
+
+        let score = (y + size.y, width);
+        if best.is_none_or(|(best_score, ..)| score < best_score) {
+            best = Some((score, (i, y)));
+        }
+    }
+
+    best
+}
+
+fn skyline_place(skyline: &mut Vec<(u32, u32, u32)>, index: usize, y: u32, size: UVec2) -> UVec2 {
+    let x = skyline[index].0;
+    skyline.insert(index, (x, y + size.y, size.x));
+
+    // Shrink or remove the segments now covered by the new one.
+    let end = x + size.x;
+    let i = index + 1;
+    while i < skyline.len() {
+        let (seg_x, seg_y, seg_width) = skyline[i];
+        if seg_x >= end {
+            break
+        }
+
+        let seg_end = seg_x + seg_width;
+        if seg_end <= end {
+            skyline.remove(i);
+        } else {
+            skyline[i] = (end, seg_y, seg_end - end);
+            break
+        }
+    }
+
+    // Merge neighboring segments of the same height.
+    let mut i = 0;
+    while i + 1 < skyline.len() {
+        if skyline[i].1 == skyline[i + 1].1 {
+            skyline[i].2 += skyline[i + 1].2;
+            skyline.remove(i + 1);
+        } else {
+            i += 1;
+        }
+    }
+
+    uvec2(x, y)
+}
+
+/// Finds the shelf wasting the least height, or `None` inside the returned option to open a new
+/// one. Each shelf is `(y, height, cursor)`. Opening a new shelf scores worse than any existing
+/// one.
+fn shelf_find(shelves: &[(u32, u32, u32)], top: u32, page: UVec2, size: UVec2) -> Option<((bool, u32), Option<usize>)> {
+    shelves
+        .iter()
+        .enumerate()
+        .filter(|&(_, &(_, height, cursor))| height >= size.y && cursor + size.x <= page.x)
+        .map(|(i, &(_, height, _))| ((false, height - size.y), Some(i)))
+        .min_by_key(|&(score, ..)| score)
+        .or_else(|| (top + size.y <= page.y).then_some(((true, size.y), None)))
+}
+
+fn shelf_place(shelves: &mut Vec<(u32, u32, u32)>, top: &mut u32, shelf: Option<usize>, size: UVec2) -> UVec2 {
+    match shelf {
+        Some(i) => {
+            let (y, _, cursor) = &mut shelves[i];
+            let min = uvec2(*cursor, *y);
+            *cursor += size.x;
+            min
+        }
+        None => {
+            let min = uvec2(0, *top);
+            shelves.push((*top, size.y, size.x));
+            *top += size.y;
+            min
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKERS: [AtlasPacker; 4] = [
+        AtlasPacker::Guillotine,
+        AtlasPacker::MaxRects,
+        AtlasPacker::Skyline,
+        AtlasPacker::Shelf,
+    ];
+
+    /// A fixed set of varied sizes, including tall and wide ones worth rotating.
+    fn sizes(packer: AtlasPacker) -> Vec<UVec2> {
+        let mut state = 0x2545_f491_u32;
+        let mut sizes = (0..96)
+            .map(|i| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let (a, b) = (4 + (state >> 8) % 28, 4 + (state >> 20) % 12);
+                if i % 2 == 0 { uvec2(a, b) } else { uvec2(b, a) }
+            })
+            .collect::<Vec<_>>();
+
+        sizes.sort_by(|&a, &b| packer.order(b, a));
+        sizes
+    }
+
+    #[test]
+    fn packs_without_overlaps() {
+        for packer in PACKERS {
+            for rotate in [false, true] {
+                let page = uvec2(128, 128);
+                let mut rects = RectPacker::new(packer, page);
+                let mut placed = Vec::<URect>::new();
+
+                for size in sizes(packer) {
+                    let Some((rect, rotated)) = rects.allocate_oriented(size, rotate) else { continue };
+
+                    assert!(rotate || !rotated, "{packer:?} rotated without being allowed to");
+                    assert_eq!(rect.size(), if rotated { size.yx() } else { size }, "{packer:?}");
+                    assert!(
+                        rect.max.x <= page.x && rect.max.y <= page.y,
+                        "{packer:?} placed {rect:?} out of bounds"
+                    );
+
+                    for &other in &placed {
+                        assert!(
+                            rect.intersect(other).is_empty(),
+                            "{packer:?} (rotate: {rotate}) placed {rect:?} over {other:?}"
+                        );
+                    }
+
+                    placed.push(rect);
+                }
+
+                assert!(
+                    placed.len() > 48,
+                    "{packer:?} (rotate: {rotate}) only placed {}",
+                    placed.len()
+                );
+
+                let area = placed.iter().map(|rect| rect.width() * rect.height()).sum::<u32>();
+                assert_eq!(
+                    rects.efficiency(),
+                    (area as f64 / (page.x * page.y) as f64) as f32,
+                    "{packer:?}"
+                );
+                assert!(!rects.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_what_doesnt_fit() {
+        for packer in PACKERS {
+            let mut rects = RectPacker::new(packer, uvec2(16, 8));
+            assert_eq!(rects.allocate(uvec2(17, 1)), None, "{packer:?}");
+            assert_eq!(rects.allocate(uvec2(0, 4)), None, "{packer:?}");
+
+            // Only fits once rotated.
+            assert_eq!(rects.allocate_oriented(uvec2(4, 12), false), None, "{packer:?}");
+            let (rect, rotated) = rects.allocate_oriented(uvec2(4, 12), true).unwrap();
+            assert!(rotated, "{packer:?}");
+            assert_eq!(rect.size(), uvec2(12, 4), "{packer:?}");
+
+            assert_eq!(rects.efficiency(), 48. / 128., "{packer:?}");
+        }
+    }
+}
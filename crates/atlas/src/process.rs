@@ -0,0 +1,638 @@
+//! [`AssetSaver`] and [`AssetLoader`] implementations for pre-packing [`Atlas`]es through Bevy's
+//! asset processor.
+//!
+//! Packing an atlas means loading every sprite image and copying them into pages, which is wasted
+//! work to do on every run of a release build. With the `asset_processor` feature of Bevy enabled,
+//! [`AtlasProcessor`] is made the default processor of `.atlas.ron` files: it packs them with
+//! [`AtlasLoader`] and writes the result through [`AtlasSaver`] into the processed asset directory,
+//! re-processing them whenever the atlas file or any of its sprite images change. Builds running in
+//! [processed mode](bevy::asset::AssetMode::Processed) then load them with
+//! [`ProcessedAtlasLoader`], which only has to decode the pages. Builds running in unprocessed mode
+//! keep loading `.atlas.ron` files with [`AtlasLoader`] as usual, hot reloading included.
+//!
+//! The processed form consists of a little-endian `u64` length of the metadata, the metadata itself
+//! in compact [RON](ron), and finally the PNG-encoded pages in order, whose lengths are written in
+//! the metadata.
+
+use std::{borrow::Cow, io, path::PathBuf};
+
+use bevy::{
+    asset::{
+        AssetLoader, LoadContext, RenderAssetUsages,
+        io::{Reader, Writer},
+        processor::LoadTransformAndSave,
+        ron,
+        ron::error::SpannedError,
+        saver::{AssetSaver, SavedAsset},
+        transformer::IdentityAssetTransformer,
+    },
+    image::TextureFormatPixelInfo,
+    platform::{collections::HashMap, hash::FixedHasher},
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    tasks::futures_lite::AsyncWriteExt,
+};
+use derive_more::{Display, Error, From};
+use image::{ExtendedColorType, ImageEncoder, ImageError, ImageFormat, codecs::png::PngEncoder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::{AnimationMode, AtlasAnimation, AtlasFrame},
+    atlas::{Atlas, AtlasSprite, ContentPadding, NineSliceCuts},
+    loader::{AtlasFile, AtlasFormat, AtlasLoader, SpriteMetaFile, generate_mipmaps, stack_pages},
+};
+
+/// Encodes the full-size level of an atlas page as PNG, leaving out its mip chain. `layer` picks
+/// the page out of [array](AtlasFile::array) atlases, and is otherwise `0`. 8-bit pages are stored
+/// as-is, while [`Rgba16Float`](AtlasFormat::Rgba16Float) pages have the bits of their half-floats
+/// stored in 16-bit channels.
+pub fn encode_page(page: &Image, layer: u32) -> Result<Vec<u8>, ProcessedAtlasError> {
+    let format = page.texture_descriptor.format;
+    let (width, height) = (page.width(), page.height());
+    let len = width as usize * height as usize * format.pixel_size();
+
+    // Layers are laid out one after another, each along with its mip chain.
+    let layer_len = (0..page.texture_descriptor.mip_level_count)
+        .map(|level| (width >> level).max(1) as usize * (height >> level).max(1) as usize * format.pixel_size())
+        .sum::<usize>();
+
+    let (Some(atlas_format), Some(data)) = (
+        AtlasFormat::from_texture_format(format),
+        page.data
+            .as_deref()
+            .and_then(|data| data.get(layer as usize * layer_len..)?.get(..len)),
+    ) else {
+        return Err(ProcessedAtlasError::UnsupportedPage { format })
+    };
+
+    let (data, color) = match atlas_format {
+        AtlasFormat::Rgba8UnormSrgb | AtlasFormat::Rgba8Unorm => (Cow::Borrowed(data), ExtendedColorType::Rgba8),
+        AtlasFormat::R8Unorm => (Cow::Borrowed(data), ExtendedColorType::L8),
+        // The encoder takes 16-bit channels in native endianness.
+        AtlasFormat::Rgba16Float => (
+            Cow::Owned(
+                data.chunks_exact(2)
+                    .flat_map(|bits| u16::from_le_bytes([bits[0], bits[1]]).to_ne_bytes())
+                    .collect(),
+            ),
+            ExtendedColorType::Rgba16,
+        ),
+    };
+
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(&data, width, height, color)?;
+    Ok(png)
+}
+
+/// Decodes an atlas page encoded by [`encode_page`].
+pub fn decode_page(png: &[u8], format: AtlasFormat, usages: RenderAssetUsages) -> Result<Image, ImageError> {
+    let image = image::load_from_memory_with_format(png, ImageFormat::Png)?;
+    let (width, height) = (image.width(), image.height());
+    let data = match format {
+        AtlasFormat::Rgba8UnormSrgb | AtlasFormat::Rgba8Unorm => image.into_rgba8().into_raw(),
+        AtlasFormat::R8Unorm => image.into_luma8().into_raw(),
+        AtlasFormat::Rgba16Float => image
+            .into_rgba16()
+            .into_raw()
+            .into_iter()
+            .flat_map(u16::to_le_bytes)
+            .collect(),
+    };
+
+    Ok(Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        format.texture_format(),
+        usages,
+    ))
+}
+
+/// Packs `.atlas.ron` files with [`AtlasLoader`] and saves them with [`AtlasSaver`]. See the
+/// [module-level](crate::process) documentation for more information.
+pub type AtlasProcessor = LoadTransformAndSave<AtlasLoader, IdentityAssetTransformer<Atlas>, AtlasSaver>;
+
+/// Errors that may arise when saving or loading processed [`Atlas`]es.
+#[derive(Error, Debug, Display, From)]
+pub enum ProcessedAtlasError {
+    /// Error that arises when the atlas being saved lacks the labeled page image or layout of one
+    /// of its pages.
+    #[display("Page {index} doesn't have its labeled image and layout")]
+    #[from(skip)]
+    MissingPage {
+        /// The page index.
+        index: usize,
+    },
+    /// Error that arises when a sprite refers to a page that isn't in the atlas.
+    #[display("Sprite '{}' refers to a page that isn't in the atlas", sprite.display())]
+    #[from(skip)]
+    UnknownPage {
+        /// The sprite lookup key.
+        sprite: PathBuf,
+    },
+    /// Error that arises when the atlas being saved lacks the labeled asset of one of its
+    /// animations.
+    #[display("Animation '{}' doesn't have its labeled asset", animation.display())]
+    #[from(skip)]
+    MissingAnimation {
+        /// The animation lookup key.
+        animation: PathBuf,
+    },
+    /// Error that arises when the processed file ends before all of its pages are read.
+    #[display("The processed atlas file is truncated")]
+    Truncated,
+    /// Error that arises when a page image isn't in any of the [`AtlasFormat`]s, or has no data.
+    #[display("Page image in format {format:?} can't be encoded")]
+    #[from(skip)]
+    UnsupportedPage {
+        /// The page image format.
+        format: TextureFormat,
+    },
+    /// Error that arises when a page image couldn't be encoded or decoded.
+    #[display("{_0}")]
+    InvalidImage(#[from] ImageError),
+    /// Error that arises when the metadata couldn't be serialized.
+    #[display("{_0}")]
+    InvalidMetadata(#[from] ron::Error),
+    /// Error that arises when the metadata has an invalid RON syntax.
+    #[display("{_0}")]
+    InvalidFile(#[from] SpannedError),
+    /// Error that arises when an IO error occurs.
+    #[display("{_0}")]
+    Io(#[from] io::Error),
+}
+
+/// Dedicated [`AssetSaver`] to write [`Atlas`]es, along with their page images, layouts, and
+/// animations, in the form [`ProcessedAtlasLoader`] reads.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AtlasSaver;
+impl AssetSaver for AtlasSaver {
+    type Asset = Atlas;
+    type Settings = ();
+    type OutputLoader = ProcessedAtlasLoader;
+    type Error = ProcessedAtlasError;
+
+    async fn save(
+        &self,
+        writer: &mut Writer,
+        asset: SavedAsset<'_, Self::Asset>,
+        _: &Self::Settings,
+    ) -> Result<(), Self::Error> {
+        let mut pages = Vec::with_capacity(asset.pages.len());
+        let mut encoded = Vec::with_capacity(asset.pages.len());
+        let mut page_indices = HashMap::with_hasher(FixedHasher);
+
+        // Array atlases have all of their pages as layers of one texture.
+        let array = asset.get_labeled::<Image, _>("pages").map(|page| page.get());
+        for (index, (layout, ..)) in asset.pages.iter().enumerate() {
+            let (Some(page_layout), Some(page)) = (
+                asset.get_labeled::<TextureAtlasLayout, _>(format!("layout-{index}").as_str()),
+                array.or_else(|| Some(asset.get_labeled::<Image, _>(format!("page-{index}").as_str())?.get())),
+            ) else {
+                return Err(ProcessedAtlasError::MissingPage { index })
+            };
+
+            // The mip chain is regenerated when loading.
+            let layer = if array.is_some() { index as u32 } else { 0 };
+            let png = encode_page(page, layer)?;
+            let Some(format) = AtlasFormat::from_texture_format(page.texture_descriptor.format) else {
+                return Err(ProcessedAtlasError::UnsupportedPage {
+                    format: page.texture_descriptor.format,
+                })
+            };
+
+            pages.push(PackedPage {
+                size: page_layout.size.into(),
+                format,
+                usages: page.asset_usage,
+                mip_levels: page.texture_descriptor.mip_level_count,
+                efficiency: asset.efficiency.get(index).copied().unwrap_or_default(),
+                textures: page_layout
+                    .textures
+                    .iter()
+                    .map(|rect| (rect.min.x, rect.min.y, rect.max.x, rect.max.y))
+                    .collect(),
+                len: png.len() as u64,
+            });
+
+            encoded.push(png);
+            page_indices.insert(layout.id(), index);
+        }
+
+        let mut sprites = Vec::with_capacity(asset.sprites.len());
+        for (name, sprite) in &asset.sprites {
+            let Some(&page) = page_indices.get(&sprite.atlas.layout.id()) else {
+                return Err(ProcessedAtlasError::UnknownPage { sprite: name.clone() })
+            };
+
+            sprites.push(PackedSprite {
+                name: name.clone(),
+                page,
+                index: sprite.atlas.index,
+                original_size: sprite.original_size.into(),
+                trim_offset: sprite.trim_offset.into(),
+                rotated: sprite.rotated,
+                nine_slices: sprite.nine_slices.map(|cuts| (cuts.left, cuts.right, cuts.top, cuts.bottom)),
+                content_padding: sprite
+                    .nine_slices
+                    .and_then(|cuts| cuts.content_padding)
+                    .map(|padding| (padding.left, padding.right, padding.top, padding.bottom)),
+                meta: (&sprite.meta).into(),
+            });
+        }
+
+        // Keep the output stable across runs.
+        sprites.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut animations = Vec::with_capacity(asset.animations.len());
+        for name in asset.animations.keys() {
+            let Some(animation) = asset.get_labeled::<AtlasAnimation, _>(format!("animation-{}", name.display()).as_str())
+            else {
+                return Err(ProcessedAtlasError::MissingAnimation { animation: name.clone() })
+            };
+
+            animations.push(PackedAnimation {
+                name: name.clone(),
+                frames: animation
+                    .frames
+                    .iter()
+                    .map(|frame| (frame.sprite.clone(), frame.duration))
+                    .collect(),
+                mode: animation.mode,
+            });
+        }
+
+        animations.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let metadata = ron::ser::to_string(&PackedAtlas {
+            array: array.is_some(),
+            pages,
+            sprites,
+            animations,
+        })?;
+
+        writer.write_all(&(metadata.len() as u64).to_le_bytes()).await?;
+        writer.write_all(metadata.as_bytes()).await?;
+        for png in encoded {
+            writer.write_all(&png).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Dedicated [`AssetLoader`] to load [`Atlas`]es written by [`AtlasSaver`]. Adds the same labeled
+/// assets [`AtlasLoader`] does.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ProcessedAtlasLoader;
+impl AssetLoader for ProcessedAtlasLoader {
+    type Asset = Atlas;
+    type Settings = ();
+    type Error = ProcessedAtlasError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let Some((&len, data)) = bytes.split_first_chunk::<8>() else {
+            return Err(ProcessedAtlasError::Truncated)
+        };
+
+        let Some((metadata, mut data)) = data.split_at_checked(u64::from_le_bytes(len) as usize) else {
+            return Err(ProcessedAtlasError::Truncated)
+        };
+
+        let PackedAtlas {
+            array,
+            pages,
+            sprites,
+            animations,
+        } = ron::de::from_bytes(metadata)?;
+
+        let mut output_atlas = Atlas {
+            pages: Vec::with_capacity(pages.len()),
+            sprites: HashMap::with_hasher(FixedHasher),
+            efficiency: Vec::with_capacity(pages.len()),
+            animations: HashMap::with_hasher(FixedHasher),
+            directories: Vec::new(),
+        };
+
+        let mut layouts = Vec::with_capacity(pages.len());
+        let mut images = Vec::with_capacity(pages.len());
+        for (
+            index,
+            PackedPage {
+                size,
+                format,
+                usages,
+                mip_levels,
+                efficiency,
+                textures,
+                len,
+            },
+        ) in pages.into_iter().enumerate()
+        {
+            let Some((png, rest)) = data.split_at_checked(len as usize) else {
+                return Err(ProcessedAtlasError::Truncated)
+            };
+
+            data = rest;
+
+            let mut image = decode_page(png, format, usages)?;
+            generate_mipmaps(&mut image, mip_levels);
+            images.push(image);
+
+            layouts.push(
+                load_context.add_labeled_asset(format!("layout-{index}"), TextureAtlasLayout {
+                    size: size.into(),
+                    textures: textures
+                        .into_iter()
+                        .map(|(min_x, min_y, max_x, max_y)| URect::new(min_x, min_y, max_x, max_y))
+                        .collect(),
+                }),
+            );
+
+            output_atlas.efficiency.push(efficiency);
+        }
+
+        let page_handles = if array {
+            let array = load_context.add_labeled_asset("pages".into(), stack_pages(images));
+            vec![array; layouts.len()]
+        } else {
+            images
+                .into_iter()
+                .enumerate()
+                .map(|(index, image)| load_context.add_labeled_asset(format!("page-{index}"), image))
+                .collect()
+        };
+
+        output_atlas.pages.extend(layouts.into_iter().zip(page_handles));
+
+        for PackedSprite {
+            name,
+            page,
+            index,
+            original_size,
+            trim_offset,
+            rotated,
+            nine_slices,
+            content_padding,
+            meta,
+        } in sprites
+        {
+            let layer = if array { page as u32 } else { 0 };
+            let Some((layout, page)) = output_atlas.pages.get(page) else {
+                return Err(ProcessedAtlasError::UnknownPage { sprite: name })
+            };
+
+            output_atlas.sprites.insert(name, AtlasSprite {
+                atlas: TextureAtlas {
+                    layout: layout.clone_weak(),
+                    index,
+                },
+                atlas_page: page.clone_weak(),
+                layer,
+                original_size: original_size.into(),
+                trim_offset: trim_offset.into(),
+                rotated,
+                nine_slices: nine_slices.map(|(left, right, top, bottom)| NineSliceCuts {
+                    left,
+                    right,
+                    top,
+                    bottom,
+                    content_padding: content_padding.map(|(left, right, top, bottom)| ContentPadding {
+                        left,
+                        right,
+                        top,
+                        bottom,
+                    }),
+                }),
+                meta: meta.into(),
+            });
+        }
+
+        for PackedAnimation { name, frames, mode } in animations {
+            let handle = load_context.add_labeled_asset(format!("animation-{}", name.display()), AtlasAnimation {
+                frames: frames
+                    .into_iter()
+                    .map(|(sprite, duration)| AtlasFrame { sprite, duration })
+                    .collect(),
+                mode,
+            });
+
+            output_atlas.animations.insert(name, handle);
+        }
+
+        Ok(output_atlas)
+    }
+
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["atlas.bin"]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackedAtlas {
+    /// Whether the pages are layers of a single texture array, see [`AtlasFile::array`].
+    array: bool,
+    pages: Vec<PackedPage>,
+    sprites: Vec<PackedSprite>,
+    animations: Vec<PackedAnimation>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackedPage {
+    size: (u32, u32),
+    format: AtlasFormat,
+    #[serde(
+        serialize_with = "AtlasFile::serialize_usages",
+        deserialize_with = "AtlasFile::deserialize_usages"
+    )]
+    usages: RenderAssetUsages,
+    mip_levels: u32,
+    efficiency: f32,
+    textures: Vec<(u32, u32, u32, u32)>,
+    /// The length of the PNG-encoded page, in bytes.
+    len: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackedSprite {
+    name: PathBuf,
+    page: usize,
+    index: usize,
+    original_size: (u32, u32),
+    trim_offset: (u32, u32),
+    rotated: bool,
+    nine_slices: Option<(u32, u32, u32, u32)>,
+    content_padding: Option<(u32, u32, u32, u32)>,
+    #[serde(default, skip_serializing_if = "SpriteMetaFile::is_empty")]
+    meta: SpriteMetaFile,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackedAnimation {
+    name: PathBuf,
+    frames: Vec<(PathBuf, f32)>,
+    mode: AnimationMode,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use half::f16;
+
+    use super::*;
+
+    fn page(format: AtlasFormat, width: u32, height: u32, data: Vec<u8>) -> Image {
+        Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            format.texture_format(),
+            RenderAssetUsages::all(),
+        )
+    }
+
+    fn round_trip(format: AtlasFormat, width: u32, height: u32, data: Vec<u8>) {
+        let image = page(format, width, height, data);
+        let png = encode_page(&image, 0).unwrap();
+        let decoded = decode_page(&png, format, RenderAssetUsages::RENDER_WORLD).unwrap();
+
+        assert_eq!(decoded.size(), uvec2(width, height), "{format:?}");
+        assert_eq!(decoded.texture_descriptor.format, format.texture_format(), "{format:?}");
+        assert_eq!(decoded.asset_usage, RenderAssetUsages::RENDER_WORLD, "{format:?}");
+        assert_eq!(decoded.data, image.data, "{format:?}");
+    }
+
+    #[test]
+    fn round_trip_8bit() {
+        round_trip(AtlasFormat::R8Unorm, 3, 2, vec![0, 1, 127, 128, 254, 255]);
+        for format in [AtlasFormat::Rgba8UnormSrgb, AtlasFormat::Rgba8Unorm] {
+            round_trip(format, 3, 2, (0..24).map(|i| i * 11).collect());
+        }
+    }
+
+    #[test]
+    fn round_trip_half_floats() {
+        // Values outside `[0, 1]` and negative zero have to survive as exact bits.
+        let values = [0., -0., 0.5, 1., 1.5, -2., 1e-3, 65504., -65504., 3.25, 100., 0.1];
+        let data = values
+            .into_iter()
+            .cycle()
+            .take(2 * 2 * 4)
+            .flat_map(|value| f16::from_f32(value).to_le_bytes())
+            .collect();
+
+        round_trip(AtlasFormat::Rgba16Float, 2, 2, data);
+    }
+
+    #[test]
+    fn encode_full_size_layer() {
+        let first = (0..4 * 2 * 4).collect::<Vec<u8>>();
+        let second = (0..4 * 2 * 4).map(|i| 255 - i).collect::<Vec<u8>>();
+
+        let mut pages = [first.clone(), second.clone()].map(|data| page(AtlasFormat::Rgba8Unorm, 4, 2, data));
+        for page in &mut pages {
+            generate_mipmaps(page, 3);
+            assert_eq!(page.texture_descriptor.mip_level_count, 3);
+        }
+
+        // Only the full-size level is encoded, both for single pages and layers of arrays.
+        let decode = |png: Vec<u8>| decode_page(&png, AtlasFormat::Rgba8Unorm, RenderAssetUsages::all()).unwrap();
+        assert_eq!(decode(encode_page(&pages[0], 0).unwrap()).data, Some(first.clone()));
+
+        let array = stack_pages(pages);
+        assert_eq!(array.texture_descriptor.size.depth_or_array_layers, 2);
+        assert_eq!(decode(encode_page(&array, 0).unwrap()).data, Some(first));
+        assert_eq!(decode(encode_page(&array, 1).unwrap()).data, Some(second));
+
+        assert!(matches!(
+            encode_page(&array, 2),
+            Err(ProcessedAtlasError::UnsupportedPage { .. })
+        ));
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let meta = SpriteMetaFile {
+            pivot: Some((4., 8.)),
+            hitbox: vec![(0., 0.), (10., 0.), (5., 18.)],
+            tags: [("kind".into(), "door".into())].into(),
+        };
+
+        let metadata = PackedAtlas {
+            array: true,
+            pages: vec![PackedPage {
+                size: (64, 32),
+                format: AtlasFormat::Rgba16Float,
+                usages: RenderAssetUsages::RENDER_WORLD,
+                mip_levels: 3,
+                efficiency: 0.5,
+                textures: vec![(0, 0, 16, 8), (16, 0, 24, 16)],
+                len: 123,
+            }],
+            sprites: vec![PackedSprite {
+                name: "ui/panel".into(),
+                page: 0,
+                index: 1,
+                original_size: (10, 18),
+                trim_offset: (1, 2),
+                rotated: true,
+                nine_slices: Some((2, 6, 3, 12)),
+                content_padding: Some((1, 1, 2, 2)),
+                meta: meta.clone(),
+            }],
+            animations: vec![PackedAnimation {
+                name: "walk".into(),
+                frames: vec![("walk_0".into(), 0.1), ("walk_1".into(), 0.25)],
+                mode: AnimationMode::PingPong,
+            }],
+        };
+
+        let PackedAtlas {
+            array,
+            pages,
+            sprites,
+            animations,
+        } = ron::de::from_str(&ron::ser::to_string(&metadata).unwrap()).unwrap();
+
+        assert!(array);
+
+        let [page] = &pages[..] else { panic!("expected one page") };
+        assert_eq!(page.size, (64, 32));
+        assert_eq!(page.format, AtlasFormat::Rgba16Float);
+        assert_eq!(page.usages, RenderAssetUsages::RENDER_WORLD);
+        assert_eq!((page.mip_levels, page.efficiency, page.len), (3, 0.5, 123));
+        assert_eq!(page.textures, [(0, 0, 16, 8), (16, 0, 24, 16)]);
+
+        let [sprite] = &sprites[..] else { panic!("expected one sprite") };
+        assert_eq!(sprite.name, Path::new("ui/panel"));
+        assert_eq!((sprite.page, sprite.index), (0, 1));
+        assert_eq!((sprite.original_size, sprite.trim_offset), ((10, 18), (1, 2)));
+        assert!(sprite.rotated);
+        assert_eq!(sprite.nine_slices, Some((2, 6, 3, 12)));
+        assert_eq!(sprite.content_padding, Some((1, 1, 2, 2)));
+        assert_eq!(sprite.meta, meta);
+
+        let [animation] = &animations[..] else { panic!("expected one animation") };
+        assert_eq!(animation.name, Path::new("walk"));
+        assert_eq!(animation.frames, [("walk_0".into(), 0.1), ("walk_1".into(), 0.25)]);
+        assert_eq!(animation.mode, AnimationMode::PingPong);
+    }
+}
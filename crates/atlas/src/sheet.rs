@@ -0,0 +1,621 @@
+//! [`AssetLoader`] implementations for importing sprite sheets packed by external tools as
+//! [`Atlas`].
+//!
+//! Unlike [`AtlasLoader`](crate::loader::AtlasLoader), these don't pack anything themselves; they
+//! read the sprite rectangles off the JSON file exported alongside the sheet image. The resulting
+//! [`Atlas`] has the sheet image and its layout labeled `"page-0"` and `"layout-0"` respectively,
+//! so it may be used in place of one loaded from an `.atlas.ron` file. Sprites are keyed by their
+//! frame names without the file extension, e.g. `"walk/0001.png"` becomes `"walk/0001"`.
+//!
+//! - [`AsepriteLoader`] reads Aseprite's JSON export (either hash or array), with `.aseprite.json`
+//!   extension. Frame tags become [`AtlasAnimation`]s keyed by the tag name with the exported frame
+//!   durations, and slices with a 9-slice center become [`NineSliceCuts`] of the frames they apply
+//!   to; slices with a pivot likewise set their [pivot](SpriteMeta::pivot). Tags with a repeat
+//!   count of 1 play [once](AnimationMode::Once); any other repeat count loops indefinitely.
+//! - [`TexturePackerLoader`] reads TexturePacker's "JSON (Hash)" or "JSON (Array)" export, with
+//!   `.texturepacker.json` extension. Numbered sprites are gathered into animations the same way
+//!   [`AtlasFile::animations`](crate::loader::AtlasFile::animations) describes.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Formatter,
+    io,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadDirectError, ParseAssetPathError, io::Reader},
+    platform::{collections::HashMap, hash::FixedHasher},
+    prelude::*,
+};
+use derive_more::{Display, Error, From};
+use serde::{
+    Deserialize, Deserializer,
+    de::{MapAccess, SeqAccess, Visitor},
+};
+
+use crate::{
+    animation::{AnimationMode, AtlasAnimation, AtlasFrame},
+    atlas::{Atlas, AtlasSprite, NineSliceCuts, SpriteMeta},
+    loader::{AtlasFile, numbered_animations},
+};
+
+/// Errors that may arise when importing a sprite sheet as [`Atlas`].
+#[derive(Error, Debug, Display, From)]
+pub enum SheetError {
+    /// Error that arises when the sheet image couldn't be loaded.
+    #[display("Sheet image '{}' failed to load: {error}", path.display())]
+    InvalidImage {
+        /// The sheet image path.
+        path: PathBuf,
+        /// The error that arises when trying to load the image.
+        error: Box<LoadDirectError>,
+    },
+    /// Error that arises when a frame tag refers to frames that don't exist.
+    #[display("Frame tag '{name}' spans frames {from}..={to}, but there are only {len} frames")]
+    InvalidTag {
+        /// The tag name.
+        name: String,
+        /// The first frame of the tag.
+        from: usize,
+        /// The last frame of the tag.
+        to: usize,
+        /// The amount of frames in the sheet.
+        len: usize,
+    },
+    /// Error that arises when the sheet image has an invalid path string.
+    #[display("{_0}")]
+    InvalidPath(#[from] ParseAssetPathError),
+    /// Error that arises when the sheet file has an invalid JSON syntax.
+    #[display("{_0}")]
+    InvalidFile(#[from] serde_json::Error),
+    /// Error that arises when an IO error occurs.
+    #[display("{_0}")]
+    Io(#[from] io::Error),
+}
+
+/// Dedicated [`AssetLoader`] to import Aseprite JSON sheets as [`Atlas`]. See the
+/// [module-level](crate::sheet) documentation for more information.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AsepriteLoader;
+impl AssetLoader for AsepriteLoader {
+    type Asset = Atlas;
+    type Settings = ();
+    type Error = SheetError;
+
+    #[inline]
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        load_sheet(reader, load_context, true).await
+    }
+
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["aseprite.json"]
+    }
+}
+
+/// Dedicated [`AssetLoader`] to import TexturePacker JSON sheets as [`Atlas`]. See the
+/// [module-level](crate::sheet) documentation for more information.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TexturePackerLoader;
+impl AssetLoader for TexturePackerLoader {
+    type Asset = Atlas;
+    type Settings = ();
+    type Error = SheetError;
+
+    #[inline]
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        load_sheet(reader, load_context, false).await
+    }
+
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["texturepacker.json"]
+    }
+}
+
+async fn load_sheet(
+    reader: &mut dyn Reader,
+    load_context: &mut LoadContext<'_>,
+    aseprite: bool,
+) -> Result<Atlas, SheetError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).await?;
+
+    let SheetFile {
+        frames: SheetFrames(frames),
+        meta: SheetMeta {
+            image,
+            frame_tags,
+            slices,
+        },
+    } = serde_json::from_slice(&bytes)?;
+
+    drop(bytes);
+
+    let image_path = load_context.asset_path().resolve_embed(&image)?;
+    let page = match load_context.loader().immediate().load::<Image>(&image_path).await {
+        Err(error) => {
+            return Err(SheetError::InvalidImage {
+                path: image_path.path().to_owned(),
+                error: Box::new(error),
+            })
+        }
+        Ok(page) => page,
+    };
+
+    let page_size = page.get().size();
+    let page = load_context.add_loaded_labeled_asset("page-0", page);
+    let layout = load_context.get_label_handle::<TextureAtlasLayout>("layout-0");
+
+    let mut output_atlas = Atlas {
+        pages: vec![(layout.clone(), page.clone())],
+        sprites: HashMap::with_hasher(FixedHasher),
+        efficiency: Vec::new(),
+        animations: HashMap::with_hasher(FixedHasher),
+        directories: Vec::new(),
+    };
+
+    let (sprites, layout_textures) = sheet_sprites(&frames, &slices, &layout, &page);
+    let used = layout_textures
+        .iter()
+        .map(|rect| rect.width() as u64 * rect.height() as u64)
+        .sum::<u64>();
+
+    load_context.add_labeled_asset("layout-0".into(), TextureAtlasLayout {
+        size: page_size,
+        textures: layout_textures,
+    });
+
+    output_atlas
+        .efficiency
+        .push((used as f64 / (page_size.x as u64 * page_size.y as u64).max(1) as f64) as f32);
+
+    let animations = if aseprite {
+        aseprite_animations(&frames, frame_tags)?
+    } else {
+        numbered_animations(sprites.iter().map(|(name, ..)| name), AtlasFile::default_frame_duration())
+    };
+
+    output_atlas.sprites.extend(sprites);
+
+    for (path, animation) in animations {
+        let handle = load_context.add_labeled_asset(format!("animation-{}", path.display()), animation);
+        output_atlas.animations.insert(path, handle);
+    }
+
+    Ok(output_atlas)
+}
+
+/// Converts sheet frames into sprites keyed by their frame names, in the same order, along with
+/// their rectangles in the sheet image. The `i`th sprite refers to the `i`th rectangle of `layout`.
+fn sheet_sprites(
+    frames: &[(String, SheetFrame)],
+    slices: &[Slice],
+    layout: &Handle<TextureAtlasLayout>,
+    page: &Handle<Image>,
+) -> (Vec<(PathBuf, AtlasSprite)>, Vec<URect>) {
+    let mut sprites = Vec::with_capacity(frames.len());
+    let mut rects = Vec::with_capacity(frames.len());
+
+    for (i, (name, frame)) in frames.iter().enumerate() {
+        let size = uvec2(frame.frame.w, frame.frame.h);
+        let min = uvec2(frame.frame.x, frame.frame.y);
+
+        // Rotated frames have their size written as displayed, not as packed.
+        let rect = URect::from_corners(min, min + if frame.rotated { size.yx() } else { size });
+        let original_size = frame.source_size.map_or(size, |size| uvec2(size.w, size.h));
+        let trim_offset = frame.sprite_source_size.map_or(UVec2::ZERO, |rect| uvec2(rect.x, rect.y));
+
+        // The 9-slice center of the last slice key at or before this frame, if any.
+        let nine_slices = slices.iter().find_map(|slice| {
+            let key = slice.keys.iter().filter(|key| key.frame <= i).max_by_key(|key| key.frame)?;
+            let center = key.center?;
+            Some(NineSliceCuts {
+                left: key.bounds.x + center.x,
+                right: key.bounds.x + center.x + center.w,
+                top: key.bounds.y + center.y,
+                bottom: key.bounds.y + center.y + center.h,
+                content_padding: None,
+            })
+        });
+
+        // Likewise, the pivot of the last slice key at or before this frame, if any.
+        let pivot = slices.iter().find_map(|slice| {
+            let key = slice.keys.iter().filter(|key| key.frame <= i).max_by_key(|key| key.frame)?;
+            let pivot = key.pivot?;
+            Some(vec2((key.bounds.x + pivot.x) as f32, (key.bounds.y + pivot.y) as f32))
+        });
+
+        sprites.push((Path::new(name).with_extension(""), AtlasSprite {
+            atlas: TextureAtlas {
+                layout: layout.clone_weak(),
+                index: rects.len(),
+            },
+            atlas_page: page.clone_weak(),
+            layer: 0,
+            original_size,
+            trim_offset,
+            rotated: frame.rotated,
+            nine_slices,
+            meta: SpriteMeta { pivot, ..default() },
+        }));
+
+        rects.push(rect);
+    }
+
+    (sprites, rects)
+}
+
+/// Converts Aseprite frame tags into animations of the sprites of `frames`.
+fn aseprite_animations(
+    frames: &[(String, SheetFrame)],
+    frame_tags: Vec<FrameTag>,
+) -> Result<BTreeMap<PathBuf, AtlasAnimation>, SheetError> {
+    let mut animations = BTreeMap::new();
+    for FrameTag {
+        name,
+        from,
+        to,
+        direction,
+        repeat,
+    } in frame_tags
+    {
+        if from > to || to >= frames.len() {
+            return Err(SheetError::InvalidTag {
+                name,
+                from,
+                to,
+                len: frames.len(),
+            })
+        }
+
+        let mut tag_frames = (from..=to)
+            .map(|i| AtlasFrame {
+                sprite: Path::new(&frames[i].0).with_extension(""),
+                duration: frames[i]
+                    .1
+                    .duration
+                    .map_or(AtlasFile::default_frame_duration(), |ms| ms as f32 / 1000.),
+            })
+            .collect::<Vec<_>>();
+
+        if direction.ends_with("reverse") {
+            tag_frames.reverse()
+        }
+
+        animations.insert(PathBuf::from(name), AtlasAnimation {
+            frames: tag_frames,
+            mode: if repeat.as_deref() == Some("1") {
+                AnimationMode::Once
+            } else if direction.starts_with("pingpong") {
+                AnimationMode::PingPong
+            } else {
+                AnimationMode::Loop
+            },
+        });
+    }
+
+    Ok(animations)
+}
+
+#[derive(Deserialize)]
+struct SheetFile {
+    frames: SheetFrames,
+    meta: SheetMeta,
+}
+
+/// Frames in the order they appear in the file, either from a JSON object keyed by frame names or
+/// from a JSON array of frames with a `filename` field.
+struct SheetFrames(Vec<(String, SheetFrame)>);
+impl<'de> Deserialize<'de> for SheetFrames {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct Visit;
+        impl<'de> Visitor<'de> for Visit {
+            type Value = SheetFrames;
+
+            #[inline]
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                write!(formatter, "a map or an array of frames")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where A: MapAccess<'de> {
+                let mut frames = Vec::with_capacity(map.size_hint().unwrap_or(0));
+                while let Some(entry) = map.next_entry()? {
+                    frames.push(entry);
+                }
+
+                Ok(SheetFrames(frames))
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where A: SeqAccess<'de> {
+                let mut frames = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(NamedFrame { filename, frame }) = seq.next_element()? {
+                    frames.push((filename, frame));
+                }
+
+                Ok(SheetFrames(frames))
+            }
+        }
+
+        de.deserialize_any(Visit)
+    }
+}
+
+#[derive(Deserialize)]
+struct NamedFrame {
+    filename: String,
+    #[serde(flatten)]
+    frame: SheetFrame,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SheetFrame {
+    frame: SheetRect,
+    #[serde(default)]
+    rotated: bool,
+    sprite_source_size: Option<SheetRect>,
+    source_size: Option<SheetSize>,
+    duration: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SheetMeta {
+    image: String,
+    #[serde(default)]
+    frame_tags: Vec<FrameTag>,
+    #[serde(default)]
+    slices: Vec<Slice>,
+}
+
+#[derive(Deserialize)]
+struct FrameTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: String,
+    repeat: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Slice {
+    keys: Vec<SliceKey>,
+}
+
+#[derive(Deserialize)]
+struct SliceKey {
+    frame: usize,
+    bounds: SheetRect,
+    center: Option<SheetRect>,
+    pivot: Option<SheetPoint>,
+}
+
+#[derive(Deserialize, Copy, Clone)]
+struct SheetRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize, Copy, Clone)]
+struct SheetPoint {
+    x: u32,
+    y: u32,
+}
+
+#[derive(Deserialize, Copy, Clone)]
+struct SheetSize {
+    w: u32,
+    h: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASEPRITE: &str = r##"{
+        "frames": {
+            "walk_0.png": {
+                "frame": { "x": 0, "y": 0, "w": 16, "h": 16 }, "rotated": false, "trimmed": false,
+                "spriteSourceSize": { "x": 0, "y": 0, "w": 16, "h": 16 }, "sourceSize": { "w": 16, "h": 16 },
+                "duration": 100
+            },
+            "walk_1.png": {
+                "frame": { "x": 16, "y": 0, "w": 16, "h": 16 }, "rotated": false, "trimmed": false,
+                "spriteSourceSize": { "x": 0, "y": 0, "w": 16, "h": 16 }, "sourceSize": { "w": 16, "h": 16 },
+                "duration": 250
+            },
+            "walk_2.png": {
+                "frame": { "x": 32, "y": 0, "w": 16, "h": 16 }, "rotated": false, "trimmed": false,
+                "spriteSourceSize": { "x": 0, "y": 0, "w": 16, "h": 16 }, "sourceSize": { "w": 16, "h": 16 },
+                "duration": 100
+            }
+        },
+        "meta": {
+            "app": "https://www.aseprite.org/", "image": "walk.png", "format": "RGBA8888",
+            "size": { "w": 48, "h": 16 }, "scale": "1",
+            "frameTags": [
+                { "name": "forward", "from": 0, "to": 2, "direction": "forward", "color": "#000000ff" },
+                { "name": "back", "from": 0, "to": 1, "direction": "reverse", "repeat": "1", "color": "#000000ff" },
+                { "name": "bounce", "from": 1, "to": 2, "direction": "pingpong", "repeat": "3", "color": "#000000ff" },
+                { "name": "bounce_back", "from": 1, "to": 2, "direction": "pingpong_reverse", "color": "#000000ff" }
+            ],
+            "layers": [{ "name": "Layer 1", "opacity": 255, "blendMode": "normal" }],
+            "slices": [{
+                "name": "panel", "color": "#0000ffff",
+                "keys": [
+                    {
+                        "frame": 0, "bounds": { "x": 2, "y": 2, "w": 12, "h": 12 },
+                        "center": { "x": 3, "y": 3, "w": 6, "h": 6 }
+                    },
+                    {
+                        "frame": 2, "bounds": { "x": 1, "y": 1, "w": 14, "h": 14 },
+                        "center": { "x": 2, "y": 2, "w": 10, "h": 10 }, "pivot": { "x": 7, "y": 14 }
+                    }
+                ]
+            }]
+        }
+    }"##;
+
+    const TEXTURE_PACKER: &str = r#"{
+        "frames": [
+            {
+                "filename": "hero/idle_0.png",
+                "frame": { "x": 0, "y": 0, "w": 10, "h": 20 }, "rotated": true, "trimmed": true,
+                "spriteSourceSize": { "x": 2, "y": 1, "w": 10, "h": 20 }, "sourceSize": { "w": 14, "h": 24 },
+                "pivot": { "x": 0.5, "y": 0.5 }
+            },
+            {
+                "filename": "hero/idle_1.png",
+                "frame": { "x": 20, "y": 0, "w": 10, "h": 20 }, "rotated": false, "trimmed": false,
+                "spriteSourceSize": { "x": 0, "y": 0, "w": 10, "h": 20 }, "sourceSize": { "w": 10, "h": 20 },
+                "pivot": { "x": 0.5, "y": 0.5 }
+            }
+        ],
+        "meta": {
+            "app": "https://www.codeandweb.com/texturepacker", "image": "hero.png", "format": "RGBA8888",
+            "size": { "w": 64, "h": 32 }, "scale": "1"
+        }
+    }"#;
+
+    fn clip(animation: &AtlasAnimation) -> Vec<(&str, f32)> {
+        animation
+            .frames
+            .iter()
+            .map(|frame| (frame.sprite.to_str().unwrap(), frame.duration))
+            .collect()
+    }
+
+    #[test]
+    fn aseprite_hash() {
+        let SheetFile {
+            frames: SheetFrames(frames),
+            meta: SheetMeta {
+                image,
+                frame_tags,
+                slices,
+            },
+        } = serde_json::from_str(ASEPRITE).unwrap();
+
+        assert_eq!(image, "walk.png");
+
+        let (sprites, rects) = sheet_sprites(&frames, &slices, &default(), &default());
+        let names = sprites.iter().map(|(name, ..)| name.to_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(names, ["walk_0", "walk_1", "walk_2"]);
+        assert_eq!(rects, [
+            URect::new(0, 0, 16, 16),
+            URect::new(16, 0, 32, 16),
+            URect::new(32, 0, 48, 16)
+        ]);
+
+        // Slice keys apply from their frame onwards, with the center relative to the bounds.
+        let cuts = |left, right, top, bottom| NineSliceCuts {
+            left,
+            right,
+            top,
+            bottom,
+            content_padding: None,
+        };
+
+        assert_eq!(sprites[0].1.nine_slices, Some(cuts(5, 11, 5, 11)));
+        assert_eq!(sprites[1].1.nine_slices, Some(cuts(5, 11, 5, 11)));
+        assert_eq!(sprites[2].1.nine_slices, Some(cuts(3, 13, 3, 13)));
+        assert_eq!(sprites[1].1.meta.pivot, None);
+        assert_eq!(sprites[2].1.meta.pivot, Some(vec2(8., 15.)));
+
+        let animations = aseprite_animations(&frames, frame_tags).unwrap();
+
+        let forward = &animations[Path::new("forward")];
+        assert_eq!(clip(forward), [("walk_0", 0.1), ("walk_1", 0.25), ("walk_2", 0.1)]);
+        assert_eq!(forward.mode, AnimationMode::Loop);
+
+        let back = &animations[Path::new("back")];
+        assert_eq!(clip(back), [("walk_1", 0.25), ("walk_0", 0.1)]);
+        assert_eq!(back.mode, AnimationMode::Once);
+
+        // Repeat counts other than 1 loop indefinitely.
+        let bounce = &animations[Path::new("bounce")];
+        assert_eq!(clip(bounce), [("walk_1", 0.25), ("walk_2", 0.1)]);
+        assert_eq!(bounce.mode, AnimationMode::PingPong);
+
+        let bounce_back = &animations[Path::new("bounce_back")];
+        assert_eq!(clip(bounce_back), [("walk_2", 0.1), ("walk_1", 0.25)]);
+        assert_eq!(bounce_back.mode, AnimationMode::PingPong);
+    }
+
+    #[test]
+    fn aseprite_invalid_tag() {
+        let SheetFile {
+            frames: SheetFrames(frames),
+            ..
+        } = serde_json::from_str(ASEPRITE).unwrap();
+
+        let tag = FrameTag {
+            name: "overflow".into(),
+            from: 1,
+            to: 3,
+            direction: String::new(),
+            repeat: None,
+        };
+
+        assert!(matches!(
+            aseprite_animations(&frames, vec![tag]),
+            Err(SheetError::InvalidTag {
+                from: 1,
+                to: 3,
+                len: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn texture_packer_array() {
+        let SheetFile {
+            frames: SheetFrames(frames),
+            meta: SheetMeta { image, slices, .. },
+        } = serde_json::from_str(TEXTURE_PACKER).unwrap();
+
+        assert_eq!(image, "hero.png");
+
+        let (sprites, rects) = sheet_sprites(&frames, &slices, &default(), &default());
+        let names = sprites.iter().map(|(name, ..)| name.to_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(names, ["hero/idle_0", "hero/idle_1"]);
+
+        // Rotated frames are packed with their displayed width and height swapped.
+        let (_, rotated) = &sprites[0];
+        assert_eq!(rects[0], URect::new(0, 0, 20, 10));
+        assert!(rotated.rotated);
+        assert_eq!(rotated.original_size, uvec2(14, 24));
+        assert_eq!(rotated.trim_offset, uvec2(2, 1));
+        assert_eq!(rotated.atlas.index, 0);
+
+        let (_, upright) = &sprites[1];
+        assert_eq!(rects[1], URect::new(20, 0, 30, 20));
+        assert!(!upright.rotated);
+        assert_eq!(upright.original_size, uvec2(10, 20));
+        assert_eq!(upright.trim_offset, UVec2::ZERO);
+        assert_eq!(upright.atlas.index, 1);
+        assert_eq!(upright.nine_slices, None);
+
+        let animations = numbered_animations(sprites.iter().map(|(name, ..)| name), AtlasFile::default_frame_duration());
+        let idle = &animations[Path::new("hero/idle")];
+        assert_eq!(clip(idle), [("hero/idle_0", 0.1), ("hero/idle_1", 0.1)]);
+    }
+}
@@ -0,0 +1,580 @@
+//! Provides [`Tilemap`], a chunked grid of atlas sprites drawn by [`TilemapDrawer`].
+//!
+//! Spawning one entity per tile quickly becomes a bottleneck for visibility checking and
+//! extraction, so instead a [`Tilemap`] stores its tiles in fixed-size chunks and spawns one child
+//! [`TilemapChunk`] entity per chunk. Each chunk has its own [`Aabb`] so that off-screen chunks are
+//! culled, and its vertices are cached in the render world and only regenerated when the chunk (or
+//! the tilemap's palette, atlas, or animation frames it uses) changes.
+//!
+//! Tiles reference [`TileSprite`]s in the tilemap's palette by index, which in turn reference
+//! [`Atlas`] sprites by path. Palette entries with multiple frames are animated.
+
+use std::{
+    any::type_name,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    ecs::{
+        query::QueryItem,
+        system::{
+            SystemParamItem,
+            lifetimeless::{Read, SQuery},
+        },
+    },
+    math::Affine3A,
+    platform::collections::HashMap,
+    prelude::*,
+    reflect::{TypePath, utility::GenericTypePathCell},
+    render::primitives::Aabb,
+};
+use hephae_render::{
+    attribute::{ColorAttrib, HasAttrib, Pos2dAttrib, Shaper, UvAttrib},
+    drawer::{DrawBy, Drawer, DrawerExtract, VertexQueuer},
+    vertex::Vertex,
+};
+
+use crate::atlas::{Atlas, AtlasCaches, AtlasEntries};
+
+/// A chunked grid of tiles. Tile `(0, 0)` has its bottom-left corner at the entity's origin.
+///
+/// Chunks are spawned as [`TilemapChunk`] children of this entity, drawn by the [`Drawer`] passed
+/// to [`new`](Self::new). Despawning the tilemap despawns its chunks.
+#[derive(Component, Clone, Debug)]
+#[require(Transform, Visibility)]
+pub struct Tilemap {
+    atlas: Handle<Atlas>,
+    tile_size: Vec2,
+    chunk_size: UVec2,
+    palette: Vec<TileSprite>,
+    chunks: HashMap<IVec2, TileChunk>,
+    removed: Vec<Entity>,
+    insert_drawer: fn(&mut EntityCommands),
+    revision: u32,
+    frames: Vec<usize>,
+    frame_revision: u32,
+}
+
+impl Tilemap {
+    /// The default amount of tiles per chunk in each axis.
+    pub const DEFAULT_CHUNK_SIZE: UVec2 = UVec2::splat(32);
+
+    /// Creates an empty tilemap whose chunks are drawn by `D`, usually [`TilemapDrawer`].
+    #[inline]
+    pub fn new<D: Drawer>(atlas: Handle<Atlas>, tile_size: impl Into<Vec2>) -> Self {
+        Self {
+            atlas,
+            tile_size: tile_size.into(),
+            chunk_size: Self::DEFAULT_CHUNK_SIZE,
+            palette: Vec::new(),
+            chunks: HashMap::default(),
+            removed: Vec::new(),
+            insert_drawer: |commands| {
+                commands.insert(DrawBy::<D>::new());
+            },
+            revision: 0,
+            frames: Vec::new(),
+            frame_revision: 0,
+        }
+    }
+
+    /// Sets the amount of tiles per chunk in each axis. Existing tiles are discarded.
+    #[inline]
+    pub fn with_chunk_size(mut self, chunk_size: UVec2) -> Self {
+        self.clear();
+        self.chunk_size = chunk_size.max(UVec2::ONE);
+        self
+    }
+
+    /// Returns the atlas tile sprites are looked up from.
+    #[inline]
+    pub fn atlas(&self) -> &Handle<Atlas> {
+        &self.atlas
+    }
+
+    /// Returns the size of each tile in local units.
+    #[inline]
+    pub fn tile_size(&self) -> Vec2 {
+        self.tile_size
+    }
+
+    /// Sets the size of each tile in local units.
+    #[inline]
+    pub fn set_tile_size(&mut self, tile_size: impl Into<Vec2>) {
+        self.tile_size = tile_size.into();
+        self.revision = self.revision.wrapping_add(1);
+
+        // Chunk transforms and bounds depend on the tile size.
+        for chunk in self.chunks.values_mut() {
+            chunk.proxy_dirty = true
+        }
+    }
+
+    /// Returns the amount of tiles per chunk in each axis.
+    #[inline]
+    pub fn chunk_size(&self) -> UVec2 {
+        self.chunk_size
+    }
+
+    /// Returns the sprites [`Tile::sprite`] indexes into.
+    #[inline]
+    pub fn palette(&self) -> &[TileSprite] {
+        &self.palette
+    }
+
+    /// Adds a sprite to the palette, returning its index for use in [`Tile::sprite`].
+    #[inline]
+    pub fn add_sprite(&mut self, sprite: TileSprite) -> u32 {
+        self.palette.push(sprite);
+        self.revision = self.revision.wrapping_add(1);
+        (self.palette.len() - 1) as u32
+    }
+
+    /// Replaces a sprite in the palette, returning the old one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    #[inline]
+    pub fn set_sprite(&mut self, index: u32, sprite: TileSprite) -> TileSprite {
+        self.revision = self.revision.wrapping_add(1);
+        std::mem::replace(&mut self.palette[index as usize], sprite)
+    }
+
+    /// Splits a tile position into its chunk position and the tile index within the chunk.
+    #[inline]
+    pub fn chunk_of(&self, pos: IVec2) -> (IVec2, usize) {
+        let size = self.chunk_size.as_ivec2();
+        let chunk = pos.div_euclid(size);
+        let local = pos.rem_euclid(size);
+
+        (chunk, (local.y * size.x + local.x) as usize)
+    }
+
+    /// Returns the tile at `pos`, if any.
+    #[inline]
+    pub fn get(&self, pos: IVec2) -> Option<Tile> {
+        let (chunk, index) = self.chunk_of(pos);
+        self.chunks.get(&chunk)?.tiles[index]
+    }
+
+    /// Sets or removes the tile at `pos`, returning the previous tile.
+    pub fn set(&mut self, pos: IVec2, tile: impl Into<Option<Tile>>) -> Option<Tile> {
+        let tile = tile.into();
+        let (chunk_pos, index) = self.chunk_of(pos);
+        let len = self.chunk_size.element_product() as usize;
+
+        if tile.is_none() && !self.chunks.contains_key(&chunk_pos) {
+            return None
+        }
+
+        let chunk = self.chunks.entry(chunk_pos).or_insert_with(|| TileChunk {
+            tiles: vec![None; len].into_boxed_slice(),
+            count: 0,
+            revision: 0,
+            proxy: None,
+            proxy_dirty: true,
+        });
+
+        let old = std::mem::replace(&mut chunk.tiles[index], tile);
+        if old != tile {
+            chunk.count = chunk.count + tile.is_some() as usize - old.is_some() as usize;
+            chunk.revision = chunk.revision.wrapping_add(1);
+
+            if chunk.count == 0 {
+                self.removed
+                    .extend(self.chunks.remove(&chunk_pos).and_then(|chunk| chunk.proxy));
+            }
+        }
+
+        old
+    }
+
+    /// Fills a rectangle of tiles, inclusive of both corners.
+    pub fn fill(&mut self, from: IVec2, to: IVec2, tile: impl Into<Option<Tile>>) {
+        let tile = tile.into();
+        let (min, max) = (from.min(to), from.max(to));
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                self.set(ivec2(x, y), tile);
+            }
+        }
+    }
+
+    /// Removes all tiles.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.removed.extend(self.chunks.drain().filter_map(|(.., chunk)| chunk.proxy));
+    }
+
+    /// Returns the chunk at the given chunk position, if any.
+    #[inline]
+    pub fn chunk(&self, chunk_pos: IVec2) -> Option<&TileChunk> {
+        self.chunks.get(&chunk_pos)
+    }
+
+    /// Iterates over all chunks and their chunk positions.
+    #[inline]
+    pub fn chunks(&self) -> impl Iterator<Item = (IVec2, &TileChunk)> {
+        self.chunks.iter().map(|(&pos, chunk)| (pos, chunk))
+    }
+
+    /// The local-space size of a chunk.
+    #[inline]
+    pub fn chunk_extent(&self) -> Vec2 {
+        self.chunk_size.as_vec2() * self.tile_size
+    }
+}
+
+impl AtlasEntries for Tilemap {
+    #[inline]
+    fn entries(&self) -> impl Iterator<Item = (AssetId<Atlas>, &Path)> {
+        let atlas = self.atlas.id();
+        self.palette
+            .iter()
+            .flat_map(|sprite| &sprite.frames)
+            .map(move |path| (atlas, path.as_path()))
+    }
+}
+
+/// A [`Tilemap`] palette entry.
+#[derive(Reflect, Clone, Debug)]
+#[reflect(Debug)]
+pub struct TileSprite {
+    /// Sprite paths in the [`Atlas`]. More than one path makes an animated tile.
+    pub frames: Vec<PathBuf>,
+    /// Frames per second for animated tiles.
+    pub frame_rate: f32,
+}
+
+impl TileSprite {
+    /// Creates a static tile sprite.
+    #[inline]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            frames: vec![path.into()],
+            frame_rate: 0.,
+        }
+    }
+
+    /// Creates an animated tile sprite.
+    #[inline]
+    pub fn animated(frames: impl IntoIterator<Item = impl Into<PathBuf>>, frame_rate: f32) -> Self {
+        Self {
+            frames: frames.into_iter().map(Into::into).collect(),
+            frame_rate,
+        }
+    }
+
+    /// Whether this sprite cycles through more than one frame.
+    #[inline]
+    pub fn is_animated(&self) -> bool {
+        self.frames.len() > 1 && self.frame_rate > 0.
+    }
+}
+
+/// A single tile in a [`Tilemap`].
+#[derive(Reflect, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[reflect(Debug, Default, PartialEq)]
+pub struct Tile {
+    /// Index into the [`Tilemap::palette`].
+    pub sprite: u32,
+    /// Mirrors the sprite horizontally.
+    pub flip_x: bool,
+    /// Mirrors the sprite vertically.
+    pub flip_y: bool,
+    /// Rotates the sprite counter-clockwise by this many quarter turns, after flipping.
+    pub rotation: u8,
+}
+
+impl Tile {
+    /// Creates an unflipped, unrotated tile.
+    #[inline]
+    pub const fn new(sprite: u32) -> Self {
+        Self {
+            sprite,
+            flip_x: false,
+            flip_y: false,
+            rotation: 0,
+        }
+    }
+
+    /// Sets the flip flags.
+    #[inline]
+    pub const fn with_flip(mut self, flip_x: bool, flip_y: bool) -> Self {
+        self.flip_x = flip_x;
+        self.flip_y = flip_y;
+        self
+    }
+
+    /// Sets the counter-clockwise quarter turns.
+    #[inline]
+    pub const fn with_rotation(mut self, rotation: u8) -> Self {
+        self.rotation = rotation;
+        self
+    }
+}
+
+/// A fixed-size chunk of tiles in a [`Tilemap`], stored row-major from the bottom-left.
+#[derive(Clone, Debug)]
+pub struct TileChunk {
+    tiles: Box<[Option<Tile>]>,
+    count: usize,
+    revision: u32,
+    proxy: Option<Entity>,
+    proxy_dirty: bool,
+}
+
+impl TileChunk {
+    /// Returns the tiles of this chunk.
+    #[inline]
+    pub fn tiles(&self) -> &[Option<Tile>] {
+        &self.tiles
+    }
+
+    /// Returns the [`TilemapChunk`] entity of this chunk, if it's been spawned already.
+    #[inline]
+    pub fn entity(&self) -> Option<Entity> {
+        self.proxy
+    }
+}
+
+/// Child entity of a [`Tilemap`] representing one of its chunks, used for culling and drawing.
+#[derive(Component, Copy, Clone, Debug)]
+#[require(Transform, Visibility, Aabb)]
+pub struct TilemapChunk {
+    /// The [`Tilemap`] entity.
+    pub tilemap: Entity,
+    /// The chunk position; multiply by [`Tilemap::chunk_extent`] to get its local position.
+    pub position: IVec2,
+}
+
+/// Advances the current frame of animated [`TileSprite`]s.
+pub fn animate_tilemaps(time: Res<Time>, mut tilemaps: Query<&mut Tilemap>) {
+    let elapsed = time.elapsed_secs_f64();
+    for mut tilemap in &mut tilemaps {
+        // Bypass change detection; frames changing shouldn't count as the tilemap changing.
+        let tilemap = tilemap.bypass_change_detection();
+
+        let mut changed = tilemap.frames.len() != tilemap.palette.len();
+        tilemap.frames.resize(tilemap.palette.len(), 0);
+
+        let mut offset = 0;
+        for (sprite, frame) in tilemap.palette.iter().zip(&mut tilemap.frames) {
+            let current = match sprite.is_animated() {
+                true => offset + (elapsed * sprite.frame_rate as f64) as usize % sprite.frames.len(),
+                false => offset,
+            };
+
+            changed |= *frame != current;
+            *frame = current;
+            offset += sprite.frames.len();
+        }
+
+        if changed {
+            tilemap.frame_revision = tilemap.frame_revision.wrapping_add(1)
+        }
+    }
+}
+
+/// Spawns, updates, and despawns [`TilemapChunk`]s of each [`Tilemap`].
+pub fn sync_tilemap_chunks(mut commands: Commands, mut tilemaps: Query<(Entity, &mut Tilemap), Changed<Tilemap>>) {
+    for (e, mut tilemap) in &mut tilemaps {
+        let tilemap = tilemap.bypass_change_detection();
+        for proxy in tilemap.removed.drain(..) {
+            if let Ok(mut proxy) = commands.get_entity(proxy) {
+                proxy.despawn();
+            }
+        }
+
+        let extent = tilemap.chunk_extent();
+        let aabb = Aabb::from_min_max(Vec3::ZERO, extent.extend(0.));
+
+        for (&position, chunk) in &mut tilemap.chunks {
+            if !std::mem::take(&mut chunk.proxy_dirty) {
+                continue
+            }
+
+            let components = (
+                TilemapChunk { tilemap: e, position },
+                Transform::from_translation((position.as_vec2() * extent).extend(0.)),
+                aabb,
+            );
+
+            match chunk.proxy {
+                Some(proxy) => {
+                    commands.entity(proxy).insert(components);
+                }
+                None => {
+                    let mut proxy = commands.spawn((components, ChildOf(e)));
+                    (tilemap.insert_drawer)(&mut proxy);
+                    chunk.proxy = Some(proxy.id());
+                }
+            }
+        }
+    }
+}
+
+/// Invalidates chunk vertices of [`Tilemap`]s whose [`AtlasCaches`] changed.
+pub fn invalidate_tilemaps(mut tilemaps: Query<&mut Tilemap, Changed<AtlasCaches>>) {
+    for mut tilemap in &mut tilemaps {
+        let tilemap = tilemap.bypass_change_detection();
+        tilemap.revision = tilemap.revision.wrapping_add(1)
+    }
+}
+
+/// [`Drawer`] for [`TilemapChunk`]s.
+///
+/// Vertices are cached across frames and only regenerated when the chunk's tiles, the tilemap's
+/// palette or atlas sprites, the chunk's [`GlobalTransform`], or (for chunks containing animated
+/// tiles) the animation frames change. Unchanged chunks only copy their cached vertices.
+#[derive(Component)]
+pub struct TilemapDrawer<T: Vertex> {
+    vertices: Vec<T>,
+    batches: Vec<(AssetId<Image>, u32, u32)>,
+    layer: f32,
+    key: Option<(u32, u32, u32, Affine3A)>,
+    animated: bool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Vertex> Default for TilemapDrawer<T> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            vertices: Vec::new(),
+            batches: Vec::new(),
+            layer: 0.,
+            key: None,
+            animated: false,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Vertex> TypePath for TilemapDrawer<T> {
+    fn type_path() -> &'static str {
+        static CELL: GenericTypePathCell = GenericTypePathCell::new();
+        CELL.get_or_insert::<Self, _>(|| format!("hephae_atlas::tilemap::TilemapDrawer<{}>", type_name::<T>()))
+    }
+
+    fn short_type_path() -> &'static str {
+        static CELL: GenericTypePathCell = GenericTypePathCell::new();
+        CELL.get_or_insert::<Self, _>(|| format!("TilemapDrawer<{}>", type_name::<T>()))
+    }
+}
+
+impl<T> Drawer for TilemapDrawer<T>
+where T: Vertex<PipelineKey: From<AssetId<Image>>> + HasAttrib<Pos2dAttrib> + HasAttrib<UvAttrib> + HasAttrib<ColorAttrib>
+{
+    type Vertex = T;
+
+    type ExtractParam = SQuery<(Read<Tilemap>, Read<AtlasCaches>)>;
+    type ExtractData = (Read<TilemapChunk>, Read<GlobalTransform>);
+    type ExtractFilter = ();
+
+    type DrawParam = ();
+
+    fn extract(
+        mut drawer: DrawerExtract<Self>,
+        tilemaps: &SystemParamItem<Self::ExtractParam>,
+        (&TilemapChunk { tilemap, position }, transform): QueryItem<Self::ExtractData>,
+    ) {
+        let Ok((tilemap, caches)) = tilemaps.get(tilemap) else { return };
+        let Some(chunk) = tilemap.chunks.get(&position) else { return };
+
+        let drawer = drawer.get_or_default();
+        let affine = transform.affine();
+        drawer.layer = transform.translation().z;
+
+        let frame_revision = if drawer.animated { tilemap.frame_revision } else { 0 };
+        let key = (chunk.revision, tilemap.revision, frame_revision, affine);
+        if drawer.key == Some(key) {
+            return
+        }
+
+        let mut quads = Vec::with_capacity(chunk.count);
+        let mut animated = false;
+
+        let width = tilemap.chunk_size.x as usize;
+        for (i, tile) in chunk.tiles.iter().enumerate() {
+            let Some(tile) = tile else { continue };
+            let Some(sprite) = tilemap.palette.get(tile.sprite as usize) else { continue };
+            let Some(&info) = tilemap.frames.get(tile.sprite as usize).and_then(|&frame| caches.get(frame)) else {
+                continue
+            };
+
+            if info.rect.is_empty() {
+                continue
+            }
+
+            animated |= sprite.is_animated();
+
+            let (mut s, mut s2, mut t, mut t2) = (0., 1., 0., 1.);
+            if tile.flip_x {
+                std::mem::swap(&mut s, &mut s2)
+            }
+
+            if tile.flip_y {
+                std::mem::swap(&mut t, &mut t2)
+            }
+
+            // Bottom-left, bottom-right, top-right, top-left; rotating the texture counter-clockwise
+            // means each corner samples from the corner preceding it.
+            let mut uvs = [vec2(s, t), vec2(s2, t), vec2(s2, t2), vec2(s, t2)].map(|point| info.uv(point));
+            uvs.rotate_right(tile.rotation as usize % 4);
+
+            let local = vec2((i % width) as f32, (i / width) as f32) * tilemap.tile_size;
+
+            let mut shaper = Shaper::<T, 4>::new();
+            shaper
+                .with_transform(affine)
+                .rect_bl(local, tilemap.tile_size)
+                .uv::<0>(uvs)
+                .color::<0>(LinearRgba::WHITE);
+
+            quads.push((info.page, shaper));
+        }
+
+        quads.sort_by_key(|&(page, ..)| page);
+
+        drawer.vertices.clear();
+        drawer.batches.clear();
+        for (page, shaper) in quads {
+            let start = drawer.vertices.len() as u32;
+            match drawer.batches.last_mut() {
+                Some((last, .., end)) if *last == page => *end = start + 4,
+                _ => drawer.batches.push((page, start, start + 4)),
+            }
+
+            drawer.vertices.extend(shaper.into_vertices());
+        }
+
+        drawer.animated = animated;
+        drawer.key = Some((
+            chunk.revision,
+            tilemap.revision,
+            if animated { tilemap.frame_revision } else { 0 },
+            affine,
+        ));
+    }
+
+    fn draw(&self, _: &SystemParamItem<Self::DrawParam>, queuer: &impl VertexQueuer<Vertex = Self::Vertex>) {
+        if self.vertices.is_empty() {
+            return
+        }
+
+        let offset = queuer.data(self.vertices.as_slice());
+        let mut indices = Vec::new();
+        for &(page, start, end) in &self.batches {
+            indices.clear();
+            indices.extend((start..end).step_by(4).flat_map(|i| {
+                let o = offset + i;
+                [o, o + 1, o + 2, o + 2, o + 3, o]
+            }));
+
+            queuer.request(self.layer, page.into(), indices.as_slice())
+        }
+    }
+}
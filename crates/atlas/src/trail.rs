@@ -0,0 +1,643 @@
+//! Provides [`Trail`], a ribbon that follows its entity's [`GlobalTransform`] and fades out over
+//! time, drawn by [`TrailDrawer`] with sprites from an [`Atlas`].
+//!
+//! Spawn an entity with [`Trail`], [`TrailSprites`], and
+//! [`DrawBy<TrailDrawer<T>>`](hephae_render::drawer::DrawBy), where `T` is your [`Vertex`], and
+//! register [`TrailDrawer<T>`] as one of the drawers in `RendererPlugin`. Trail points are recorded
+//! in world space, so only the head of the trail follows the entity.
+//!
+//! The body sprite is mapped with its bottom edge towards the tail and its top edge towards the
+//! head, and its horizontal center along the trail's center line. Cap sprites are mapped with
+//! their bottom edge attached to the trail, pointing outwards.
+
+use std::{
+    any::type_name,
+    collections::VecDeque,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    ecs::{
+        query::QueryItem,
+        system::{SystemParamItem, lifetimeless::Read},
+    },
+    prelude::*,
+    reflect::{TypePath, utility::GenericTypePathCell},
+    render::primitives::Aabb,
+};
+use hephae_render::{
+    attribute::{ColorAttrib, HasAttrib, Pos2dAttrib, Shaper, UvAttrib},
+    curve::Keyframes,
+    drawer::{Drawer, DrawerExtract, VertexQueuer},
+    vertex::Vertex,
+};
+
+use crate::atlas::{Atlas, AtlasCaches, AtlasEntries, AtlasInfo};
+
+/// Records points from its entity's [`GlobalTransform`] into [`TrailPoints`].
+///
+/// Curves are sampled over the normalized length of the trail, where `0` is the tail and `1` is the
+/// head.
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component, Default, Debug)]
+#[require(TrailPoints, Transform, Visibility, Aabb)]
+pub struct Trail {
+    /// Whether new points are recorded. Existing points keep aging regardless, so the trail
+    /// retracts towards its head once this is `false`.
+    pub emitting: bool,
+    /// How long each point lives, in seconds.
+    pub lifetime: f32,
+    /// The minimum distance the head has to travel before a new point is recorded, in world
+    /// units.
+    pub min_distance: f32,
+    /// The maximum amount of recorded points. Once exceeded, the oldest points are removed
+    /// regardless of their age.
+    pub max_points: usize,
+    /// The full width of the trail.
+    pub width: Keyframes<f32>,
+    /// The color along the trail's center line.
+    pub color: Keyframes<LinearRgba>,
+    /// The color along the trail's edges. If `None`, [`color`](Self::color) is used.
+    pub edge_color: Option<Keyframes<LinearRgba>>,
+    /// How the body sprite is mapped along the trail.
+    pub uv_mode: TrailUvMode,
+}
+
+impl Default for Trail {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            emitting: true,
+            lifetime: 0.5,
+            min_distance: 4.,
+            max_points: 256,
+            width: Keyframes::constant(16.),
+            color: Keyframes::constant(LinearRgba::WHITE),
+            edge_color: None,
+            uv_mode: TrailUvMode::Stretch,
+        }
+    }
+}
+
+/// How the body sprite of a [`Trail`] is mapped along its length.
+#[derive(Reflect, Copy, Clone, Debug, Default)]
+#[reflect(Default, Debug)]
+pub enum TrailUvMode {
+    /// The body sprite is stretched across the whole trail.
+    #[default]
+    Stretch,
+    /// The body sprite repeats every `length` world units of traveled distance, so that it stays
+    /// in place as the trail grows and retracts.
+    Tile {
+        /// The length of one repetition, in world units.
+        length: f32,
+    },
+}
+
+/// Atlas sprites a [`Trail`] is drawn with. Caps are optional, and are scaled to the width of the
+/// trail at their end while preserving their aspect ratio.
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Debug)]
+pub struct TrailSprites {
+    /// Handle to the [`Atlas`].
+    pub atlas: Handle<Atlas>,
+    /// The body sprite, stretched or tiled along the trail.
+    pub body: PathBuf,
+    /// The sprite drawn past the head of the trail.
+    pub head: Option<PathBuf>,
+    /// The sprite drawn past the tail of the trail.
+    pub tail: Option<PathBuf>,
+}
+
+impl TrailSprites {
+    /// Creates a new [`TrailSprites`] without caps.
+    #[inline]
+    pub fn new(atlas: Handle<Atlas>, body: impl Into<PathBuf>) -> Self {
+        Self {
+            atlas,
+            body: body.into(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    /// Sets the head cap sprite.
+    #[inline]
+    pub fn with_head(mut self, head: impl Into<PathBuf>) -> Self {
+        self.head = Some(head.into());
+        self
+    }
+
+    /// Sets the tail cap sprite.
+    #[inline]
+    pub fn with_tail(mut self, tail: impl Into<PathBuf>) -> Self {
+        self.tail = Some(tail.into());
+        self
+    }
+
+    /// Returns the body, head, and tail sprite information from `caches`, in that order.
+    #[inline]
+    pub fn get(&self, caches: &AtlasCaches) -> (Option<AtlasInfo>, Option<AtlasInfo>, Option<AtlasInfo>) {
+        let mut caches = caches.iter().copied();
+        let body = caches.next();
+        let head = self.head.as_ref().and_then(|_| caches.next());
+        let tail = self.tail.as_ref().and_then(|_| caches.next());
+
+        (body, head, tail)
+    }
+}
+
+impl AtlasEntries for TrailSprites {
+    #[inline]
+    fn entries(&self) -> impl Iterator<Item = (AssetId<Atlas>, &Path)> {
+        let atlas = self.atlas.id();
+        [Some(&self.body), self.head.as_ref(), self.tail.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(move |path| (atlas, path.as_path()))
+    }
+}
+
+/// A recorded point of a [`Trail`].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TrailPoint {
+    /// World-space position.
+    pub position: Vec2,
+    /// The total distance the trail's head has traveled when this point was recorded.
+    pub distance: f32,
+    /// How long this point has been alive, in seconds.
+    pub age: f32,
+}
+
+impl TrailPoint {
+    #[inline]
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Self {
+            position: self.position + (to.position - self.position) * t,
+            distance: self.distance + (to.distance - self.distance) * t,
+            age: self.age + (to.age - self.age) * t,
+        }
+    }
+}
+
+/// Recorded points of a [`Trail`].
+#[derive(Component, Clone, Debug, Default)]
+pub struct TrailPoints {
+    points: VecDeque<TrailPoint>,
+    head: Option<TrailPoint>,
+    tail: Option<(TrailPoint, f32)>,
+}
+
+impl TrailPoints {
+    /// Returns the recorded points, oldest first. This doesn't include the interpolated tail nor
+    /// the head; see [`path`](Self::path).
+    #[inline]
+    pub fn points(&self) -> &VecDeque<TrailPoint> {
+        &self.points
+    }
+
+    /// Removes all points.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.points.clear();
+        self.head = None;
+        self.tail = None;
+    }
+
+    /// Advances the trail by `delta` seconds, moving its head to `position`.
+    ///
+    /// Expired points are pruned, but the last pruned point is kept as the tail, which slides
+    /// towards the next point as it ages so that the trail retracts smoothly instead of losing a
+    /// whole segment at once.
+    pub fn step(&mut self, trail: &Trail, position: Vec2, delta: f32) {
+        for point in self.points.iter_mut().chain(self.tail.as_mut().map(|(tail, ..)| tail)) {
+            point.age += delta;
+        }
+
+        let last = self.points.back().or(self.head.as_ref()).copied();
+        let head = TrailPoint {
+            position,
+            distance: last.map_or(0., |last| last.distance + last.position.distance(position)),
+            age: 0.,
+        };
+
+        self.head = Some(head);
+        if trail.emitting &&
+            self.points
+                .back()
+                .is_none_or(|last| last.position.distance_squared(position) >= trail.min_distance * trail.min_distance)
+        {
+            self.points.push_back(head);
+        }
+
+        while let Some(&front) = self.points.front() {
+            if front.age < trail.lifetime && self.points.len() <= trail.max_points {
+                break
+            }
+
+            self.points.pop_front();
+
+            let next = self.points.front().unwrap_or(&head);
+            self.tail = Some((front, (front.age - next.age).max(0.)));
+        }
+
+        if self.tail.is_some() && self.tail(trail.lifetime).is_none() {
+            self.tail = None
+        }
+    }
+
+    /// Returns the interpolated tail, if the last pruned point hasn't fully retracted yet.
+    pub fn tail(&self, lifetime: f32) -> Option<TrailPoint> {
+        let (tail, gap) = self.tail?;
+        let next = self.points.front().or(self.head.as_ref())?;
+        if gap <= 0. {
+            return None
+        }
+
+        let t = ((tail.age - lifetime) / gap).clamp(0., 1.);
+        (t < 1.).then(|| tail.lerp(*next, t))
+    }
+
+    /// Returns the full path of the trail from its tail to its head.
+    pub fn path(&self, lifetime: f32) -> impl Iterator<Item = TrailPoint> + '_ {
+        let head = self.head.filter(|head| {
+            self.points
+                .back()
+                .is_none_or(|last| head.distance - last.distance > f32::EPSILON)
+        });
+
+        self.tail(lifetime).into_iter().chain(self.points.iter().copied()).chain(head)
+    }
+}
+
+/// Steps every [`Trail`] and updates their [`Aabb`] for visibility checks.
+pub fn update_trails(
+    time: Res<Time>,
+    mut trails: Query<(&Trail, &mut TrailPoints, &GlobalTransform, &mut Aabb, Option<&AtlasCaches>)>,
+) {
+    let delta = time.delta_secs();
+    trails
+        .par_iter_mut()
+        .for_each(|(trail, mut points, origin, mut aabb, caches)| {
+            points.step(trail, origin.translation().truncate(), delta);
+
+            let mut path = points.path(trail.lifetime).map(|point| point.position);
+            let Some(first) = path.next() else {
+                *aabb = Aabb::default();
+                return
+            };
+
+            let (min, max) = path.fold((first, first), |(min, max), point| (min.min(point), max.max(point)));
+
+            // Caps extend past the ends of the trail by their aspect ratio relative to its width.
+            let aspect = caches
+                .into_iter()
+                .flat_map(|caches| caches.iter())
+                .map(AtlasInfo::size)
+                .filter(|size| size.x > 0)
+                .map(|size| size.y as f32 / size.x as f32)
+                .fold(0.5, f32::max);
+
+            let width = trail.width.keys().iter().map(|&(.., width)| width.abs()).fold(0., f32::max);
+            let (min, max) = (min - width * aspect, max + width * aspect);
+
+            let inverse = origin.affine().inverse();
+            *aabb = Aabb::enclosing(
+                [min, vec2(max.x, min.y), max, vec2(min.x, max.y)].map(|corner| inverse.transform_point3(corner.extend(0.))),
+            )
+            .unwrap_or_default();
+        })
+}
+
+#[derive(Copy, Clone, Default)]
+struct Station {
+    position: Vec2,
+    distance: f32,
+    width: f32,
+    color: LinearRgba,
+    edge_color: LinearRgba,
+}
+
+impl Station {
+    #[inline]
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Self {
+            position: self.position + (to.position - self.position) * t,
+            distance: self.distance + (to.distance - self.distance) * t,
+            width: self.width + (to.width - self.width) * t,
+            color: self.color + (to.color - self.color) * t,
+            edge_color: self.edge_color + (to.edge_color - self.edge_color) * t,
+        }
+    }
+}
+
+/// [`Drawer`] for [`Trail`]s.
+#[derive(Component)]
+pub struct TrailDrawer<T: Vertex> {
+    stations: Vec<Station>,
+    uv_mode: TrailUvMode,
+    body: AtlasInfo,
+    head: Option<AtlasInfo>,
+    tail: Option<AtlasInfo>,
+    layer: f32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Vertex> Default for TrailDrawer<T> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            stations: Vec::new(),
+            uv_mode: TrailUvMode::Stretch,
+            body: AtlasInfo::DEFAULT,
+            head: None,
+            tail: None,
+            layer: 0.,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Vertex> TypePath for TrailDrawer<T> {
+    fn type_path() -> &'static str {
+        static CELL: GenericTypePathCell = GenericTypePathCell::new();
+        CELL.get_or_insert::<Self, _>(|| format!("hephae_atlas::trail::TrailDrawer<{}>", type_name::<T>()))
+    }
+
+    fn short_type_path() -> &'static str {
+        static CELL: GenericTypePathCell = GenericTypePathCell::new();
+        CELL.get_or_insert::<Self, _>(|| format!("TrailDrawer<{}>", type_name::<T>()))
+    }
+}
+
+impl<T> Drawer for TrailDrawer<T>
+where T: Vertex<PipelineKey: From<AssetId<Image>>> + HasAttrib<Pos2dAttrib> + HasAttrib<UvAttrib> + HasAttrib<ColorAttrib>
+{
+    type Vertex = T;
+
+    type ExtractParam = ();
+    type ExtractData = (
+        Read<Trail>,
+        Read<TrailPoints>,
+        Read<TrailSprites>,
+        Read<AtlasCaches>,
+        Read<GlobalTransform>,
+    );
+    type ExtractFilter = ();
+
+    type DrawParam = ();
+
+    fn extract(
+        mut drawer: DrawerExtract<Self>,
+        _: &SystemParamItem<Self::ExtractParam>,
+        (trail, points, sprites, caches, origin): QueryItem<Self::ExtractData>,
+    ) {
+        let drawer = drawer.get_or_default();
+        let (body, head, tail) = sprites.get(caches);
+
+        drawer.uv_mode = trail.uv_mode;
+        drawer.body = body.unwrap_or_default();
+        drawer.head = head;
+        drawer.tail = tail;
+        drawer.layer = origin.translation().z;
+
+        drawer.stations.clear();
+        drawer.stations.extend(points.path(trail.lifetime).map(|point| Station {
+            position: point.position,
+            distance: point.distance,
+            ..default()
+        }));
+
+        let (Some(first), Some(last)) = (drawer.stations.first(), drawer.stations.last()) else { return };
+        let (start, length) = (first.distance, last.distance - first.distance);
+
+        for station in &mut drawer.stations {
+            let t = match length > 0. {
+                true => (station.distance - start) / length,
+                false => 1.,
+            };
+
+            station.width = trail.width.sample(t);
+            station.color = trail.color.sample(t);
+            station.edge_color = match trail.edge_color {
+                Some(ref edge_color) => edge_color.sample(t),
+                None => station.color,
+            };
+        }
+    }
+
+    fn draw(&self, _: &SystemParamItem<Self::DrawParam>, queuer: &impl VertexQueuer<Vertex = Self::Vertex>) {
+        let stations = &self.stations;
+        let (Some(&first), Some(&last)) = (stations.first(), stations.last()) else { return };
+
+        let length = last.distance - first.distance;
+        if stations.len() < 2 || length <= 0. {
+            return
+        }
+
+        // Per-segment directions, falling back to the previous direction for zero-length segments.
+        let mut directions = Vec::with_capacity(stations.len() - 1);
+        let mut last_dir = stations
+            .windows(2)
+            .find_map(|ab| (ab[1].position - ab[0].position).try_normalize())
+            .unwrap_or(Vec2::Y);
+
+        for ab in stations.windows(2) {
+            last_dir = (ab[1].position - ab[0].position).normalize_or(last_dir);
+            directions.push(last_dir);
+        }
+
+        let normal = |i: usize| match (i.checked_sub(1).map(|i| directions[i]), directions.get(i)) {
+            (Some(prev), Some(&next)) => (prev + next).normalize_or(next).perp(),
+            (Some(dir), None) | (None, Some(&dir)) => dir.perp(),
+            (None, None) => Vec2::X,
+        };
+
+        let body = self.body;
+        let mut shapers = Vec::new();
+        for (i, ab) in stations.windows(2).enumerate() {
+            let &[a, b] = ab else { break };
+            let (na, nb) = (normal(i), normal(i + 1));
+
+            // Pieces of this segment, as `(from, to, v_from, v_to)` in normalized sprite heights.
+            let mut push = |t0: f32, t1: f32, v0: f32, v1: f32| {
+                let (from, to) = (a.lerp(b, t0), a.lerp(b, t1));
+                let (n0, n1) = (na.lerp(nb, t0).normalize_or(na), na.lerp(nb, t1).normalize_or(nb));
+
+                shapers.extend(ribbon::<T>((from, n0, v0), (to, n1, v1), body));
+            };
+
+            match self.uv_mode {
+                TrailUvMode::Tile { length: tile } if tile > 0. => {
+                    let span = b.distance - a.distance;
+                    if span <= 0. {
+                        continue
+                    }
+
+                    let mut d0 = a.distance;
+                    while d0 < b.distance {
+                        // Step to the next tile boundary, guarding against `d0` landing right below one
+                        // due to rounding errors.
+                        let mut boundary = ((d0 / tile).floor() + 1.) * tile;
+                        if boundary <= d0 {
+                            boundary += tile
+                        }
+
+                        let d1 = boundary.min(b.distance);
+                        let base = ((d0 + d1) * 0.5 / tile).floor();
+
+                        push(
+                            (d0 - a.distance) / span,
+                            (d1 - a.distance) / span,
+                            d0 / tile - base,
+                            d1 / tile - base,
+                        );
+
+                        d0 = d1;
+                    }
+                }
+                _ => push(
+                    0.,
+                    1.,
+                    (a.distance - first.distance) / length,
+                    (b.distance - first.distance) / length,
+                ),
+            }
+        }
+
+        Shaper::queue_rects(shapers, queuer, self.layer, body.page.into());
+
+        let caps = [
+            (self.head, last, normal(stations.len() - 1), *directions.last().unwrap()),
+            (self.tail, first, -normal(0), -directions[0]),
+        ];
+
+        for (cap, station, normal, direction) in caps {
+            let Some(cap) = cap.filter(|cap| cap.size().cmpgt(UVec2::ZERO).all()) else { continue };
+            let size = cap.size();
+            let aspect = size.y as f32 / size.x as f32;
+
+            let end = Station {
+                position: station.position + direction * station.width * aspect,
+                ..station
+            };
+
+            Shaper::queue_rects(
+                ribbon::<T>((station, normal, 0.), (end, normal, 1.), cap),
+                queuer,
+                self.layer,
+                cap.page.into(),
+            )
+        }
+    }
+}
+
+/// Creates the left and right halves of a ribbon quad from `from` to `to`, where `v` is normalized
+/// from the bottom to the top of `sprite`.
+fn ribbon<T>(
+    (from, n0, v0): (Station, Vec2, f32),
+    (to, n1, v1): (Station, Vec2, f32),
+    sprite: AtlasInfo,
+) -> [Shaper<T, 4>; 2]
+where
+    T: Vertex + HasAttrib<Pos2dAttrib> + HasAttrib<UvAttrib> + HasAttrib<ColorAttrib>,
+{
+    let (e0, e1) = (n0 * from.width * 0.5, n1 * to.width * 0.5);
+
+    [(1., 0.), (-1., 1.)].map(|(side, edge)| {
+        let mut shaper = Shaper::new();
+        shaper
+            .pos2d([from.position + e0 * side, from.position, to.position, to.position + e1 * side])
+            .uv::<0>([vec2(edge, v0), vec2(0.5, v0), vec2(0.5, v1), vec2(edge, v1)].map(|point| sprite.uv(point)))
+            .colors::<0>([from.edge_color, from.color, to.color, to.edge_color]);
+        shaper
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(points: impl IntoIterator<Item = TrailPoint>) -> Vec<f32> {
+        points.into_iter().map(|point| point.position.x).collect()
+    }
+
+    #[test]
+    fn step_spaces_points() {
+        let trail = Trail {
+            lifetime: 10.,
+            min_distance: 4.,
+            ..default()
+        };
+
+        let mut points = TrailPoints::default();
+        for x in [0., 1., 2., 3., 4., 5., 9.] {
+            points.step(&trail, vec2(x, 0.), 0.1);
+        }
+
+        // Points are only recorded once the head has traveled at least `min_distance`.
+        assert_eq!(positions(points.points().iter().copied()), [0., 4., 9.]);
+        assert_eq!(points.points().iter().map(|point| point.distance).collect::<Vec<_>>(), [
+            0., 4., 9.
+        ]);
+
+        // The head between recorded points is part of the path, with its distance accumulated.
+        points.step(&trail, vec2(11., 0.), 0.1);
+        let path = points.path(trail.lifetime).collect::<Vec<_>>();
+        assert_eq!(positions(path.iter().copied()), [0., 4., 9., 11.]);
+        assert_eq!(path.last().unwrap().distance, 11.);
+        assert_eq!(path.last().unwrap().age, 0.);
+    }
+
+    #[test]
+    fn tail_interpolates_to_lifetime() {
+        let trail = Trail {
+            lifetime: 1.,
+            min_distance: 0.,
+            ..default()
+        };
+
+        let mut points = TrailPoints::default();
+        points.step(&trail, vec2(0., 0.), 0.);
+        points.step(&trail, vec2(10., 0.), 0.5);
+        assert!(points.tail(trail.lifetime).is_none());
+
+        // The first point expires, and is kept as a tail sliding towards the next one.
+        points.step(&trail, vec2(20., 0.), 0.75);
+        assert_eq!(positions(points.points().iter().copied()), [10., 20.]);
+
+        let tail = points.tail(trail.lifetime).unwrap();
+        assert!((tail.position.x - 5.).abs() < 1e-5);
+        assert!((tail.age - trail.lifetime).abs() < 1e-5);
+        assert_eq!(positions(points.path(trail.lifetime)), [tail.position.x, 10., 20.]);
+    }
+
+    #[test]
+    fn step_prunes_expired_points() {
+        let mut trail = Trail {
+            lifetime: 1.,
+            min_distance: 0.,
+            max_points: 3,
+            ..default()
+        };
+
+        let mut points = TrailPoints::default();
+        for x in 0..5 {
+            points.step(&trail, vec2(x as f32, 0.), 0.1);
+        }
+
+        // Points beyond `max_points` are removed regardless of their age.
+        assert_eq!(positions(points.points().iter().copied()), [2., 3., 4.]);
+
+        // Without emitting, every point eventually expires and the tail fully retracts.
+        trail.emitting = false;
+        for _ in 0..20 {
+            points.step(&trail, vec2(4., 0.), 0.1);
+        }
+
+        assert!(points.points().is_empty());
+        assert!(points.tail(trail.lifetime).is_none());
+        assert_eq!(positions(points.path(trail.lifetime)), [4.]);
+    }
+}
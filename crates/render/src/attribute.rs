@@ -7,6 +7,7 @@ use std::{
 };
 
 use bevy::{
+    math::Affine3A,
     prelude::*,
     render::render_resource::{VertexAttribute, VertexFormat},
 };
@@ -226,10 +227,25 @@ impl<F: FnOnce(u32) -> T, T: Transfer<u32>> IndexQueuer for F {
 ///
 /// This utilizes [`HasAttrib`] to determine whether the vertex it's working with supports those
 /// attributes.
+///
+/// A transform may be attached with [`with_transform`](Self::with_transform), in which case the
+/// positions set through [`pos2d`](Self::pos2d), [`pos3d`](Self::pos3d), and their derivatives are
+/// kept in local space and only transformed when [queued](Self::queue).
 #[derive(Debug, Copy, Clone)]
 pub struct Shaper<T: Vertex, const VERTICES: usize> {
     /// The raw vertex array for manual access.
     pub vertices: [T; VERTICES],
+    transform: Option<Affine3A>,
+    position: Option<PositionOffset>,
+}
+
+/// Remembers which position attribute was written to by [`Shaper`], so that
+/// [`Shaper::with_transform`] may be applied at queue time without requiring [`HasAttrib`] bounds
+/// there.
+#[derive(Debug, Copy, Clone)]
+enum PositionOffset {
+    Pos2d(usize),
+    Pos3d(usize),
 }
 
 impl<T: Vertex, const VERTICES: usize> Default for Shaper<T, VERTICES> {
@@ -245,9 +261,57 @@ impl<T: Vertex, const VERTICES: usize> Shaper<T, VERTICES> {
     pub fn new() -> Self {
         Self {
             vertices: [T::zeroed(); VERTICES],
+            transform: None,
+            position: None,
+        }
+    }
+
+    /// Creates a shaper over already-written vertices, without a transform. Replaces the
+    /// `Shaper { vertices }` literal, which no longer compiles now that the shaper keeps private
+    /// state.
+    #[inline]
+    pub fn from_vertices(vertices: [T; VERTICES]) -> Self {
+        Self {
+            vertices,
+            transform: None,
+            position: None,
         }
     }
 
+    /// Sets the transform applied to the position attributes of all vertices when
+    /// [queued](Self::queue). Replaces any previously set transform.
+    ///
+    /// Positions are only transformed if they were set through the position methods of this
+    /// shaper (e.g. [`pos2d`](Self::pos2d), [`pos3d`](Self::pos3d), or [`rect`](Shaper::rect)).
+    /// 2D positions are treated as if their Z component is `0`, and the resulting Z component is
+    /// discarded.
+    #[inline]
+    pub fn with_transform(&mut self, transform: impl Into<Affine3A>) -> &mut Self {
+        self.transform = Some(transform.into());
+        self
+    }
+
+    /// Similar to [`with_transform`](Self::with_transform), except the transform is applied
+    /// around `pivot` in local space; that is, `pivot` is moved to the origin before the transform
+    /// is applied. Useful for rotating and scaling sprites around arbitrary origins.
+    #[inline]
+    pub fn with_transform_pivot(&mut self, transform: impl Into<Affine3A>, pivot: impl Into<Vec3>) -> &mut Self {
+        self.with_transform(transform.into() * Affine3A::from_translation(-pivot.into()))
+    }
+
+    /// Convenience method for [`with_transform`](Self::with_transform) that uses the affine
+    /// matrix of a [`GlobalTransform`], typically extracted from the drawer's entity.
+    #[inline]
+    pub fn transform(&mut self, transform: &GlobalTransform) -> &mut Self {
+        self.with_transform(transform.affine())
+    }
+
+    /// Returns the transform set by [`with_transform`](Self::with_transform), if any.
+    #[inline]
+    pub fn get_transform(&self) -> Option<Affine3A> {
+        self.transform
+    }
+
     /// Manually sets the vertex at a given index.
     ///
     /// # Panics
@@ -299,16 +363,55 @@ impl<T: Vertex, const VERTICES: usize> Shaper<T, VERTICES> {
         self
     }
 
-    /// Finishes using the [`Shaper`] API.
+    /// Finishes using the [`Shaper`] API, applying the [transform](Self::with_transform) if any.
     #[inline]
-    pub fn queue(self, queuer: &impl VertexQueuer<Vertex = T>, layer: f32, key: T::PipelineKey, indices: impl IndexQueuer) {
+    pub fn queue(
+        mut self,
+        queuer: &impl VertexQueuer<Vertex = T>,
+        layer: f32,
+        key: T::PipelineKey,
+        indices: impl IndexQueuer,
+    ) {
+        self.apply_transform();
         queuer.request(layer, key, indices.queue(queuer.data(self.vertices.as_ref())))
     }
 
+    /// Returns the vertices with the [transform](Self::with_transform) applied, for drawers that
+    /// cache vertices across frames instead of queueing them immediately.
+    #[inline]
+    pub fn into_vertices(mut self) -> [T; VERTICES] {
+        self.apply_transform();
+        self.vertices
+    }
+
+    fn apply_transform(&mut self) {
+        let (Some(transform), Some(position)) = (self.transform.take(), self.position) else { return };
+        for vertex in &mut self.vertices {
+            let ptr = (vertex as *mut T).cast::<u8>();
+
+            // Safety: The offsets were obtained from `HasAttrib` bounds in the position methods, which
+            // guarantee that they point to fields of the appropriate types.
+            unsafe {
+                match position {
+                    PositionOffset::Pos2d(offset) => {
+                        let ptr = ptr.add(offset).cast::<Vec2>();
+                        let pos = transform.transform_point3(ptr.read_unaligned().extend(0.));
+                        ptr.write_unaligned(pos.truncate());
+                    }
+                    PositionOffset::Pos3d(offset) => {
+                        let ptr = ptr.add(offset).cast::<Vec3>();
+                        ptr.write_unaligned(transform.transform_point3(ptr.read_unaligned()));
+                    }
+                }
+            }
+        }
+    }
+
     /// Sets 2D positions for all vertices.
     #[inline]
     pub fn pos2d(&mut self, positions: [Vec2; VERTICES]) -> &mut Self
     where T: HasAttrib<Pos2dAttrib> {
+        self.position = Some(PositionOffset::Pos2d(const { <T as HasAttrib<Pos2dAttrib>>::OFFSET }));
         self.attribs::<Pos2dAttrib>(positions)
     }
 
@@ -320,6 +423,7 @@ impl<T: Vertex, const VERTICES: usize> Shaper<T, VERTICES> {
     #[inline]
     pub fn pos2d_at(&mut self, index: usize, position: impl Into<Vec2>) -> &mut Self
     where T: HasAttrib<Pos2dAttrib> {
+        self.position = Some(PositionOffset::Pos2d(const { <T as HasAttrib<Pos2dAttrib>>::OFFSET }));
         self.attrib_at::<Pos2dAttrib>(index, position.into())
     }
 
@@ -327,6 +431,7 @@ impl<T: Vertex, const VERTICES: usize> Shaper<T, VERTICES> {
     #[inline]
     pub fn pos3d(&mut self, positions: [Vec3; VERTICES]) -> &mut Self
     where T: HasAttrib<Pos3dAttrib> {
+        self.position = Some(PositionOffset::Pos3d(const { <T as HasAttrib<Pos3dAttrib>>::OFFSET }));
         self.attribs::<Pos3dAttrib>(positions)
     }
 
@@ -338,6 +443,7 @@ impl<T: Vertex, const VERTICES: usize> Shaper<T, VERTICES> {
     #[inline]
     pub fn pos3d_at(&mut self, index: usize, position: impl Into<Vec3>) -> &mut Self
     where T: HasAttrib<Pos3dAttrib> {
+        self.position = Some(PositionOffset::Pos3d(const { <T as HasAttrib<Pos3dAttrib>>::OFFSET }));
         self.attrib_at::<Pos3dAttrib>(index, position.into())
     }
 
@@ -440,6 +546,45 @@ impl<T: Vertex> Shaper<T, 4> {
         self.pos2d([vec2(x, y), vec2(x + w, y), vec2(x + w, y + h), vec2(x, y + h)])
     }
 
+    /// Positions the rectangle such that the origin lies on `anchor`, relative to the rectangle's
+    /// size. `(-0.5, -0.5)` is the bottom-left corner, `(0, 0)` is the center, and `(0.5, 0.5)` is
+    /// the top-right corner. Combined with [`with_transform`](Self::with_transform), this rotates
+    /// and scales the rectangle around the anchor.
+    ///
+    /// This sets the attributes in the order of bottom-left, bottom-right, top-right, and top-left,
+    /// which works in tandem with [`Self::queue_rect`].
+    #[inline]
+    pub fn rect_anchor(&mut self, anchor: impl Into<Vec2>, size: impl Into<Vec2>) -> &mut Self
+    where T: HasAttrib<Pos2dAttrib> {
+        let size = size.into();
+        self.rect_bl(size * (-anchor.into() - 0.5), size)
+    }
+
+    /// Positions a sprite whose transparent borders were trimmed off when packed, such that it
+    /// lands where it would have if untrimmed. `center` and `size` describe the untrimmed
+    /// sprite of `original_size` pixels, and `trim` is the region it was trimmed to, in pixels
+    /// with "+Y = down". Pair this with [`uv_rect`](Self::uv_rect) on the trimmed rectangle.
+    ///
+    /// This sets the attributes in the order of bottom-left, bottom-right, top-right, and top-left,
+    /// which works in tandem with [`Self::queue_rect`].
+    #[inline]
+    pub fn rect_trimmed(
+        &mut self,
+        center: impl Into<Vec2>,
+        size: impl Into<Vec2>,
+        original_size: UVec2,
+        trim: URect,
+    ) -> &mut Self
+    where
+        T: HasAttrib<Pos2dAttrib>,
+    {
+        let size = size.into();
+        let scale = size / original_size.max(UVec2::ONE).as_vec2();
+        let offset = uvec2(trim.min.x, original_size.y.saturating_sub(trim.max.y)).as_vec2() * scale;
+
+        self.rect_bl(center.into() - size / 2. + offset, trim.size().as_vec2() * scale)
+    }
+
     /// Assigns UV coordinates based on an atlas sprite entry. Note that this flips the V
     /// coordinate, since images are actually flipped vertically (y=0 is at the top, not bottom).
     ///
@@ -455,10 +600,183 @@ impl<T: Vertex> Shaper<T, 4> {
         self.uv::<INDEX>([vec2(u, v), vec2(u2, v), vec2(u2, v2), vec2(u, v2)])
     }
 
+    /// Like [`uv_rect`](Self::uv_rect), but if `rotated` is `true`, treats `rect` as holding the
+    /// sprite rotated 90° clockwise in the atlas page, as packers may do to fit sprites tighter.
+    ///
+    /// This sets the attributes in the order of bottom-left, bottom-right, top-right, and top-left,
+    /// which works in tandem with [`Self::queue_rect`].
+    #[inline]
+    pub fn uv_rect_rotated<const INDEX: usize>(&mut self, rect: URect, atlas_size: UVec2, rotated: bool) -> &mut Self
+    where T: HasAttrib<UvAttrib<INDEX>> {
+        if !rotated {
+            return self.uv_rect::<INDEX>(rect, atlas_size)
+        }
+
+        let page = atlas_size.as_vec2();
+        let Vec2 { x: u, y: v2 } = rect.min.as_vec2() / page;
+        let Vec2 { x: u2, y: v } = rect.max.as_vec2() / page;
+
+        self.uv::<INDEX>([vec2(u, v2), vec2(u, v), vec2(u2, v), vec2(u2, v2)])
+    }
+
     /// Convenience method for [`Self::queue`] where the index array is `[0, 1, 2, 2, 3, 0]`, offset
     /// by the base index.
     #[inline]
     pub fn queue_rect(self, queuer: &impl VertexQueuer<Vertex = T>, layer: f32, key: T::PipelineKey) {
         self.queue(queuer, layer, key, |o| [o, o + 1, o + 2, o + 2, o + 3, o])
     }
+
+    /// Like [`Self::queue_rect`], but queues all the rectangles in one draw request. Prefer this
+    /// when drawing many rectangles with the same layer and key, as this produces fewer phase
+    /// items to sort.
+    pub fn queue_rects(
+        shapers: impl IntoIterator<Item = Self>,
+        queuer: &impl VertexQueuer<Vertex = T>,
+        layer: f32,
+        key: T::PipelineKey,
+    ) {
+        let vertices = shapers.into_iter().flat_map(Self::into_vertices).collect::<Vec<_>>();
+
+        if vertices.is_empty() {
+            return
+        }
+
+        let offset = queuer.data(vertices.as_slice());
+        let indices = (0..(vertices.len() / 4) as u32)
+            .flat_map(|i| {
+                let o = offset + i * 4;
+                [o, o + 1, o + 2, o + 2, o + 3, o]
+            })
+            .collect::<Vec<_>>();
+
+        queuer.request(layer, key, indices.as_slice())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use bevy::{core_pipeline::core_2d::Transparent2d, ecs::system::SystemParamItem};
+
+    use super::*;
+
+    #[derive(VertexLayout, Copy, Clone, Pod, Zeroable)]
+    #[repr(C)]
+    pub(crate) struct Vert {
+        #[attrib(Pos2d)]
+        pub pos: Vec2,
+        #[attrib(Uv)]
+        pub uv: Vec2,
+    }
+
+    impl Vertex for Vert {
+        type PipelineParam = ();
+        type PipelineProp = ();
+        type PipelineKey = ();
+
+        type BatchParam = ();
+        type BatchProp = ();
+
+        type Item = Transparent2d;
+        type RenderCommand = ();
+
+        const SHADER: &'static str = "";
+
+        fn init_pipeline(_: SystemParamItem<Self::PipelineParam>) -> Self::PipelineProp {}
+
+        fn create_batch(_: &mut SystemParamItem<Self::BatchParam>, _: Self::PipelineKey) -> Self::BatchProp {}
+    }
+
+    fn assert_positions(shaper: Shaper<Vert, 4>, expected: [Vec2; 4]) {
+        let positions = shaper.into_vertices().map(|vertex| vertex.pos);
+        for (position, expected) in positions.into_iter().zip(expected) {
+            assert!(position.abs_diff_eq(expected, 1e-5), "{positions:?} != {expected:?}");
+        }
+    }
+
+    fn assert_uvs(shaper: Shaper<Vert, 4>, expected: [Vec2; 4]) {
+        let uvs = shaper.into_vertices().map(|vertex| vertex.uv);
+        for (uv, expected) in uvs.into_iter().zip(expected) {
+            assert!(uv.abs_diff_eq(expected, 1e-5), "{uvs:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn rect_anchor() {
+        let mut shaper = Shaper::new();
+        shaper.rect_anchor(vec2(-0.5, -0.5), vec2(2., 4.));
+        assert_positions(shaper, [vec2(0., 0.), vec2(2., 0.), vec2(2., 4.), vec2(0., 4.)]);
+
+        let mut shaper = Shaper::new();
+        shaper.rect_anchor(vec2(0.5, 0.), vec2(2., 4.));
+        assert_positions(shaper, [vec2(-2., -2.), vec2(0., -2.), vec2(0., 2.), vec2(-2., 2.)]);
+    }
+
+    #[test]
+    fn with_transform() {
+        // Rotates a quarter turn around the anchor, then moves the anchor to (10, 0).
+        let mut shaper = Shaper::new();
+        shaper
+            .rect_anchor(Vec2::ZERO, vec2(2., 2.))
+            .with_transform(Affine3A::from_translation(vec3(10., 0., 0.)) * Affine3A::from_rotation_z(FRAC_PI_2));
+
+        assert_positions(shaper, [vec2(11., -1.), vec2(11., 1.), vec2(9., 1.), vec2(9., -1.)]);
+    }
+
+    #[test]
+    fn with_transform_pivot() {
+        // Scales around the center of the rectangle, which is moved to the origin.
+        let mut shaper = Shaper::new();
+        shaper
+            .rect_bl(Vec2::ZERO, vec2(2., 2.))
+            .with_transform_pivot(Affine3A::from_scale(Vec3::splat(2.)), vec3(1., 1., 0.));
+
+        assert_positions(shaper, [vec2(-2., -2.), vec2(2., -2.), vec2(2., 2.), vec2(-2., 2.)]);
+    }
+
+    #[test]
+    fn rect_trimmed() {
+        // Untrimmed, this is the same quad as `rect`.
+        let mut shaper = Shaper::new();
+        shaper.rect_trimmed(Vec2::ZERO, vec2(16., 16.), uvec2(8, 8), URect::new(0, 0, 8, 8));
+        assert_positions(shaper, [vec2(-8., -8.), vec2(8., -8.), vec2(8., 8.), vec2(-8., 8.)]);
+
+        // Trimmed to columns 2..6 and rows 1..4 counting from the top, at twice the pixel size: the
+        // quad covers the same part of the untrimmed quad that the trimmed pixels did.
+        let mut shaper = Shaper::new();
+        shaper.rect_trimmed(Vec2::ZERO, vec2(16., 16.), uvec2(8, 8), URect::new(2, 1, 6, 4));
+        assert_positions(shaper, [vec2(-4., 0.), vec2(4., 0.), vec2(4., 6.), vec2(-4., 6.)]);
+
+        // Non-square sprites scale each axis separately.
+        let mut shaper = Shaper::new();
+        shaper.rect_trimmed(vec2(10., 0.), vec2(4., 2.), uvec2(8, 4), URect::new(0, 2, 4, 4));
+        assert_positions(shaper, [vec2(8., -1.), vec2(10., -1.), vec2(10., 0.), vec2(8., 0.)]);
+    }
+
+    #[test]
+    fn uv_rect_rotated() {
+        let rect = URect::new(4, 8, 6, 11);
+        let page = uvec2(16, 16);
+
+        let mut shaper = Shaper::new();
+        shaper.uv_rect_rotated::<0>(rect, page, false);
+        assert_uvs(shaper, [
+            vec2(0.25, 0.6875),
+            vec2(0.375, 0.6875),
+            vec2(0.375, 0.5),
+            vec2(0.25, 0.5),
+        ]);
+
+        // A 3x2 sprite rotated 90° clockwise into the 2x3 `rect`: its bottom-left corner ends up at
+        // the top-left of `rect`, its bottom-right at the bottom-left, and so on.
+        let mut shaper = Shaper::new();
+        shaper.uv_rect_rotated::<0>(rect, page, true);
+        assert_uvs(shaper, [
+            vec2(0.25, 0.5),
+            vec2(0.25, 0.6875),
+            vec2(0.375, 0.6875),
+            vec2(0.375, 0.5),
+        ]);
+    }
 }
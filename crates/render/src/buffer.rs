@@ -0,0 +1,166 @@
+//! GPU buffer allocation strategy for vertex and index buffers.
+//!
+//! Both the shared vertex buffer and per-view index buffers are rewritten every frame. Instead of
+//! reallocating at the exact required size whenever the contents outgrow the buffer, they grow
+//! according to [`HephaeBufferPolicy::growth`], optionally shrink back after being underused for a
+//! while, and rotate through a ring of buffers so that writing a frame's contents never touches a
+//! buffer the GPU may still be reading from a previous frame.
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_resource::{Buffer, BufferAddress, BufferDescriptor, BufferId, BufferUsages, COPY_BUFFER_ALIGNMENT},
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+
+/// Configures how Hephae allocates its vertex and index buffers. Insert this into the main world to
+/// override the defaults; it's extracted to the render world every frame.
+#[derive(Resource, ExtractResource, Reflect, Copy, Clone, Debug)]
+#[reflect(Resource, Default, Debug)]
+pub struct HephaeBufferPolicy {
+    /// How much to allocate when the contents outgrow a buffer.
+    pub growth: BufferGrowth,
+    /// If set, buffers that have been using at most a quarter of their capacity for this many
+    /// consecutive frames are reallocated to fit their contents.
+    pub shrink_after: Option<u32>,
+    /// How many buffers to rotate through. `1` reuses the same buffer every frame.
+    pub ring_size: usize,
+}
+
+impl Default for HephaeBufferPolicy {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            growth: BufferGrowth::PowerOfTwo,
+            shrink_after: Some(300),
+            ring_size: 2,
+        }
+    }
+}
+
+/// How much a buffer grows when its contents no longer fit.
+#[derive(Reflect, Copy, Clone, Debug, Default, PartialEq)]
+#[reflect(Default, Debug, PartialEq)]
+pub enum BufferGrowth {
+    /// Allocates exactly the required size.
+    Exact,
+    /// Allocates the next power of two of the required size.
+    #[default]
+    PowerOfTwo,
+    /// Allocates the required size multiplied by the given factor, which is clamped to at least
+    /// `1`.
+    Headroom(f32),
+}
+
+impl BufferGrowth {
+    /// Returns the capacity to allocate for `size` bytes, aligned to [`COPY_BUFFER_ALIGNMENT`].
+    #[inline]
+    pub fn capacity(self, size: BufferAddress) -> BufferAddress {
+        let capacity = match self {
+            Self::Exact => size,
+            Self::PowerOfTwo => size.next_power_of_two(),
+            Self::Headroom(factor) => (size as f64 * factor.max(1.) as f64).ceil() as BufferAddress,
+        };
+
+        capacity.max(COPY_BUFFER_ALIGNMENT).next_multiple_of(COPY_BUFFER_ALIGNMENT)
+    }
+}
+
+pub(crate) struct BufferRing {
+    label: &'static str,
+    usage: BufferUsages,
+    slots: Vec<(Option<Buffer>, u32)>,
+    current: usize,
+}
+
+impl BufferRing {
+    #[inline]
+    pub const fn new(label: &'static str, usage: BufferUsages) -> Self {
+        Self {
+            label,
+            usage: usage.union(BufferUsages::COPY_DST),
+            slots: Vec::new(),
+            current: 0,
+        }
+    }
+
+    /// Returns the buffer written to by the last [`write`](Self::write).
+    #[inline]
+    pub fn current(&self) -> Option<&Buffer> {
+        self.slots.get(self.current)?.0.as_ref()
+    }
+
+    /// Returns whether `id` is one of the buffers in the ring.
+    #[inline]
+    pub fn contains(&self, id: BufferId) -> bool {
+        self.slots
+            .iter()
+            .any(|(buffer, ..)| buffer.as_ref().is_some_and(|buffer| buffer.id() == id))
+    }
+
+    /// Adds `usage` to buffers allocated from now on, reallocating the ones that lack it as they're
+    /// written to.
+    #[inline]
+    pub fn add_usage(&mut self, usage: BufferUsages) {
+        self.usage |= usage;
+    }
+
+    /// Advances to the next buffer in the ring and writes `contents` into it, reallocating as the
+    /// policy dictates.
+    #[inline]
+    pub fn write(&mut self, device: &RenderDevice, queue: &RenderQueue, policy: &HephaeBufferPolicy, contents: &[u8]) {
+        self.write_sized(device, queue, policy, contents, contents.len() as BufferAddress)
+    }
+
+    /// Like [`write`](Self::write), but ensures the buffer is at least `size` bytes long; the bytes
+    /// past `contents` are left for the GPU to fill.
+    pub fn write_sized(
+        &mut self,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        policy: &HephaeBufferPolicy,
+        contents: &[u8],
+        size: BufferAddress,
+    ) {
+        let ring_size = policy.ring_size.max(1);
+        self.slots.resize_with(ring_size, || (None, 0));
+        self.current = (self.current + 1) % ring_size;
+
+        let (buffer, idle) = &mut self.slots[self.current];
+        let size = size.max(contents.len() as BufferAddress);
+
+        let reallocate = match buffer {
+            None => true,
+            Some(buffer) if buffer.size() < size || !buffer.usage().contains(self.usage) => true,
+            Some(buffer) => {
+                if size.saturating_mul(4) <= buffer.size() {
+                    *idle = idle.saturating_add(1)
+                } else {
+                    *idle = 0
+                }
+
+                // Each slot is only written every `ring_size` frames, so scale its threshold down to
+                // keep `shrink_after` counted in frames.
+                let writes = |frames: u32| frames.div_ceil(u32::try_from(ring_size).unwrap_or(u32::MAX));
+                policy.shrink_after.is_some_and(|frames| *idle >= writes(frames)) &&
+                    policy.growth.capacity(size) < buffer.size()
+            }
+        };
+
+        if reallocate {
+            *idle = 0;
+            *buffer = Some(device.create_buffer(&BufferDescriptor {
+                label: Some(self.label),
+                size: policy.growth.capacity(size),
+                usage: self.usage,
+                mapped_at_creation: false,
+            }));
+        }
+
+        if !contents.is_empty() {
+            queue.write_buffer(buffer.as_ref().unwrap(), 0, contents);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Provides [`Keyframes`], a simple piecewise-linear curve over a normalized `[0, 1]` domain.
+//!
+//! Used by built-in drawers such as [particles](crate::particle) to define values over the
+//! lifetime of an object, e.g. color gradients or size curves.
+
+use bevy::{math::VectorSpace, prelude::*};
+
+/// A piecewise-linear curve defined by keys sorted by their time in `[0, 1]`.
+///
+/// Sampling before the first key or after the last key clamps to the respective key's value. An
+/// empty curve samples to [`VectorSpace::ZERO`].
+#[derive(Reflect, Clone, Debug)]
+pub struct Keyframes<T> {
+    keys: Vec<(f32, T)>,
+}
+
+impl<T: VectorSpace> Default for Keyframes<T> {
+    #[inline]
+    fn default() -> Self {
+        Self { keys: Vec::new() }
+    }
+}
+
+impl<T: VectorSpace> Keyframes<T> {
+    /// Creates a curve out of `(time, value)` keys. The keys don't have to be sorted.
+    #[inline]
+    pub fn new(keys: impl IntoIterator<Item = (f32, T)>) -> Self {
+        let mut keys = keys.into_iter().collect::<Vec<_>>();
+        keys.sort_by(|(a, ..), (b, ..)| a.total_cmp(b));
+
+        Self { keys }
+    }
+
+    /// Creates a curve that always samples to `value`.
+    #[inline]
+    pub fn constant(value: T) -> Self {
+        Self { keys: vec![(0., value)] }
+    }
+
+    /// Creates a curve that linearly interpolates from `start` to `end`.
+    #[inline]
+    pub fn linear(start: T, end: T) -> Self {
+        Self {
+            keys: vec![(0., start), (1., end)],
+        }
+    }
+
+    /// Returns the keys of this curve, sorted by their time.
+    #[inline]
+    pub fn keys(&self) -> &[(f32, T)] {
+        &self.keys
+    }
+
+    /// Samples the curve at `t`.
+    pub fn sample(&self, t: f32) -> T {
+        let index = self.keys.partition_point(|&(time, ..)| time <= t);
+        match (index.checked_sub(1).map(|i| self.keys[i]), self.keys.get(index)) {
+            (None, None) => T::ZERO,
+            (Some((.., value)), None) | (None, Some(&(.., value))) => value,
+            (Some((from_time, from)), Some(&(to_time, to))) => {
+                let span = to_time - from_time;
+                match span > 0. {
+                    true => VectorSpace::lerp(from, to, (t - from_time) / span),
+                    false => to,
+                }
+            }
+        }
+    }
+}
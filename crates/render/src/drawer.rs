@@ -23,11 +23,12 @@ use bevy::{
     utils::Parallel,
 };
 use fixedbitset::FixedBitSet;
-use vec_belt::Transfer;
+use vec_belt::{Transfer, transfer};
 
 use crate::{
+    instance::SpriteInstance,
     pipeline::{DrawBuffers, VisibleDrawers},
-    vertex::{DrawItems, Vertex},
+    vertex::{DrawItem, DrawItems, Vertex},
 };
 
 /// A render world [`Component`] extracted from the main world that will be used to issue draw
@@ -103,6 +104,55 @@ pub trait VertexQueuer {
     /// Extends the index buffer with the supplied iterator. Indices should be offset by the index
     /// returned by [`data`](VertexQueuer::data).
     fn request(&self, layer: f32, key: <Self::Vertex as Vertex>::PipelineKey, indices: impl Transfer<u32>);
+
+    /// Like [`request`](VertexQueuer::request), but only draws the indices in views whose
+    /// [`RenderLayers`] intersect `layers`. This is checked on top of the drawer entity's own
+    /// visibility, so one drawer may e.g. send its body to one camera and its outline to another.
+    ///
+    /// The default implementation ignores `layers` and forwards to
+    /// [`request`](VertexQueuer::request).
+    #[inline]
+    fn request_with_layers(
+        &self,
+        layers: RenderLayers,
+        layer: f32,
+        key: <Self::Vertex as Vertex>::PipelineKey,
+        indices: impl Transfer<u32>,
+    ) {
+        _ = layers;
+        self.request(layer, key, indices)
+    }
+
+    /// Uploads [`SpriteInstance`]s to be [expanded](crate::instance) into quads on the GPU, and
+    /// requests them to be drawn. Indices are generated automatically. Requires
+    /// [`InstancePlugin`](crate::instance::InstancePlugin) for this vertex type; otherwise, the
+    /// request is skipped.
+    ///
+    /// The default implementation is for queuers that don't support instancing; it discards the
+    /// instances and warns once.
+    #[inline]
+    fn instances(&self, layer: f32, key: <Self::Vertex as Vertex>::PipelineKey, instances: impl Transfer<SpriteInstance>) {
+        _ = (layer, key, instances);
+        warn_once!("This `VertexQueuer` doesn't support instancing; discarding `SpriteInstance`s")
+    }
+
+    /// Like [`instances`](VertexQueuer::instances), but only draws the quads in views whose
+    /// [`RenderLayers`] intersect `layers`, like
+    /// [`request_with_layers`](VertexQueuer::request_with_layers).
+    ///
+    /// The default implementation ignores `layers` and forwards to
+    /// [`instances`](VertexQueuer::instances).
+    #[inline]
+    fn instances_with_layers(
+        &self,
+        layers: RenderLayers,
+        layer: f32,
+        key: <Self::Vertex as Vertex>::PipelineKey,
+        instances: impl Transfer<SpriteInstance>,
+    ) {
+        _ = layers;
+        self.instances(layer, key, instances)
+    }
 }
 
 /// Marker component for entities that may extract out [`Drawer`]s to the render world. This *must*
@@ -359,14 +409,78 @@ pub(crate) fn queue_drawers<T: Drawer>(
 
         #[inline]
         fn request(&self, layer: f32, key: T::PipelineKey, indices: impl Transfer<u32>) {
+            self.push(None, layer, key, indices)
+        }
+
+        #[inline]
+        fn request_with_layers(&self, layers: RenderLayers, layer: f32, key: T::PipelineKey, indices: impl Transfer<u32>) {
+            self.push(Some(layers), layer, key, indices)
+        }
+
+        #[inline]
+        fn instances(&self, layer: f32, key: T::PipelineKey, instances: impl Transfer<SpriteInstance>) {
+            self.push_instances(None, layer, key, instances)
+        }
+
+        #[inline]
+        fn instances_with_layers(
+            &self,
+            layers: RenderLayers,
+            layer: f32,
+            key: T::PipelineKey,
+            instances: impl Transfer<SpriteInstance>,
+        ) {
+            self.push_instances(Some(layers), layer, key, instances)
+        }
+    }
+
+    impl<T: Vertex> Queuer<'_, T> {
+        #[inline]
+        fn push(&self, layers: Option<RenderLayers>, layer: f32, key: T::PipelineKey, indices: impl Transfer<u32>) {
             let len = indices.len();
             let offset = self.buffers.indices.append(indices);
 
-            self.items
-                .0
-                .lock()
-                .unwrap_or_else(PoisonError::into_inner)
-                .push((offset..offset + len, layer, key));
+            self.items.0.lock().unwrap_or_else(PoisonError::into_inner).push(DrawItem {
+                indices: offset..offset + len,
+                layer,
+                key,
+                layers,
+                instanced: false,
+            });
+        }
+
+        fn push_instances(
+            &self,
+            layers: Option<RenderLayers>,
+            layer: f32,
+            key: T::PipelineKey,
+            instances: impl Transfer<SpriteInstance>,
+        ) {
+            let count = instances.len();
+            if count == 0 {
+                return
+            }
+
+            let base = self.buffers.instances.append(instances) as u32 * 4;
+            let len = count * 6;
+
+            // Safety: Exactly `len` indices are written.
+            let offset = self.buffers.indices.append(unsafe {
+                transfer(len, |dst: *mut u32| {
+                    for i in 0..count {
+                        let o = base + i as u32 * 4;
+                        dst.add(i * 6).cast::<[u32; 6]>().write([o, o + 1, o + 2, o + 2, o + 3, o])
+                    }
+                })
+            });
+
+            self.items.0.lock().unwrap_or_else(PoisonError::into_inner).push(DrawItem {
+                indices: offset..offset + len,
+                layer,
+                key,
+                layers,
+                instanced: true,
+            });
         }
     }
 }
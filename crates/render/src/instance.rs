@@ -0,0 +1,344 @@
+//! GPU-side vertex generation for sprite-like quads.
+//!
+//! Drawers that emit many textured quads spend most of their time writing four vertices per quad,
+//! even though a quad is fully described by its position, rotation, size, atlas rectangle, and
+//! color. With [`InstancePlugin<T>`] added, drawers may instead
+//! [upload](crate::drawer::VertexQueuer::instances) compact [`SpriteInstance`]s, and a compute
+//! shader expands them into the shared vertex buffer of `T` in a render graph node that runs before
+//! the cameras are drawn.
+//!
+//! The expanded vertices live past the end of the vertices queued through
+//! [`data`](crate::drawer::VertexQueuer::data), and their indices still go through the regular
+//! per-view index buffers, so instanced requests sort and batch exactly like any other draw request
+//! and share [`ViewBatches`](crate::pipeline::ViewBatches) with them. Only the
+//! [position](Pos2dAttrib), [UV](UvAttrib), and [color](ColorAttrib) attributes are written;
+//! everything else in the expanded vertices is left zeroed.
+//!
+//! Instanced requests are skipped, with a warning, while they can't be expanded; that is, without
+//! [`InstancePlugin<T>`] or while its compute pipeline is still compiling.
+//!
+//! This requires compute shaders and storage buffers, so it's unavailable on WebGL2.
+
+use std::{any::TypeId, marker::PhantomData, num::NonZero};
+
+use bevy::{
+    asset::weak_handle,
+    platform::collections::HashMap,
+    prelude::*,
+    render::{
+        Render, RenderApp, RenderSet,
+        graph::CameraDriverLabel,
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, RenderLabel},
+        render_resource::{
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer, BufferDescriptor, BufferId,
+            BufferUsages, CachedComputePipelineId, ComputePassDescriptor, ComputePipelineDescriptor, PipelineCache,
+            ShaderDefVal, ShaderStages,
+            binding_types::{storage_buffer_read_only_sized, storage_buffer_sized, uniform_buffer_sized},
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+    },
+};
+use bytemuck::{Pod, Zeroable, cast_slice};
+use hephae_utils::prelude::*;
+
+use crate::{
+    HephaeRenderSystems,
+    attribute::{ColorAttrib, HasAttrib, Pos2dAttrib, UvAttrib},
+    pipeline::DrawBuffers,
+    vertex::Vertex,
+};
+
+/// Global handle to the compute shader expanding [`SpriteInstance`]s into vertices.
+pub const HEPHAE_INSTANCE_SHADER_HANDLE: Handle<Shader> = weak_handle!("4f0b6c71-2a8e-4d3b-9c5a-e17d3f2b8a60");
+
+/// Compact description of a textured quad, expanded into four vertices on the GPU.
+///
+/// The layout mirrors the WGSL struct in `hephae/instance.wgsl`.
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct SpriteInstance {
+    /// Normalized UV rectangle, as `(min.x, min.y, max.x, max.y)` with y pointing down the image.
+    pub rect: Vec4,
+    /// The color written to every vertex.
+    pub color: LinearRgba,
+    /// The world position of the anchor.
+    pub position: Vec2,
+    /// The size of the quad.
+    pub size: Vec2,
+    /// The anchor relative to the quad's size, where `(-0.5, -0.5)` is the bottom-left corner and
+    /// `(0, 0)` is the center.
+    pub anchor: Vec2,
+    /// Counter-clockwise rotation around the anchor, in radians.
+    pub rotation: f32,
+    _pad: f32,
+}
+
+impl SpriteInstance {
+    /// Creates a centered, unrotated, white instance of the given size.
+    #[inline]
+    pub const fn new(position: Vec2, size: Vec2) -> Self {
+        Self {
+            rect: Vec4::new(0., 0., 1., 1.),
+            color: LinearRgba::WHITE,
+            position,
+            size,
+            anchor: Vec2::ZERO,
+            rotation: 0.,
+            _pad: 0.,
+        }
+    }
+
+    /// Samples `rect` of an atlas page of size `page`, like
+    /// [`Shaper::uv_rect`](crate::attribute::Shaper::uv_rect).
+    #[inline]
+    pub fn with_uv_rect(mut self, rect: URect, page: UVec2) -> Self {
+        let page = page.as_vec2();
+        let (min, max) = (rect.min.as_vec2() / page, rect.max.as_vec2() / page);
+
+        self.rect = Vec4::new(min.x, min.y, max.x, max.y);
+        self
+    }
+
+    /// Sets the color.
+    #[inline]
+    pub fn with_color(mut self, color: impl Into<LinearRgba>) -> Self {
+        self.color = color.into();
+        self
+    }
+
+    /// Sets the anchor.
+    #[inline]
+    pub fn with_anchor(mut self, anchor: Vec2) -> Self {
+        self.anchor = anchor;
+        self
+    }
+
+    /// Sets the rotation.
+    #[inline]
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+}
+
+/// [`Vertex`]es that [`SpriteInstance`]s may be expanded into.
+pub trait InstanceVertex: Vertex + HasAttrib<Pos2dAttrib> + HasAttrib<UvAttrib> + HasAttrib<ColorAttrib> {}
+impl<T: Vertex + HasAttrib<Pos2dAttrib> + HasAttrib<UvAttrib> + HasAttrib<ColorAttrib>> InstanceVertex for T {}
+
+plugin_def! {
+    /// Enables [`SpriteInstance`] expansion for `T`. Must be added after `T`'s
+    /// [`VertexPlugin`](crate::VertexPlugin).
+    pub struct InstancePlugin<T: InstanceVertex>;
+    fn build(&self, app: &mut App) {
+        let mut shaders = app.world_mut().resource_mut::<Assets<Shader>>();
+        if !shaders.contains(&HEPHAE_INSTANCE_SHADER_HANDLE) {
+            shaders.insert(
+                &HEPHAE_INSTANCE_SHADER_HANDLE,
+                Shader::from_wgsl(include_str!("instance.wgsl"), "hephae/instance.wgsl"),
+            );
+        }
+
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.add_systems(
+                Render,
+                (
+                    check_instance_pipeline::<T>
+                        .in_set(RenderSet::PrepareResources)
+                        .before(HephaeRenderSystems::PrepareIndices),
+                    prepare_instance_bind_groups::<T>.in_set(HephaeRenderSystems::ExpandInstances),
+                ),
+            );
+        }
+    }
+
+    fn finish(&self, app: &mut App) {
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<InstancePipeline<T>>()
+                .init_resource::<InstanceBindGroups<T>>();
+
+            let mut graph = render_app.world_mut().resource_mut::<RenderGraph>();
+            graph.add_node(InstanceLabel::of::<T>(), InstanceNode::<T>(PhantomData));
+            graph.add_node_edge(InstanceLabel::of::<T>(), CameraDriverLabel);
+        }
+    }
+}
+
+/// Render graph label of the node expanding the [`SpriteInstance`]s of a [`InstanceVertex`] type.
+/// The node is added to the root graph and runs before [`CameraDriverLabel`].
+#[derive(RenderLabel, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct InstanceLabel(TypeId);
+impl InstanceLabel {
+    /// The label of `T`'s expansion node.
+    #[inline]
+    pub fn of<T: InstanceVertex>() -> Self {
+        Self(TypeId::of::<T>())
+    }
+}
+
+/// Compute pipeline expanding [`SpriteInstance`]s into `T`.
+#[derive(Resource)]
+pub struct InstancePipeline<T: InstanceVertex> {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: InstanceVertex> FromWorld for InstancePipeline<T> {
+    fn from_world(world: &mut World) -> Self {
+        const fn word(offset: usize) -> u32 {
+            assert!(offset % 4 == 0, "vertex attributes must be 4-byte aligned");
+            (offset / 4) as u32
+        }
+
+        let layout = world.resource::<RenderDevice>().create_bind_group_layout(
+            "hephae_instance_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer_sized(false, NonZero::new(16)),
+                    storage_buffer_read_only_sized(false, NonZero::new(size_of::<SpriteInstance>() as u64)),
+                    storage_buffer_sized(false, None),
+                ),
+            ),
+        );
+
+        let pipeline = world
+            .resource::<PipelineCache>()
+            .queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some("hephae_instance_pipeline".into()),
+                layout: vec![layout.clone()],
+                push_constant_ranges: Vec::new(),
+                shader: HEPHAE_INSTANCE_SHADER_HANDLE,
+                shader_defs: vec![
+                    ShaderDefVal::UInt("STRIDE".into(), const { word(size_of::<T>()) }),
+                    ShaderDefVal::UInt("POS_OFFSET".into(), const { word(<T as HasAttrib<Pos2dAttrib>>::OFFSET) }),
+                    ShaderDefVal::UInt("UV_OFFSET".into(), const { word(<T as HasAttrib<UvAttrib>>::OFFSET) }),
+                    ShaderDefVal::UInt("COLOR_OFFSET".into(), const { word(<T as HasAttrib<ColorAttrib>>::OFFSET) }),
+                ],
+                entry_point: "expand".into(),
+                zero_initialize_workgroup_memory: false,
+            });
+
+        Self {
+            layout,
+            pipeline,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Marks instanced requests of `T` as drawable once the expansion pipeline has compiled.
+pub(crate) fn check_instance_pipeline<T: InstanceVertex>(
+    pipeline_cache: Res<PipelineCache>,
+    pipeline: Res<InstancePipeline<T>>,
+    mut buffers: ResMut<DrawBuffers<T>>,
+) {
+    buffers.instances_ready = pipeline_cache.get_compute_pipeline(pipeline.pipeline).is_some();
+}
+
+/// Parameters and bind groups of the expansion of `T`'s [`SpriteInstance`]s.
+#[derive(Resource)]
+pub struct InstanceBindGroups<T: InstanceVertex> {
+    params: Buffer,
+    cache: HashMap<(BufferId, BufferId), BindGroup>,
+    current: Option<BindGroup>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: InstanceVertex> FromWorld for InstanceBindGroups<T> {
+    #[inline]
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            params: world.resource::<RenderDevice>().create_buffer(&BufferDescriptor {
+                label: Some("hephae_instance_params"),
+                size: 16,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            cache: HashMap::default(),
+            current: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Writes this frame's expansion parameters and picks the bind group of the instance and vertex
+/// buffers written to this frame, creating it only the first time that pair of buffers is used.
+pub(crate) fn prepare_instance_bind_groups<T: InstanceVertex>(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    pipeline: Res<InstancePipeline<T>>,
+    buffers: Res<DrawBuffers<T>>,
+    bind_groups: ResMut<InstanceBindGroups<T>>,
+) {
+    let bind_groups = bind_groups.into_inner();
+    bind_groups.current = None;
+
+    // Forget bind groups of buffers that have been reallocated or dropped out of the ring.
+    bind_groups.cache.retain(|&(instance, vertex), _| {
+        buffers.instance_buffer.contains(instance) && buffers.vertex_buffer.contains(vertex)
+    });
+
+    let (base_vertex, count) = buffers.instanced;
+    if count == 0 || !buffers.instances_ready {
+        return
+    }
+
+    let (Some(instance_buffer), Some(vertex_buffer)) = (buffers.instance_buffer.current(), buffers.vertex_buffer.current())
+    else {
+        return
+    };
+
+    queue.write_buffer(&bind_groups.params, 0, cast_slice(&[base_vertex, count, 0, 0]));
+
+    let params = &bind_groups.params;
+    let bind_group = bind_groups
+        .cache
+        .entry((instance_buffer.id(), vertex_buffer.id()))
+        .or_insert_with(|| {
+            device.create_bind_group(
+                "hephae_instance_bind_group",
+                &pipeline.layout,
+                &BindGroupEntries::sequential((
+                    params.as_entire_binding(),
+                    instance_buffer.as_entire_binding(),
+                    vertex_buffer.as_entire_binding(),
+                )),
+            )
+        });
+
+    bind_groups.current = Some(bind_group.clone());
+}
+
+/// Render graph node dispatching the expansion of this frame's [`SpriteInstance`]s of `T`.
+struct InstanceNode<T: InstanceVertex>(PhantomData<fn() -> T>);
+impl<T: InstanceVertex> Node for InstanceNode<T> {
+    fn run(
+        &self,
+        _: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let (Some(bind_group), Some(compute_pipeline)) = (
+            world.resource::<InstanceBindGroups<T>>().current.as_ref(),
+            world
+                .resource::<PipelineCache>()
+                .get_compute_pipeline(world.resource::<InstancePipeline<T>>().pipeline),
+        ) else {
+            return Ok(())
+        };
+
+        let (.., count) = world.resource::<DrawBuffers<T>>().instanced;
+        let mut pass = render_context.command_encoder().begin_compute_pass(&ComputePassDescriptor {
+            label: Some("hephae_instance_pass"),
+            timestamp_writes: None,
+        });
+
+        pass.set_pipeline(compute_pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.dispatch_workgroups(count.div_ceil(64), 1, 1);
+
+        Ok(())
+    }
+}
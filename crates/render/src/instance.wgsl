@@ -0,0 +1,70 @@
+#define_import_path hephae::instance
+
+struct SpriteInstance {
+    rect: vec4<f32>,
+    color: vec4<f32>,
+    position: vec2<f32>,
+    size: vec2<f32>,
+    anchor: vec2<f32>,
+    rotation: f32,
+    _pad: f32,
+}
+
+struct InstanceParams {
+    base_vertex: u32,
+    count: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: InstanceParams;
+@group(0) @binding(1) var<storage, read> instances: array<SpriteInstance>;
+@group(0) @binding(2) var<storage, read_write> vertices: array<u32>;
+
+fn write_vec2(word: u32, value: vec2<f32>) {
+    vertices[word] = bitcast<u32>(value.x);
+    vertices[word + 1u] = bitcast<u32>(value.y);
+}
+
+fn write_vec4(word: u32, value: vec4<f32>) {
+    vertices[word] = bitcast<u32>(value.x);
+    vertices[word + 1u] = bitcast<u32>(value.y);
+    vertices[word + 2u] = bitcast<u32>(value.z);
+    vertices[word + 3u] = bitcast<u32>(value.w);
+}
+
+@compute @workgroup_size(64)
+fn expand(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= params.count {
+        return;
+    }
+
+    let instance = instances[index];
+    let sin_cos = vec2<f32>(sin(instance.rotation), cos(instance.rotation));
+
+    // Bottom-left, bottom-right, top-right, top-left; matches `Shaper::rect`.
+    var corners = array<vec2<f32>, 4>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+    );
+
+    for (var i = 0u; i < 4u; i += 1u) {
+        let corner = corners[i];
+        let local = (corner - 0.5 - instance.anchor) * instance.size;
+        let position = instance.position + vec2<f32>(
+            local.x * sin_cos.y - local.y * sin_cos.x,
+            local.x * sin_cos.x + local.y * sin_cos.y,
+        );
+
+        let uv = vec2<f32>(
+            mix(instance.rect.x, instance.rect.z, corner.x),
+            mix(instance.rect.w, instance.rect.y, corner.y),
+        );
+
+        let base = (params.base_vertex + index * 4u + i) * #{STRIDE}u;
+        write_vec2(base + #{POS_OFFSET}u, position);
+        write_vec2(base + #{UV_OFFSET}u, uv);
+        write_vec4(base + #{COLOR_OFFSET}u, instance.color);
+    }
+}
@@ -4,9 +4,16 @@
 #![cfg_attr(doc, deny(missing_docs))]
 
 pub mod attribute;
+pub mod buffer;
+pub mod curve;
 pub mod drawer;
 pub mod image_bind;
+pub mod instance;
+pub mod particle;
+pub mod pass;
 pub mod pipeline;
+pub mod polygon;
+pub mod target;
 pub mod vertex;
 
 use bevy::{
@@ -15,6 +22,8 @@ use bevy::{
     prelude::*,
     render::{
         Render, RenderApp, RenderSet,
+        camera::CameraUpdateSystem,
+        extract_resource::ExtractResourcePlugin,
         render_phase::AddRenderCommand,
         render_resource::SpecializedRenderPipelines,
         sync_component::SyncComponentPlugin,
@@ -24,14 +33,17 @@ use bevy::{
 use hephae_utils::prelude::*;
 
 use crate::{
+    buffer::HephaeBufferPolicy,
     drawer::{DrawBy, Drawer, check_visibilities, extract_drawers, queue_drawers},
     image_bind::{ImageAssetEvents, ImageBindGroups, extract_image_events, validate_image_bind_groups},
+    particle::{ParticleEmitter, simulate_particles},
     pipeline::{
         DrawBuffers, DrawRequests, VertexPipeline, ViewBatches, ViewIndexBuffer, VisibleDrawers, extract_shader,
         load_shader, prepare_indices, prepare_view_bind_groups, queue_vertices,
     },
     prelude::Vertex,
-    vertex::DrawItems,
+    target::{HephaeRenderTarget, sync_render_targets},
+    vertex::{DrawItems, DrawerPhaseItem},
 };
 
 /// Common imports for [`hephae_render`](crate).
@@ -44,9 +56,16 @@ pub mod prelude {
             ByteColorAttrib, ColorAttrib, IsAttribData, LinearRgbaExt as _, Nor, Pos2dAttrib, Pos3dAttrib, Shaper, UvAttrib,
             VertexLayout,
         },
+        buffer::{BufferGrowth, HephaeBufferPolicy},
+        curve::Keyframes,
         drawer::{DrawBy, Drawer, DrawerExtract, VertexQueuer},
         image_bind::ImageBindGroups,
+        instance::{InstancePlugin, InstanceVertex, SpriteInstance},
+        particle::{ParticleDrawer, ParticleEmitter, ParticleSprite, Particles},
+        pass::{HephaePass, HephaePassItem},
         pipeline::{VertexPipeline, ViewBatches},
+        polygon::{Polygon, PolygonShaper},
+        target::HephaeRenderTarget,
         vertex::Vertex,
     };
 }
@@ -72,6 +91,7 @@ plugin_def! {
     /// [`drawer`] for more.
     pub struct VertexPlugin<T: Vertex>;
     fn build(&self, app: &mut App) {
+        T::Item::setup(app);
         app.add_systems(Startup, load_shader::<T>);
 
         if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
@@ -138,6 +158,19 @@ plugin_def! {
                 Shader::from_wgsl(include_str!("view_bindings.wgsl"), "hephae/view_bindings.wgsl"),
             );
 
+            app.init_resource::<HephaeBufferPolicy>()
+                .register_type::<HephaeBufferPolicy>()
+                .register_type::<HephaeRenderTarget>()
+                .register_type::<ParticleEmitter>()
+                .add_plugins(ExtractResourcePlugin::<HephaeBufferPolicy>::default())
+                .add_systems(
+                    PostUpdate,
+                    (
+                        sync_render_targets.before(CameraUpdateSystem),
+                        simulate_particles.in_set(VisibilitySystems::CalculateBounds),
+                    ),
+                );
+
             if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
                 render_app
                     .configure_sets(
@@ -151,6 +184,7 @@ plugin_def! {
                             HephaeRenderSystems::QueueDrawers.before(HephaeRenderSystems::QueueVertices),
                             HephaeRenderSystems::PrepareIndices.in_set(RenderSet::PrepareResources),
                             HephaeRenderSystems::PrepareBindGroups.in_set(RenderSet::PrepareBindGroups),
+                            HephaeRenderSystems::ExpandInstances.in_set(RenderSet::PrepareBindGroups),
                         ),
                     )
                     .init_resource::<ImageAssetEvents>()
@@ -184,4 +218,8 @@ pub enum HephaeRenderSystems {
     PrepareIndices,
     /// Label for preparing batches and view bind groups, in [`RenderSet::PrepareBindGroups`].
     PrepareBindGroups,
+    /// Label for preparing the bind groups of [`SpriteInstance`](instance::SpriteInstance)
+    /// expansion, in [`RenderSet::PrepareBindGroups`]. The expansion itself is dispatched by the
+    /// [`InstanceLabel`](instance::InstanceLabel) render graph node.
+    ExpandInstances,
 }
@@ -0,0 +1,416 @@
+//! Provides a simple particle system, simulated in the main world and drawn by [`ParticleDrawer`].
+//!
+//! Spawn an entity with [`ParticleEmitter`] and
+//! [`DrawBy<ParticleDrawer<T>>`](crate::drawer::DrawBy), where `T` is your [`Vertex`], and register
+//! [`ParticleDrawer<T>`] as one of the drawers in [`RendererPlugin`](crate::RendererPlugin).
+//! Particles are simulated in world space, so moving the emitter doesn't drag existing particles
+//! along.
+//!
+//! The emitter doesn't know about texture atlases; fill [`ParticleSprite`] with the page and rects
+//! of your atlas sprites, e.g. from `hephae_atlas`'s `AtlasInfo`. Each emitter issues one draw
+//! request for all of its particles, so emitters sharing the same page and layer batch together.
+
+use std::{any::type_name, marker::PhantomData, ops::Range};
+
+use bevy::{
+    ecs::{
+        query::QueryItem,
+        system::{SystemParamItem, lifetimeless::Read},
+    },
+    prelude::*,
+    reflect::{TypePath, utility::GenericTypePathCell},
+    render::primitives::Aabb,
+};
+use fastrand::Rng;
+
+use crate::{
+    attribute::{ColorAttrib, HasAttrib, Pos2dAttrib, Shaper, UvAttrib},
+    curve::Keyframes,
+    drawer::{Drawer, DrawerExtract, VertexQueuer},
+    vertex::Vertex,
+};
+
+/// Emits [`Particles`] from its entity's [`GlobalTransform`].
+///
+/// Everything about the emitter may be changed at runtime; changes only affect newly spawned
+/// particles, except for [`color`](Self::color), [`size`](Self::size), and
+/// [`sprite`](Self::sprite), which are evaluated when drawing.
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component, Default, Debug)]
+#[require(Particles, Transform, Visibility, Aabb)]
+pub struct ParticleEmitter {
+    /// Whether new particles are spawned. Existing particles keep being simulated regardless.
+    pub emitting: bool,
+    /// How many particles are spawned per second.
+    pub rate: f32,
+    /// The maximum amount of particles alive at once for this emitter.
+    pub max_particles: usize,
+    /// The lifetime range of each particle, in seconds.
+    pub lifetime: Range<f32>,
+    /// The initial direction of particles, in radians relative to the emitter's rotation.
+    pub direction: f32,
+    /// The angle of the cone particles are emitted in, in radians, centered at
+    /// [`direction`](Self::direction).
+    pub spread: f32,
+    /// The initial speed range of each particle, in world units per second.
+    pub speed: Range<f32>,
+    /// Constant acceleration applied to all particles, in world units per second squared.
+    pub gravity: Vec2,
+    /// The angular velocity range of each particle, in radians per second.
+    pub spin: Range<f32>,
+    /// The color of particles over their normalized lifetime.
+    pub color: Keyframes<LinearRgba>,
+    /// The size multiplier of particles over their normalized lifetime, relative to the sprite
+    /// frame size.
+    pub size: Keyframes<f32>,
+    /// The sprite particles are drawn with.
+    pub sprite: ParticleSprite,
+    /// Seeds the random number generator, making simulations deterministic given the same inputs.
+    pub seed: u64,
+}
+
+impl Default for ParticleEmitter {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            emitting: true,
+            rate: 10.,
+            max_particles: 256,
+            lifetime: 1.0..1.0,
+            direction: std::f32::consts::FRAC_PI_2,
+            spread: 0.,
+            speed: 100.0..100.0,
+            gravity: Vec2::ZERO,
+            spin: 0.0..0.0,
+            color: Keyframes::constant(LinearRgba::WHITE),
+            size: Keyframes::constant(1.),
+            sprite: ParticleSprite::default(),
+            seed: 0,
+        }
+    }
+}
+
+/// Atlas sprite or animation particles are drawn with.
+#[derive(Reflect, Clone, Debug, Default)]
+#[reflect(Default, Debug)]
+pub struct ParticleSprite {
+    /// The page image [`AssetId`], used as the [pipeline key](Vertex::PipelineKey).
+    pub page: AssetId<Image>,
+    /// The page size.
+    pub page_size: UVec2,
+    /// Animation frames within the page. A single frame makes a static sprite.
+    pub frames: Vec<URect>,
+    /// Frames per second. If `0`, the frames are instead spread evenly across each particle's
+    /// lifetime.
+    pub frame_rate: f32,
+}
+
+impl ParticleSprite {
+    /// Returns the frame a particle should display given its age and lifetime.
+    #[inline]
+    pub fn frame(&self, age: f32, lifetime: f32) -> Option<URect> {
+        let len = self.frames.len();
+        let index = match self.frame_rate > 0. {
+            true => (age * self.frame_rate) as usize % len.max(1),
+            false => ((age / lifetime) * len as f32) as usize,
+        };
+
+        self.frames.get(index.min(len.saturating_sub(1))).copied()
+    }
+}
+
+/// A single simulated particle.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Particle {
+    /// World-space position.
+    pub position: Vec2,
+    /// World-space velocity, in units per second.
+    pub velocity: Vec2,
+    /// World-space rotation, in radians. Starts out facing the emitted direction.
+    pub rotation: f32,
+    /// Angular velocity, in radians per second.
+    pub spin: f32,
+    /// How long this particle has been alive, in seconds.
+    pub age: f32,
+    /// How long this particle lives, in seconds.
+    pub lifetime: f32,
+}
+
+impl Particle {
+    /// The normalized age of this particle, in `[0, 1]`.
+    #[inline]
+    pub fn progress(&self) -> f32 {
+        (self.age / self.lifetime).clamp(0., 1.)
+    }
+}
+
+/// Live particles of a [`ParticleEmitter`].
+#[derive(Component, Clone, Default)]
+pub struct Particles {
+    particles: Vec<Particle>,
+    rng: Option<Rng>,
+    pending: f32,
+}
+
+impl Particles {
+    /// Returns the live particles.
+    #[inline]
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// Removes all particles and reseeds the random number generator from the emitter on the next
+    /// simulation step.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.particles.clear();
+        self.rng = None;
+        self.pending = 0.;
+    }
+
+    /// Advances the simulation by `delta` seconds, spawning particles from `origin`.
+    pub fn step(&mut self, emitter: &ParticleEmitter, origin: &GlobalTransform, delta: f32) {
+        let gravity = emitter.gravity;
+        self.particles.retain_mut(|particle| {
+            particle.age += delta;
+            if particle.age >= particle.lifetime {
+                return false
+            }
+
+            particle.velocity += gravity * delta;
+            particle.position += particle.velocity * delta;
+            particle.rotation += particle.spin * delta;
+            true
+        });
+
+        if !emitter.emitting {
+            self.pending = 0.;
+            return
+        }
+
+        let rng = self.rng.get_or_insert_with(|| Rng::with_seed(emitter.seed));
+        let mut sample = |range: &Range<f32>| range.start + (range.end - range.start) * rng.f32();
+
+        let affine = origin.affine();
+        let position = affine.translation.truncate();
+
+        self.pending += emitter.rate * delta;
+        while self.pending >= 1. {
+            self.pending -= 1.;
+            if self.particles.len() >= emitter.max_particles {
+                continue
+            }
+
+            let angle = emitter.direction + emitter.spread * (sample(&(-0.5..0.5)));
+            let direction = affine
+                .transform_vector3(Vec2::from_angle(angle).extend(0.))
+                .truncate()
+                .normalize_or_zero();
+
+            self.particles.push(Particle {
+                position,
+                velocity: direction * sample(&emitter.speed),
+                rotation: direction.to_angle(),
+                spin: sample(&emitter.spin),
+                age: 0.,
+                lifetime: sample(&emitter.lifetime).max(f32::EPSILON),
+            });
+        }
+    }
+
+    /// Computes the local-space bounds of the particles relative to `origin`, accounting for
+    /// particle sizes.
+    pub fn bounds(&self, emitter: &ParticleEmitter, origin: &GlobalTransform) -> Aabb {
+        let Some(&first) = self.particles.first() else { return Aabb::default() };
+
+        let mut extent = Vec2::ZERO;
+        if let Some(max) = emitter
+            .sprite
+            .frames
+            .iter()
+            .map(|rect| rect.size().as_vec2().length())
+            .reduce(f32::max)
+        {
+            let scale = emitter.size.keys().iter().map(|&(.., size)| size.abs()).fold(0., f32::max);
+            extent = Vec2::splat(max * scale * 0.5);
+        }
+
+        let (min, max) = self
+            .particles
+            .iter()
+            .fold((first.position, first.position), |(min, max), particle| {
+                (min.min(particle.position), max.max(particle.position))
+            });
+
+        let inverse = origin.affine().inverse();
+        let (min, max) = (min - extent, max + extent);
+        Aabb::enclosing(
+            [min, vec2(max.x, min.y), max, vec2(min.x, max.y)].map(|corner| inverse.transform_point3(corner.extend(0.))),
+        )
+        .unwrap_or_default()
+    }
+}
+
+/// Steps every [`ParticleEmitter`] and updates their [`Aabb`] for visibility checks.
+pub fn simulate_particles(
+    time: Res<Time>,
+    mut emitters: Query<(&ParticleEmitter, &mut Particles, &GlobalTransform, &mut Aabb)>,
+) {
+    let delta = time.delta_secs();
+    emitters
+        .par_iter_mut()
+        .for_each(|(emitter, mut particles, origin, mut aabb)| {
+            particles.step(emitter, origin, delta);
+            *aabb = particles.bounds(emitter, origin);
+        })
+}
+
+/// [`Drawer`] for [`ParticleEmitter`]s, drawing each particle as a rotated quad.
+#[derive(Component)]
+pub struct ParticleDrawer<T: Vertex> {
+    particles: Vec<(Vec2, Vec2, f32, LinearRgba, URect)>,
+    page: AssetId<Image>,
+    page_size: UVec2,
+    layer: f32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Vertex> Default for ParticleDrawer<T> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            particles: Vec::new(),
+            page: AssetId::default(),
+            page_size: UVec2::ZERO,
+            layer: 0.,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Vertex> TypePath for ParticleDrawer<T> {
+    fn type_path() -> &'static str {
+        static CELL: GenericTypePathCell = GenericTypePathCell::new();
+        CELL.get_or_insert::<Self, _>(|| format!("hephae_render::particle::ParticleDrawer<{}>", type_name::<T>()))
+    }
+
+    fn short_type_path() -> &'static str {
+        static CELL: GenericTypePathCell = GenericTypePathCell::new();
+        CELL.get_or_insert::<Self, _>(|| format!("ParticleDrawer<{}>", type_name::<T>()))
+    }
+}
+
+impl<T> Drawer for ParticleDrawer<T>
+where T: Vertex<PipelineKey: From<AssetId<Image>>> + HasAttrib<Pos2dAttrib> + HasAttrib<UvAttrib> + HasAttrib<ColorAttrib>
+{
+    type Vertex = T;
+
+    type ExtractParam = ();
+    type ExtractData = (Read<ParticleEmitter>, Read<Particles>, Read<GlobalTransform>);
+    type ExtractFilter = ();
+
+    type DrawParam = ();
+
+    fn extract(
+        mut drawer: DrawerExtract<Self>,
+        _: &SystemParamItem<Self::ExtractParam>,
+        (emitter, particles, origin): QueryItem<Self::ExtractData>,
+    ) {
+        let drawer = drawer.get_or_default();
+        drawer.page = emitter.sprite.page;
+        drawer.page_size = emitter.sprite.page_size;
+        drawer.layer = origin.translation().z;
+
+        drawer.particles.clear();
+        drawer.particles.extend(particles.particles.iter().filter_map(|particle| {
+            let rect = emitter.sprite.frame(particle.age, particle.lifetime)?;
+            let progress = particle.progress();
+
+            Some((
+                particle.position,
+                rect.size().as_vec2() * emitter.size.sample(progress),
+                particle.rotation,
+                emitter.color.sample(progress),
+                rect,
+            ))
+        }));
+    }
+
+    fn draw(&self, _: &SystemParamItem<Self::DrawParam>, queuer: &impl VertexQueuer<Vertex = Self::Vertex>) {
+        let shapers = self.particles.iter().map(|&(position, size, rotation, color, rect)| {
+            let rot = Vec2::from_angle(rotation);
+            let Vec2 { x: w, y: h } = size / 2.;
+
+            let mut shaper = Shaper::<T, 4>::new();
+            shaper
+                .pos2d([vec2(-w, -h), vec2(w, -h), vec2(w, h), vec2(-w, h)].map(|corner| position + rot.rotate(corner)))
+                .uv_rect::<0>(rect, self.page_size)
+                .color::<0>(color);
+            shaper
+        });
+
+        Shaper::queue_rects(shapers, queuer, self.layer, self.page.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    use super::*;
+
+    fn emitter() -> ParticleEmitter {
+        ParticleEmitter {
+            rate: 60.,
+            lifetime: 0.5..2.,
+            spread: PI,
+            speed: 50.0..150.,
+            gravity: vec2(0., -98.),
+            spin: -1.0..1.,
+            seed: 42,
+            ..default()
+        }
+    }
+
+    fn simulate(emitter: &ParticleEmitter, origin: &GlobalTransform) -> Vec<Particle> {
+        let mut particles = Particles::default();
+        for _ in 0..120 {
+            particles.step(emitter, origin, 1. / 60.);
+        }
+
+        particles.particles
+    }
+
+    #[test]
+    fn same_seed_simulates_identically() {
+        let emitter = emitter();
+        let origin = GlobalTransform::from(Transform::from_xyz(10., -20., 0.).with_rotation(Quat::from_rotation_z(0.3)));
+
+        let particles = simulate(&emitter, &origin);
+        assert!(!particles.is_empty());
+        assert_eq!(particles, simulate(&emitter, &origin));
+
+        let reseeded = ParticleEmitter { seed: 7, ..emitter };
+        assert_ne!(particles, simulate(&reseeded, &origin));
+    }
+
+    #[test]
+    fn rotation_follows_emitter() {
+        let emitter = ParticleEmitter {
+            rate: 1.,
+            direction: 0.,
+            ..default()
+        };
+
+        let mut particles = Particles::default();
+        particles.step(
+            &emitter,
+            &GlobalTransform::from(Transform::from_rotation(Quat::from_rotation_z(FRAC_PI_2))),
+            1.,
+        );
+
+        let [particle] = particles.particles() else { panic!("expected exactly one particle") };
+        assert!((particle.rotation - FRAC_PI_2).abs() < 1e-5);
+        assert!(particle.velocity.normalize().abs_diff_eq(Vec2::Y, 1e-5));
+    }
+}
@@ -0,0 +1,297 @@
+//! Provides [`HephaePassNode`], a render graph node for Hephae-only passes outside of `core_2d`'s
+//! main transparent pass.
+//!
+//! By default, [`Vertex`](crate::vertex::Vertex)es render into [`Transparent2d`], i.e., alongside
+//! everything else in the main 2D pass. To render somewhere else, e.g. before the main pass for
+//! backgrounds or after tonemapping for HUD overlays, define a [`HephaePass`] and set
+//! [`Vertex::Item`](crate::vertex::Vertex::Item) to [`HephaePassItem<P>`]:
+//!
+//! ```rust,ignore
+//! #[derive(RenderLabel, Debug, Hash, PartialEq, Eq, Clone, Default)]
+//! struct Hud;
+//!
+//! impl HephaePass for Hud {
+//!     fn after() -> impl RenderLabel { Node2d::Tonemapping }
+//!     fn before() -> impl RenderLabel { Node2d::EndMainPassPostProcessing }
+//! }
+//!
+//! impl Vertex for HudVertex {
+//!     type Item = HephaePassItem<Hud>;
+//!     // ...
+//! }
+//! ```
+//!
+//! The pass (along with its phase, sorting, and node) is set up automatically when the first vertex
+//! using it is registered, so there's no need to add [`HephaePassPlugin`] manually. Multiple vertex
+//! types may share the same pass; their items are sorted by layer together.
+//!
+//! [`Transparent2d`]: bevy::core_pipeline::core_2d::Transparent2d
+
+use std::{marker::PhantomData, ops::Range};
+
+use bevy::{
+    core_pipeline::core_2d::graph::Core2d,
+    ecs::query::QueryItem,
+    math::FloatOrd,
+    platform::collections::HashSet,
+    prelude::*,
+    render::{
+        Extract, Render, RenderApp, RenderSet,
+        camera::ExtractedCamera,
+        render_graph::{NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner},
+        render_phase::{
+            CachedRenderPipelinePhaseItem, DrawFunctionId, DrawFunctions, PhaseItem, PhaseItemExtraIndex, SortedPhaseItem,
+            ViewSortedRenderPhases, sort_phase_system,
+        },
+        render_resource::{CachedRenderPipelineId, LoadOp, Operations, RenderPassDescriptor, StoreOp},
+        renderer::RenderContext,
+        sync_world::MainEntity,
+        view::{ExtractedView, RetainedViewEntity, ViewDepthTexture, ViewTarget},
+    },
+};
+use hephae_utils::prelude::*;
+
+use crate::vertex::DrawerPhaseItem;
+
+/// Defines a Hephae-only pass in the [`Core2d`] render graph. The implementing type itself is used
+/// as the node's label.
+pub trait HephaePass: RenderLabel + Default {
+    /// The node this pass runs after, e.g. `Node2d::StartMainPass` for backgrounds or
+    /// `Node2d::Tonemapping` for overlays.
+    fn after() -> impl RenderLabel;
+
+    /// The node this pass runs before.
+    fn before() -> impl RenderLabel;
+
+    /// How the color target is loaded. `None` defers to the view target, i.e., clears with the
+    /// camera's clear color if nothing has been rendered into it this frame and loads otherwise.
+    #[inline]
+    fn load() -> Option<LoadOp<LinearRgba>> {
+        None
+    }
+
+    /// Whether the rendered contents are stored.
+    #[inline]
+    fn store() -> StoreOp {
+        StoreOp::Store
+    }
+}
+
+/// [`DrawerPhaseItem`] of [`HephaePassNode<P>`], sorted by layer.
+pub struct HephaePassItem<P: HephaePass> {
+    /// The layer; items with lower layers are drawn first.
+    pub sort_key: FloatOrd,
+    /// The render and main entities of the drawer.
+    pub entity: (Entity, MainEntity),
+    /// The specialized pipeline.
+    pub pipeline: CachedRenderPipelineId,
+    /// The draw function.
+    pub draw_function: DrawFunctionId,
+    /// The batch range.
+    pub batch_range: Range<u32>,
+    /// The draw request index of the drawer.
+    pub command: usize,
+    /// The extra index.
+    pub extra_index: PhaseItemExtraIndex,
+    _marker: PhantomData<fn() -> P>,
+}
+
+impl<P: HephaePass> PhaseItem for HephaePassItem<P> {
+    #[inline]
+    fn entity(&self) -> Entity {
+        self.entity.0
+    }
+
+    #[inline]
+    fn main_entity(&self) -> MainEntity {
+        self.entity.1
+    }
+
+    #[inline]
+    fn draw_function(&self) -> DrawFunctionId {
+        self.draw_function
+    }
+
+    #[inline]
+    fn batch_range(&self) -> &Range<u32> {
+        &self.batch_range
+    }
+
+    #[inline]
+    fn batch_range_mut(&mut self) -> &mut Range<u32> {
+        &mut self.batch_range
+    }
+
+    #[inline]
+    fn extra_index(&self) -> PhaseItemExtraIndex {
+        self.extra_index.clone()
+    }
+
+    #[inline]
+    fn batch_range_and_extra_index_mut(&mut self) -> (&mut Range<u32>, &mut PhaseItemExtraIndex) {
+        (&mut self.batch_range, &mut self.extra_index)
+    }
+}
+
+impl<P: HephaePass> SortedPhaseItem for HephaePassItem<P> {
+    type SortKey = FloatOrd;
+
+    #[inline]
+    fn sort_key(&self) -> Self::SortKey {
+        self.sort_key
+    }
+
+    #[inline]
+    fn sort(items: &mut [Self]) {
+        items.sort_by_key(|item| item.sort_key)
+    }
+
+    #[inline]
+    fn indexed(&self) -> bool {
+        true
+    }
+}
+
+impl<P: HephaePass> CachedRenderPipelinePhaseItem for HephaePassItem<P> {
+    #[inline]
+    fn cached_pipeline(&self) -> CachedRenderPipelineId {
+        self.pipeline
+    }
+}
+
+impl<P: HephaePass> DrawerPhaseItem for HephaePassItem<P> {
+    #[inline]
+    fn create(
+        layer: f32,
+        entity: (Entity, MainEntity),
+        pipeline: CachedRenderPipelineId,
+        draw_function: DrawFunctionId,
+        command: usize,
+    ) -> Self {
+        Self {
+            sort_key: FloatOrd(layer),
+            entity,
+            pipeline,
+            draw_function,
+            batch_range: 0..0,
+            command,
+            extra_index: PhaseItemExtraIndex::None,
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn command(&self) -> usize {
+        self.command
+    }
+
+    #[inline]
+    fn setup(app: &mut App) {
+        if !app.is_plugin_added::<HephaePassPlugin<P>>() {
+            app.add_plugins(HephaePassPlugin::<P>::default());
+        }
+    }
+}
+
+plugin_def! {
+    /// Sets up the phase and [`HephaePassNode`] of `P`. Added automatically by
+    /// [`VertexPlugin`](crate::VertexPlugin)s whose item is [`HephaePassItem<P>`].
+    pub struct HephaePassPlugin<P: HephaePass>;
+    fn build(&self, app: &mut App) {
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<DrawFunctions<HephaePassItem<P>>>()
+                .init_resource::<ViewSortedRenderPhases<HephaePassItem<P>>>()
+                .add_systems(ExtractSchedule, extract_pass_phases::<P>)
+                .add_systems(Render, sort_phase_system::<HephaePassItem<P>>.in_set(RenderSet::PhaseSort))
+                .add_render_graph_node::<ViewNodeRunner<HephaePassNode<P>>>(Core2d, P::default())
+                .add_render_graph_edges(Core2d, (P::after(), P::default(), P::before()));
+        }
+    }
+}
+
+/// Creates phases of `P` for every active 2D camera.
+pub fn extract_pass_phases<P: HephaePass>(
+    mut phases: ResMut<ViewSortedRenderPhases<HephaePassItem<P>>>,
+    cameras: Extract<Query<(Entity, &Camera), With<Camera2d>>>,
+    mut live_entities: Local<HashSet<RetainedViewEntity>>,
+) {
+    live_entities.clear();
+    for (main_entity, camera) in &cameras {
+        if !camera.is_active {
+            continue
+        }
+
+        let retained_view_entity = RetainedViewEntity::new(main_entity.into(), None, 0);
+        phases.insert_or_clear(retained_view_entity);
+        live_entities.insert(retained_view_entity);
+    }
+
+    phases.retain(|view, _| live_entities.contains(view));
+}
+
+/// Renders the [`HephaePassItem<P>`] phase of each view into its main texture.
+pub struct HephaePassNode<P: HephaePass>(PhantomData<fn() -> P>);
+impl<P: HephaePass> Default for HephaePassNode<P> {
+    #[inline]
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<P: HephaePass> ViewNode for HephaePassNode<P> {
+    type ViewQuery = (
+        &'static ExtractedCamera,
+        &'static ExtractedView,
+        &'static ViewTarget,
+        &'static ViewDepthTexture,
+    );
+
+    fn run<'w>(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        (camera, view, target, depth): QueryItem<'w, Self::ViewQuery>,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        let Some(phases) = world.get_resource::<ViewSortedRenderPhases<HephaePassItem<P>>>() else {
+            return Ok(())
+        };
+        let Some(phase) = phases.get(&view.retained_view_entity) else { return Ok(()) };
+
+        // Only skip when the pass doesn't need to clear anything on its own.
+        let load = P::load();
+        if phase.items.is_empty() && !matches!(load, Some(LoadOp::Clear(..))) {
+            return Ok(())
+        }
+
+        let mut color_attachment = target.get_color_attachment();
+        color_attachment.ops = Operations {
+            load: match load {
+                None => color_attachment.ops.load,
+                Some(LoadOp::Clear(color)) => LoadOp::Clear(color.into()),
+                Some(LoadOp::Load) => LoadOp::Load,
+            },
+            store: P::store(),
+        };
+
+        let label = format!("hephae_pass_{:?}", P::default());
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some(label.as_str()),
+            color_attachments: &[Some(color_attachment)],
+            depth_stencil_attachment: Some(depth.get_attachment(StoreOp::Store)),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        if let Some(viewport) = camera.viewport.as_ref() {
+            render_pass.set_camera_viewport(viewport);
+        }
+
+        if let Err(error) = phase.render(&mut render_pass, world, graph.view_entity()) {
+            error!("Error encountered while rendering Hephae pass {:?}: {error:?}", P::default());
+        }
+
+        Ok(())
+    }
+}
@@ -35,25 +35,28 @@ use bevy::{
             ViewSortedRenderPhases,
         },
         render_resource::{
-            BindGroup, BindGroupEntry, BindGroupLayout, BindingResource, BlendState, Buffer, BufferAddress, BufferBinding,
-            BufferDescriptor, BufferId, BufferInitDescriptor, BufferSize, BufferUsages, ColorTargetState, ColorWrites,
-            CompareFunction, DepthBiasState, DepthStencilState, FragmentState, FrontFace, IndexFormat, MultisampleState,
-            PipelineCache, PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipelineDescriptor, SamplerId,
-            ShaderDefVal, ShaderStages, ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines, StencilFaceState,
-            StencilState, TextureFormat, TextureViewId, VertexBufferLayout, VertexState, VertexStepMode,
-            binding_types::uniform_buffer,
+            BindGroup, BindGroupEntry, BindGroupLayout, BindingResource, BlendState, BufferAddress, BufferBinding, BufferId,
+            BufferUsages, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, FragmentState,
+            FrontFace, IndexFormat, MultisampleState, PipelineCache, PolygonMode, PrimitiveState, PrimitiveTopology,
+            RenderPipelineDescriptor, SamplerId, ShaderDefVal, ShaderStages, ShaderType, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, StencilFaceState, StencilState, TextureFormat, TextureViewId, VertexBufferLayout,
+            VertexState, VertexStepMode, binding_types::uniform_buffer,
         },
         renderer::{RenderDevice, RenderQueue},
         sync_world::MainEntity,
         texture::{FallbackImage, GpuImage},
-        view::{ExtractedView, RetainedViewEntity, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
+        view::{ExtractedView, RenderLayers, RetainedViewEntity, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
     },
 };
 use bytemuck::cast_slice;
 use fixedbitset::FixedBitSet;
 use vec_belt::VecBelt;
 
-use crate::vertex::{DrawItems, DrawerPhaseItem, Vertex};
+use crate::{
+    buffer::{BufferRing, HephaeBufferPolicy},
+    instance::SpriteInstance,
+    vertex::{DrawItem, DrawItems, DrawerPhaseItem, Vertex},
+};
 
 /// Common pipeline descriptor for use in [specialization](Vertex::specialize_pipeline). See the
 /// module-level documentation.
@@ -242,22 +245,28 @@ impl<T: Vertex> SpecializedRenderPipeline for VertexPipeline<T> {
 pub struct DrawBuffers<T: Vertex> {
     pub(crate) vertices: VecBelt<T>,
     pub(crate) indices: VecBelt<u32>,
-    vertex_buffer: Buffer,
+    pub(crate) instances: VecBelt<SpriteInstance>,
+    pub(crate) vertex_buffer: BufferRing,
+    pub(crate) instance_buffer: BufferRing,
+    /// The first expanded vertex and the amount of instances this frame.
+    pub(crate) instanced: (u32, u32),
+    /// Whether instanced requests get expanded, i.e.,
+    /// [`InstancePlugin`](crate::instance::InstancePlugin) is added and its pipeline has
+    /// compiled. Instanced requests are skipped otherwise.
+    pub(crate) instances_ready: bool,
 }
 
 impl<T: Vertex> FromWorld for DrawBuffers<T> {
     #[inline]
-    fn from_world(world: &mut World) -> Self {
-        let device = world.resource::<RenderDevice>();
+    fn from_world(_: &mut World) -> Self {
         Self {
             vertices: VecBelt::new(4096),
             indices: VecBelt::new(6144),
-            vertex_buffer: device.create_buffer(&BufferDescriptor {
-                label: Some("hephae_vertex_buffer"),
-                size: (4096 * size_of::<T>()) as BufferAddress,
-                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-                mapped_at_creation: false,
-            }),
+            instances: VecBelt::new(1024),
+            vertex_buffer: BufferRing::new("hephae_vertex_buffer", BufferUsages::VERTEX),
+            instance_buffer: BufferRing::new("hephae_instance_buffer", BufferUsages::STORAGE),
+            instanced: (0, 0),
+            instances_ready: false,
         }
     }
 }
@@ -284,12 +293,14 @@ pub(crate) fn queue_vertices<T: Vertex>(
         &Msaa,
         Option<&Tonemapping>,
         Option<&DebandDither>,
+        Option<&RenderLayers>,
     )>,
     mut items: Query<(Entity, &MainEntity, &mut DrawItems<T>)>,
     mut iterated: Local<FixedBitSet>,
 ) {
     let draw_function = draw_functions.read().id::<DrawRequests<T>>();
-    for (mut visible_drawers, view, &msaa, tonemapping, dither) in &mut views {
+    for (mut visible_drawers, view, &msaa, tonemapping, dither, view_layers) in &mut views {
+        let view_layers = view_layers.cloned().unwrap_or_default();
         let Some(transparent_phase) = transparent_phases.get_mut(&view.retained_view_entity) else {
             continue
         };
@@ -320,7 +331,8 @@ pub(crate) fn queue_vertices<T: Vertex>(
                         .unwrap_or_else(PoisonError::into_inner)
                         .iter_mut()
                         .enumerate()
-                        .map(|(i, &mut (.., layer, ref key))| {
+                        .filter(|(_, item)| item.layers.as_ref().is_none_or(|layers| layers.intersects(&view_layers)))
+                        .map(|(i, &mut DrawItem { layer, ref key, .. })| {
                             T::Item::create(
                                 layer,
                                 (e, main_e),
@@ -349,7 +361,7 @@ pub struct ViewBindGroup<T: Vertex> {
 #[derive(Component)]
 pub struct ViewIndexBuffer<T: Vertex> {
     indices: Vec<u32>,
-    index_buffer: Option<Buffer>,
+    index_buffer: BufferRing,
     _marker: PhantomData<fn() -> T>,
 }
 
@@ -358,7 +370,7 @@ impl<T: Vertex> Default for ViewIndexBuffer<T> {
     fn default() -> Self {
         Self {
             indices: Vec::with_capacity(6144),
-            index_buffer: None,
+            index_buffer: BufferRing::new("hephae_index_buffer", BufferUsages::INDEX),
             _marker: PhantomData,
         }
     }
@@ -379,6 +391,7 @@ pub(crate) fn prepare_indices<T: Vertex>(
         (
             Res<RenderDevice>,
             Res<RenderQueue>,
+            Option<Res<HephaeBufferPolicy>>,
             ResMut<DrawBuffers<T>>,
             ResMut<ViewSortedRenderPhases<T::Item>>,
             Query<(&ExtractedView, &mut ViewIndexBuffer<T>)>,
@@ -390,25 +403,36 @@ pub(crate) fn prepare_indices<T: Vertex>(
     mut batches: Local<HashMap<(RetainedViewEntity, Entity), (T::PipelineKey, Range<u32>)>>,
     mut batched_results: Local<HashMap<(RetainedViewEntity, Entity), (T::BatchProp, Range<u32>)>>,
 ) {
-    let (device, queue, buffers, mut transparent_phases, mut views, mut items) = param_set.p0();
+    let (device, queue, policy, buffers, mut transparent_phases, mut views, mut items) = param_set.p0();
+    let policy = policy.as_deref().copied().unwrap_or_default();
 
     let buffers = buffers.into_inner();
     buffers.vertices.clear(|vertices| {
-        let contents = cast_slice::<T, u8>(&vertices);
-        if (buffers.vertex_buffer.size() as usize) < contents.len() {
-            buffers.vertex_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
-                label: Some("hephae_vertex_buffer"),
-                contents,
-                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-            });
-        } else if let Some(len) = BufferSize::new(contents.len() as u64) {
-            queue
-                .write_buffer_with(&buffers.vertex_buffer, 0, len)
-                .unwrap()
-                .copy_from_slice(contents);
-        }
+        buffers.instances.clear(|instances| {
+            // Instances are expanded past the end of the directly queued vertices.
+            let base = vertices.len();
+            let len = base + instances.len() * 4;
+
+            if !instances.is_empty() {
+                buffers.vertex_buffer.add_usage(BufferUsages::STORAGE);
+                buffers
+                    .instance_buffer
+                    .write(&device, &queue, &policy, cast_slice::<SpriteInstance, u8>(&instances));
+            }
+
+            buffers.instanced = (base as u32, instances.len() as u32);
+            buffers.vertex_buffer.write_sized(
+                &device,
+                &queue,
+                &policy,
+                cast_slice::<T, u8>(&vertices),
+                (len * size_of::<T>()) as BufferAddress,
+            )
+        })
     });
 
+    let (instance_base, instances_ready) = (buffers.instanced.0, buffers.instances_ready);
+
     buffers.indices.clear(|indices| {
         for (view, view_indices) in &mut views {
             let Some(transparent_phase) = transparent_phases.get_mut(&view.retained_view_entity) else {
@@ -430,12 +454,31 @@ pub(crate) fn prepare_indices<T: Vertex>(
                     continue;
                 };
 
-                let Some((range, .., key)) = items.0.get_mut().unwrap_or_else(PoisonError::into_inner).get(item.command())
+                let Some(DrawItem {
+                    indices: range,
+                    key,
+                    instanced,
+                    ..
+                }) = items.0.get_mut().unwrap_or_else(PoisonError::into_inner).get(item.command())
                 else {
                     continue
                 };
 
-                view_indices.indices.extend(&indices[range.clone()]);
+                // Without expansion, the instanced vertices are zeroed or stale from a previous frame.
+                if *instanced && !instances_ready {
+                    warn_once!(
+                        "Skipping instanced draw requests; `InstancePlugin` isn't added or its pipeline hasn't compiled yet"
+                    );
+                    continue
+                }
+
+                if *instanced {
+                    view_indices
+                        .indices
+                        .extend(indices[range.clone()].iter().map(|&index| index + instance_base));
+                } else {
+                    view_indices.indices.extend(&indices[range.clone()]);
+                }
                 if match batch_key {
                     None => true,
                     Some(ref batch_key) => batch_key != key,
@@ -456,23 +499,9 @@ pub(crate) fn prepare_indices<T: Vertex>(
                 batch_key = Some(key.clone());
             }
 
-            let contents = cast_slice::<u32, u8>(&view_indices.indices);
-            if view_indices
+            view_indices
                 .index_buffer
-                .as_ref()
-                .is_none_or(|index_buffer| (index_buffer.size() as usize) < contents.len())
-            {
-                view_indices.index_buffer = Some(device.create_buffer_with_data(&BufferInitDescriptor {
-                    label: Some("hephae_index_buffer"),
-                    contents,
-                    usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
-                }));
-            } else if let Some(len) = BufferSize::new(contents.len() as u64) {
-                queue
-                    .write_buffer_with(view_indices.index_buffer.as_ref().unwrap(), 0, len)
-                    .unwrap()
-                    .copy_from_slice(contents);
-            }
+                .write(&device, &queue, &policy, cast_slice::<u32, u8>(&view_indices.indices));
         }
     });
 
@@ -598,12 +627,14 @@ impl<P: PhaseItem, T: Vertex> RenderCommand<P> for DrawBatch<T> {
             return RenderCommandResult::Skip;
         };
 
-        let Some(ref index_buffer) = index_buffer.index_buffer else {
+        let (Some(vertex_buffer), Some(index_buffer)) = (
+            buffers.into_inner().vertex_buffer.current(),
+            index_buffer.index_buffer.current(),
+        ) else {
             return RenderCommandResult::Skip;
         };
 
-        let buffers = buffers.into_inner();
-        pass.set_vertex_buffer(0, buffers.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(0, vertex_buffer.slice(..));
         pass.set_index_buffer(index_buffer.slice(..), 0, IndexFormat::Uint32);
         pass.draw_indexed(range.clone(), 0, 0..1);
 
@@ -0,0 +1,567 @@
+//! Provides [`Polygon`] triangulation and [`PolygonShaper`], a dynamically-sized counterpart of
+//! [`Shaper`](crate::attribute::Shaper).
+//!
+//! Useful for shapes whose vertex count isn't known at compile time, e.g. destructible terrain or
+//! editor-drawn shapes. Polygons may contain holes and are triangulated through ear clipping.
+
+use std::ops::Range;
+
+use bevy::{math::Affine3A, prelude::*};
+
+use crate::{
+    attribute::{Attrib, ByteColorAttrib, ColorAttrib, HasAttrib, Nor, Pos2dAttrib, UvAttrib},
+    drawer::VertexQueuer,
+    vertex::Vertex,
+};
+
+/// A simple polygon outline, optionally with holes.
+///
+/// The winding order of the outline and the holes doesn't matter, as it is normalized when
+/// [triangulating](Polygon::triangulate). Holes are expected to lie entirely inside the outline and
+/// not overlap each other.
+#[derive(Debug, Clone, Default)]
+pub struct Polygon {
+    points: Vec<Vec2>,
+    holes: Vec<usize>,
+}
+
+impl Polygon {
+    /// Creates a new polygon with the given outline.
+    #[inline]
+    pub fn new(outline: impl IntoIterator<Item = Vec2>) -> Self {
+        Self {
+            points: outline.into_iter().collect(),
+            holes: Vec::new(),
+        }
+    }
+
+    /// Cuts a hole into this polygon.
+    #[inline]
+    pub fn hole(&mut self, hole: impl IntoIterator<Item = Vec2>) -> &mut Self {
+        self.holes.push(self.points.len());
+        self.points.extend(hole);
+        self
+    }
+
+    /// Returns all points of this polygon, starting from the outline and followed by each holes.
+    /// Indices returned by [`triangulate`](Self::triangulate) refer to this slice.
+    #[inline]
+    pub fn points(&self) -> &[Vec2] {
+        &self.points
+    }
+
+    /// Returns the outline of this polygon.
+    #[inline]
+    pub fn outline(&self) -> &[Vec2] {
+        &self.points[self.range(0)]
+    }
+
+    /// Returns the holes of this polygon.
+    #[inline]
+    pub fn holes(&self) -> impl Iterator<Item = &[Vec2]> {
+        (1..=self.holes.len()).map(|i| &self.points[self.range(i)])
+    }
+
+    /// Returns the bounding rectangle of the outline.
+    pub fn bounds(&self) -> Rect {
+        let mut outline = self.outline().iter().copied();
+        let Some(first) = outline.next() else { return Rect::default() };
+
+        outline.fold(Rect::from_corners(first, first), |rect, point| rect.union_point(point))
+    }
+
+    /// Whether any two non-adjacent edges of the outline and holes properly cross each other.
+    /// Edges that merely touch or overlap collinearly aren't considered crossing.
+    fn self_intersects(&self) -> bool {
+        let edges = (0..=self.holes.len())
+            .flat_map(|ring| {
+                let range = self.range(ring);
+                let len = range.len();
+                range.clone().map(move |i| (i, range.start + (i - range.start + 1) % len))
+            })
+            .collect::<Vec<_>>();
+
+        let crosses = |a: Vec2, b: Vec2, c: Vec2, d: Vec2| (b - a).perp_dot(c - a) * (b - a).perp_dot(d - a) < 0.;
+        edges.iter().enumerate().any(|(i, &(a, b))| {
+            edges[i + 1..].iter().any(|&(c, d)| {
+                if a == c || a == d || b == c || b == d {
+                    return false
+                }
+
+                let (pa, pb, pc, pd) = (self.points[a], self.points[b], self.points[c], self.points[d]);
+                crosses(pa, pb, pc, pd) && crosses(pc, pd, pa, pb)
+            })
+        })
+    }
+
+    #[inline]
+    fn range(&self, ring: usize) -> Range<usize> {
+        let start = match ring {
+            0 => 0,
+            i => self.holes[i - 1],
+        };
+
+        start..self.holes.get(ring).copied().unwrap_or(self.points.len())
+    }
+
+    /// Triangulates this polygon, appending triangle-list indices into [`points`](Self::points) to
+    /// `indices`. Returns `false` without appending anything if the outline has fewer than 3
+    /// points, or if any edges of the outline and holes cross each other.
+    ///
+    /// Holes are first bridged into the outline, and the resulting ring is then triangulated by
+    /// ear clipping. This runs in quadratic time, which is fine for the few hundreds of points
+    /// editor-drawn shapes usually consist of.
+    pub fn triangulate(&self, indices: &mut Vec<u32>) -> bool {
+        let points = &self.points;
+        let mut ring = self.range(0).collect::<Vec<_>>();
+        if ring.len() < 3 || self.self_intersects() {
+            return false
+        }
+
+        // Normalize the outline to counter-clockwise and the holes to clockwise.
+        if signed_area(points, &ring) < 0. {
+            ring.reverse();
+        }
+
+        let mut holes = (1..=self.holes.len())
+            .map(|i| self.range(i).collect::<Vec<_>>())
+            .filter(|hole| hole.len() >= 3)
+            .map(|mut hole| {
+                if signed_area(points, &hole) > 0. {
+                    hole.reverse();
+                }
+
+                hole
+            })
+            .collect::<Vec<_>>();
+
+        // Bridge the holes from right to left, so that bridges never cross each other.
+        let max_x = |hole: &[usize]| hole.iter().map(|&i| points[i].x).fold(f32::NEG_INFINITY, f32::max);
+        holes.sort_by(|a, b| max_x(b).total_cmp(&max_x(a)));
+        for hole in &holes {
+            bridge(points, &mut ring, hole);
+        }
+
+        clip(points, ring, indices);
+        true
+    }
+}
+
+#[inline]
+fn cross(a: Vec2, b: Vec2, c: Vec2) -> f32 {
+    (b - a).perp_dot(c - b)
+}
+
+fn signed_area(points: &[Vec2], ring: &[usize]) -> f32 {
+    let mut area = 0.;
+    for (i, &a) in ring.iter().enumerate() {
+        let b = ring[(i + 1) % ring.len()];
+        area += points[a].perp_dot(points[b]);
+    }
+
+    area * 0.5
+}
+
+#[inline]
+fn in_triangle(a: Vec2, b: Vec2, c: Vec2, p: Vec2) -> bool {
+    let d0 = (b - a).perp_dot(p - a);
+    let d1 = (c - b).perp_dot(p - b);
+    let d2 = (a - c).perp_dot(p - c);
+
+    (d0 >= 0. && d1 >= 0. && d2 >= 0.) || (d0 <= 0. && d1 <= 0. && d2 <= 0.)
+}
+
+/// Connects `hole` to `ring` with a zero-width bridge from the hole's rightmost point to a point in
+/// the ring visible from it, turning both into one ring.
+fn bridge(points: &[Vec2], ring: &mut Vec<usize>, hole: &[usize]) {
+    let Some((start, m)) = hole
+        .iter()
+        .enumerate()
+        .max_by(|&(.., &a), &(.., &b)| points[a].x.total_cmp(&points[b].x))
+        .map(|(i, &m)| (i, points[m]))
+    else {
+        return
+    };
+
+    // Cast a ray towards +X and find the nearest ring edge it hits.
+    let len = ring.len();
+    let mut nearest = None::<(f32, usize)>;
+    for i in 0..len {
+        let a = points[ring[i]];
+        let b = points[ring[(i + 1) % len]];
+        if a.y == b.y || m.y < a.y.min(b.y) || m.y > a.y.max(b.y) {
+            continue
+        }
+
+        let x = a.x + (m.y - a.y) * (b.x - a.x) / (b.y - a.y);
+        if x >= m.x && nearest.is_none_or(|(nearest_x, ..)| x < nearest_x) {
+            nearest = Some((x, if a.x > b.x { i } else { (i + 1) % len }));
+        }
+    }
+
+    // The hole is outside of the outline; nothing sensible may be done.
+    let Some((hit_x, mut target)) = nearest else { return };
+
+    // If any ring point lies within the triangle formed by the hole point, the hit point, and the
+    // candidate, the candidate isn't visible. Pick the one closest in angle to the ray instead.
+    let hit = vec2(hit_x, m.y);
+    let candidate = points[ring[target]];
+    if hit != candidate {
+        let mut best = f32::INFINITY;
+        for (i, &p) in ring.iter().enumerate() {
+            let p = points[p];
+            if i == target || p.x < m.x || p == candidate || !in_triangle(m, hit, candidate, p) {
+                continue
+            }
+
+            let d = p - m;
+            let tan = d.y.abs() / d.x.max(f32::EPSILON);
+            if tan < best {
+                best = tan;
+                target = i;
+            }
+        }
+    }
+
+    let mut insert = Vec::with_capacity(hole.len() + 2);
+    insert.extend((0..=hole.len()).map(|i| hole[(start + i) % hole.len()]));
+    insert.push(ring[target]);
+    ring.splice(target + 1..target + 1, insert);
+}
+
+fn is_ear(points: &[Vec2], ring: &[usize], prev: usize, current: usize, next: usize) -> bool {
+    let (a, b, c) = (points[ring[prev]], points[ring[current]], points[ring[next]]);
+    if cross(a, b, c) <= 0. {
+        return false
+    }
+
+    !ring.iter().enumerate().any(|(i, &p)| {
+        let p = points[p];
+        i != prev && i != current && i != next && p != a && p != b && p != c && in_triangle(a, b, c, p)
+    })
+}
+
+fn clip(points: &[Vec2], mut ring: Vec<usize>, indices: &mut Vec<u32>) {
+    let mut current = 0;
+    let mut stall = 0;
+
+    while ring.len() > 3 {
+        let len = ring.len();
+        current %= len;
+
+        let prev = (current + len - 1) % len;
+        let next = (current + 1) % len;
+
+        if is_ear(points, &ring, prev, current, next) {
+            indices.extend([ring[prev], ring[current], ring[next]].map(|i| i as u32));
+            ring.remove(current);
+
+            // Step back, since removing this ear may have turned the previous point into one.
+            current = prev.min(ring.len() - 1);
+            stall = 0;
+        } else if stall >= len {
+            // No ears left; the remaining ring is degenerate. Discard zero-area points first, and only
+            // when there are none, forcefully clip the current point so that the loop terminates.
+            match (0..len).find(|&i| {
+                cross(
+                    points[ring[(i + len - 1) % len]],
+                    points[ring[i]],
+                    points[ring[(i + 1) % len]],
+                ) == 0.
+            }) {
+                Some(i) => {
+                    ring.remove(i);
+                }
+                None => {
+                    indices.extend([ring[prev], ring[current], ring[next]].map(|i| i as u32));
+                    ring.remove(current);
+                }
+            }
+
+            stall = 0;
+        } else {
+            current += 1;
+            stall += 1;
+        }
+    }
+
+    if let &[a, b, c] = ring.as_slice() {
+        if cross(points[a], points[b], points[c]) != 0. {
+            indices.extend([a, b, c].map(|i| i as u32));
+        }
+    }
+}
+
+/// Similar to [`Shaper`](crate::attribute::Shaper), except the vertices are backed by a
+/// triangulated [`Polygon`] instead of a fixed-size array.
+///
+/// Positions are always set from the polygon's points; the rest of the attributes may be set
+/// through the provided methods. Since attributes are stored per-point, the shaper may be cached
+/// and [queued](Self::queue) multiple times.
+#[derive(Debug, Clone)]
+pub struct PolygonShaper<T: Vertex> {
+    /// The raw vertices for manual access, one for each of the polygon's points.
+    pub vertices: Vec<T>,
+    /// The triangle-list indices into [`vertices`](Self::vertices).
+    pub indices: Vec<u32>,
+    positions: Vec<Vec2>,
+    bounds: Rect,
+    transform: Option<Affine3A>,
+}
+
+impl<T: Vertex + HasAttrib<Pos2dAttrib>> PolygonShaper<T> {
+    /// Triangulates the polygon and creates a shaper with its points as 2D positions. If the
+    /// polygon can't be [triangulated](Polygon::triangulate), the shaper has no indices.
+    pub fn new(polygon: &Polygon) -> Self {
+        let mut indices = Vec::new();
+        polygon.triangulate(&mut indices);
+
+        let mut this = Self {
+            vertices: vec![T::zeroed(); polygon.points().len()],
+            indices,
+            positions: polygon.points().to_vec(),
+            bounds: polygon.bounds(),
+            transform: None,
+        };
+
+        this.attribs_with::<Pos2dAttrib>(|pos| pos);
+        this
+    }
+
+    /// Finishes using the [`PolygonShaper`] API, applying the
+    /// [transform](Self::with_transform) if any.
+    pub fn queue(&self, queuer: &impl VertexQueuer<Vertex = T>, layer: f32, key: T::PipelineKey) {
+        if self.indices.is_empty() {
+            return
+        }
+
+        let offset = match self.transform {
+            None => queuer.data(self.vertices.as_slice()),
+            Some(transform) => {
+                let mut vertices = self.vertices.clone();
+                write_attribs::<T, Pos2dAttrib>(&mut vertices, &self.positions, |pos| {
+                    transform.transform_point3(pos.extend(0.)).truncate()
+                });
+
+                queuer.data(vertices.as_slice())
+            }
+        };
+
+        let indices = self.indices.iter().map(|&i| i + offset).collect::<Vec<_>>();
+        queuer.request(layer, key, indices.as_slice())
+    }
+}
+
+impl<T: Vertex> PolygonShaper<T> {
+    /// Returns the bounding rectangle of the polygon's outline, in local space.
+    #[inline]
+    pub fn bounds(&self) -> Rect {
+        self.bounds
+    }
+
+    /// Sets a vertex attribute for all vertices, computed from their local position.
+    #[inline]
+    pub fn attribs_with<M: Attrib>(&mut self, attribute: impl FnMut(Vec2) -> M::Data) -> &mut Self
+    where T: HasAttrib<M> {
+        write_attribs::<T, M>(&mut self.vertices, &self.positions, attribute);
+        self
+    }
+
+    /// Sets the transform applied to the positions when [queued](Self::queue). Replaces any
+    /// previously set transform.
+    #[inline]
+    pub fn with_transform(&mut self, transform: impl Into<Affine3A>) -> &mut Self {
+        self.transform = Some(transform.into());
+        self
+    }
+
+    /// Convenience method for [`with_transform`](Self::with_transform) that uses the affine
+    /// matrix of a [`GlobalTransform`].
+    #[inline]
+    pub fn transform(&mut self, transform: &GlobalTransform) -> &mut Self {
+        self.with_transform(transform.affine())
+    }
+
+    /// Colors all vertices with a uniform color.
+    #[inline]
+    pub fn color<const INDEX: usize>(&mut self, color: impl Into<LinearRgba>) -> &mut Self
+    where T: HasAttrib<ColorAttrib<INDEX>> {
+        let color = color.into();
+        self.attribs_with::<ColorAttrib<INDEX>>(|_| color)
+    }
+
+    /// Colors all vertices with a uniform color.
+    #[inline]
+    pub fn byte_color<const INDEX: usize>(&mut self, color: [Nor<u8>; 4]) -> &mut Self
+    where T: HasAttrib<ByteColorAttrib<INDEX>> {
+        self.attribs_with::<ByteColorAttrib<INDEX>>(|_| color)
+    }
+
+    /// Assigns UV coordinates by projecting `bounds` in local space onto an atlas sprite entry.
+    /// Positions outside of `bounds` extrapolate beyond the sprite entry. Note that this flips the
+    /// V coordinate, similar to [`Shaper::uv_rect`](crate::attribute::Shaper::uv_rect).
+    #[inline]
+    pub fn uv_project<const INDEX: usize>(&mut self, bounds: Rect, rect: URect, atlas_size: UVec2) -> &mut Self
+    where T: HasAttrib<UvAttrib<INDEX>> {
+        let page = atlas_size.as_vec2();
+        let uv_min = vec2(rect.min.x as f32, rect.max.y as f32) / page;
+        let uv_max = vec2(rect.max.x as f32, rect.min.y as f32) / page;
+        // Keep degenerate bounds from dividing by zero; their points all project onto the sprite's edge.
+        let size = bounds.size().max(Vec2::splat(f32::EPSILON));
+
+        self.attribs_with::<UvAttrib<INDEX>>(|pos| uv_min + (uv_max - uv_min) * (pos - bounds.min) / size)
+    }
+
+    /// Convenience method for [`uv_project`](Self::uv_project) that projects the polygon's
+    /// [bounds](Self::bounds), stretching the atlas sprite entry over the whole polygon.
+    #[inline]
+    pub fn uv_fit<const INDEX: usize>(&mut self, rect: URect, atlas_size: UVec2) -> &mut Self
+    where T: HasAttrib<UvAttrib<INDEX>> {
+        self.uv_project::<INDEX>(self.bounds, rect, atlas_size)
+    }
+}
+
+fn write_attribs<T: HasAttrib<M>, M: Attrib>(
+    vertices: &mut [T],
+    positions: &[Vec2],
+    mut attribute: impl FnMut(Vec2) -> M::Data,
+) {
+    let offset = const { <T as HasAttrib<M>>::OFFSET };
+    for (vertex, &pos) in vertices.iter_mut().zip(positions) {
+        let data = attribute(pos);
+
+        // Safety: `HasAttrib` guarantees the offset points to a field of type `M::Data`.
+        unsafe {
+            (vertex as *mut T)
+                .cast::<u8>()
+                .add(offset)
+                .copy_from_nonoverlapping((&raw const data).cast::<u8>(), size_of::<M::Data>())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::tests::Vert;
+
+    /// Triangulates `polygon`, asserting that every triangle is counter-clockwise and
+    /// non-degenerate, and returns the triangle count along with their total area.
+    fn triangulate(polygon: &Polygon) -> (usize, f32) {
+        let mut indices = Vec::new();
+        assert!(polygon.triangulate(&mut indices));
+        assert_eq!(indices.len() % 3, 0);
+
+        let points = polygon.points();
+        let area = indices.chunks_exact(3).fold(0., |area, triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| points[triangle[i] as usize]);
+            let triangle_area = (b - a).perp_dot(c - a) * 0.5;
+            assert!(triangle_area > 0., "triangle {triangle:?} isn't counter-clockwise");
+
+            area + triangle_area
+        });
+
+        (indices.len() / 3, area)
+    }
+
+    fn square() -> [Vec2; 4] {
+        [vec2(0., 0.), vec2(2., 0.), vec2(2., 2.), vec2(0., 2.)]
+    }
+
+    #[test]
+    fn convex() {
+        assert_eq!(triangulate(&Polygon::new(square())), (2, 4.));
+    }
+
+    #[test]
+    fn concave() {
+        // An L shape, with a reflex vertex at (1, 1).
+        let polygon = Polygon::new([
+            vec2(0., 0.),
+            vec2(2., 0.),
+            vec2(2., 1.),
+            vec2(1., 1.),
+            vec2(1., 2.),
+            vec2(0., 2.),
+        ]);
+
+        assert_eq!(triangulate(&polygon), (4, 3.));
+    }
+
+    #[test]
+    fn winding_order() {
+        let mut clockwise = square();
+        clockwise.reverse();
+
+        assert_eq!(triangulate(&Polygon::new(clockwise)), triangulate(&Polygon::new(square())));
+    }
+
+    #[test]
+    fn collinear() {
+        let polygon = Polygon::new([vec2(0., 0.), vec2(1., 0.), vec2(2., 0.), vec2(2., 2.), vec2(0., 2.)]);
+
+        let (count, area) = triangulate(&polygon);
+        assert_eq!(area, 4.);
+        assert!(count <= 3);
+    }
+
+    #[test]
+    fn holes() {
+        let mut polygon = Polygon::new([vec2(0., 0.), vec2(4., 0.), vec2(4., 4.), vec2(0., 4.)]);
+        polygon.hole([vec2(1., 1.), vec2(1., 3.), vec2(3., 3.), vec2(3., 1.)]);
+
+        let (.., area) = triangulate(&polygon);
+        assert_eq!(area, 12.);
+    }
+
+    #[test]
+    fn degenerate() {
+        let mut indices = Vec::new();
+        assert!(!Polygon::new([vec2(0., 0.), vec2(1., 0.)]).triangulate(&mut indices));
+        assert!(indices.is_empty());
+
+        // Zero-area polygons are valid, but have nothing to draw.
+        assert_eq!(
+            triangulate(&Polygon::new([vec2(0., 0.), vec2(1., 0.), vec2(2., 0.)])),
+            (0, 0.)
+        );
+    }
+
+    #[test]
+    fn self_intersecting() {
+        let mut indices = Vec::new();
+        let bowtie = Polygon::new([vec2(0., 0.), vec2(2., 2.), vec2(2., 0.), vec2(0., 2.)]);
+
+        assert!(!bowtie.triangulate(&mut indices));
+        assert!(indices.is_empty());
+
+        // Holes crossing the outline count too.
+        let mut polygon = Polygon::new(square());
+        polygon.hole([vec2(1., 1.), vec2(3., 1.), vec2(3., 1.5)]);
+        assert!(!polygon.triangulate(&mut indices));
+        assert!(indices.is_empty());
+    }
+
+    #[test]
+    fn uv_project() {
+        let mut shaper = PolygonShaper::<Vert>::new(&Polygon::new(square()));
+        shaper.uv_fit::<0>(URect::new(0, 0, 4, 4), uvec2(8, 8));
+
+        let uvs = shaper.vertices.iter().map(|vertex| vertex.uv).collect::<Vec<_>>();
+        assert_eq!(uvs, [vec2(0., 0.5), vec2(0.5, 0.5), vec2(0.5, 0.), vec2(0., 0.)]);
+    }
+
+    #[test]
+    fn uv_project_degenerate() {
+        // A flat line has zero height and a single point has no size at all; neither may produce NaNs.
+        for points in [vec![vec2(0., 1.), vec2(1., 1.), vec2(2., 1.)], vec![vec2(1., 1.); 3]] {
+            let mut shaper = PolygonShaper::<Vert>::new(&Polygon::new(points));
+            shaper.uv_fit::<0>(URect::new(0, 0, 4, 4), uvec2(8, 8));
+
+            for vertex in &shaper.vertices {
+                assert!(vertex.uv.is_finite(), "{:?} isn't finite", vertex.uv);
+                assert_eq!(vertex.uv.y, 0.5);
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Offscreen render targets for rendering Hephae content into [`Image`]s.
+//!
+//! [`HephaeRenderTarget`] is a thin wrapper over a [`Camera2d`] rendering into the given image,
+//! before any other camera by default; it isn't a camera-less view, so each target costs as much as
+//! any other camera. With its [layers](HephaeRenderTarget::layers), this may be used to render a
+//! subset of drawers into, e.g., portals, minimaps, or cached UI panels. Since the target is an
+//! ordinary [`Image`] asset, its [`AssetId`] may be used as a
+//! [`PipelineKey`](crate::vertex::Vertex::PipelineKey) of other drawers as-is.
+
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        view::RenderLayers,
+    },
+};
+
+/// Renders Hephae content visible to [`layers`](Self::layers) into [`image`](Self::image).
+///
+/// This is merely a convenience over a regular camera: it requires [`Camera2d`] and
+/// [`RenderLayers`], which are kept in sync with this component, i.e., the render target, clear
+/// color, order, and render layers are all overwritten whenever this component changes. The entity
+/// is therefore extracted and rendered as a full camera view.
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component, Debug)]
+#[require(Camera2d, RenderLayers)]
+pub struct HephaeRenderTarget {
+    /// The image to render into. Must have [`TextureUsages::RENDER_ATTACHMENT`]; see
+    /// [`create_image`](Self::create_image).
+    pub image: Handle<Image>,
+    /// How the image is cleared before rendering.
+    pub clear_color: ClearColorConfig,
+    /// The camera order. Defaults to `-1`, so that the image is rendered into before the
+    /// (default-ordered) cameras that sample it.
+    pub order: isize,
+    /// The render layers of drawers to render. Defaults to [`RenderLayers::default`], i.e., only
+    /// layer `0`.
+    pub layers: RenderLayers,
+}
+
+impl HephaeRenderTarget {
+    /// Creates a render target that clears to transparent before rendering.
+    #[inline]
+    pub fn new(image: Handle<Image>) -> Self {
+        Self {
+            image,
+            clear_color: ClearColorConfig::Custom(Color::NONE),
+            order: -1,
+            layers: RenderLayers::default(),
+        }
+    }
+
+    /// Sets the clear color.
+    #[inline]
+    pub fn with_clear_color(mut self, clear_color: impl Into<ClearColorConfig>) -> Self {
+        self.clear_color = clear_color.into();
+        self
+    }
+
+    /// Sets the camera order.
+    #[inline]
+    pub fn with_order(mut self, order: isize) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Sets the render layers.
+    #[inline]
+    pub fn with_layers(mut self, layers: RenderLayers) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Creates an empty image suitable as a render target, i.e., it may be both rendered into and
+    /// sampled from. The format should match the camera's output; that is,
+    /// [`TextureFormat::bevy_default`] for non-HDR cameras.
+    pub fn create_image(size: UVec2, format: TextureFormat) -> Image {
+        let mut image = Image::new_uninit(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            format,
+            RenderAssetUsages::RENDER_WORLD,
+        );
+
+        image.texture_descriptor.usage =
+            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+        image
+    }
+}
+
+/// Synchronizes [`HephaeRenderTarget`] into the [`Camera`] and [`RenderLayers`] of the same entity.
+pub fn sync_render_targets(
+    mut targets: Query<(&HephaeRenderTarget, &mut Camera, &mut RenderLayers), Changed<HephaeRenderTarget>>,
+) {
+    for (target, mut camera, mut layers) in &mut targets {
+        camera.target = RenderTarget::Image(target.image.clone().into());
+        camera.clear_color = target.clear_color;
+        camera.order = target.order;
+        *layers = target.layers.clone();
+    }
+}
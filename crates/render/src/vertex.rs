@@ -14,6 +14,7 @@ use bevy::{
         render_phase::{CachedRenderPipelinePhaseItem, DrawFunctionId, PhaseItemExtraIndex, RenderCommand, SortedPhaseItem},
         render_resource::{CachedRenderPipelineId, RenderPipelineDescriptor, TextureFormat},
         sync_world::MainEntity,
+        view::RenderLayers,
     },
 };
 use smallvec::SmallVec;
@@ -38,6 +39,11 @@ pub trait DrawerPhaseItem: CachedRenderPipelinePhaseItem + SortedPhaseItem {
 
     /// Returns the associated draw request index.
     fn command(&self) -> usize;
+
+    /// Further sets up the app for this phase item, e.g. by adding the plugin that creates its
+    /// phases. Called by [`VertexPlugin`](crate::VertexPlugin) before registering render commands.
+    #[allow(unused)]
+    fn setup(app: &mut App) {}
 }
 
 /// Implements [`DrawerPhaseItem`] for [`Transparent2d`] with its `extracted_index` field.
@@ -127,8 +133,21 @@ pub trait Vertex: Send + Sync + VertexLayout {
     fn create_batch(param: &mut SystemParamItem<Self::BatchParam>, key: Self::PipelineKey) -> Self::BatchProp;
 }
 
+/// A draw request issued by a [`Drawer`](crate::drawer::Drawer).
+pub(crate) struct DrawItem<K> {
+    /// The range in the shared index array.
+    pub indices: Range<usize>,
+    pub layer: f32,
+    pub key: K,
+    /// If set, only views whose render layers intersect these may draw this request.
+    pub layers: Option<RenderLayers>,
+    /// Whether the indices point into the [instance-expanded](crate::instance) vertices rather than
+    /// the ones queued directly.
+    pub instanced: bool,
+}
+
 #[derive(Component)]
-pub(crate) struct DrawItems<T: Vertex>(pub Mutex<SmallVec<[(Range<usize>, f32, T::PipelineKey); 8]>>);
+pub(crate) struct DrawItems<T: Vertex>(pub Mutex<SmallVec<[DrawItem<T::PipelineKey>; 8]>>);
 impl<T: Vertex> Default for DrawItems<T> {
     #[inline]
     fn default() -> Self {
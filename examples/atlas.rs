@@ -10,6 +10,7 @@ use bevy::{
             lifetimeless::{Read, SRes, SResMut},
         },
     },
+    math::Affine3A,
     prelude::*,
     render::{
         render_asset::RenderAssets,
@@ -126,11 +127,13 @@ impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetSpriteBindGroup<I> {
 
 #[derive(TypePath, Component, Copy, Clone, Default)]
 struct DrawSprite {
-    pos: Vec3,
-    scl: Vec2,
+    trns: Affine3A,
+    layer: f32,
     page: AssetId<Image>,
     page_size: UVec2,
     rect: URect,
+    size: UVec2,
+    rotated: bool,
 }
 
 impl Drawer for DrawSprite {
@@ -148,29 +151,37 @@ impl Drawer for DrawSprite {
         _: &SystemParamItem<Self::ExtractParam>,
         (&trns, cache): QueryItem<Self::ExtractData>,
     ) {
-        let Some(&AtlasInfo {
-            page, page_size, rect, ..
-        }) = cache.get(0)
+        let Some(
+            &info @ AtlasInfo {
+                page,
+                page_size,
+                rect,
+                rotated,
+                ..
+            },
+        ) = cache.get(0)
         else {
             return
         };
 
-        let (scale, .., translation) = trns.to_scale_rotation_translation();
         *drawer.get_or_default() = DrawSprite {
-            pos: translation,
-            scl: scale.truncate(),
+            trns: trns.affine(),
+            layer: trns.translation().z,
             page,
             page_size,
             rect,
+            size: info.size(),
+            rotated,
         };
     }
 
     #[inline]
     fn draw(&self, _: &SystemParamItem<Self::DrawParam>, queuer: &impl VertexQueuer<Vertex = Self::Vertex>) {
         Shaper::new()
-            .rect(self.pos.truncate(), self.rect.size().as_vec2() * self.scl)
-            .uv_rect(self.rect, self.page_size)
-            .queue_rect(queuer, self.pos.z, self.page)
+            .with_transform(self.trns)
+            .rect_anchor(Vec2::ZERO, self.size.as_vec2())
+            .uv_rect_rotated(self.rect, self.page_size, self.rotated)
+            .queue_rect(queuer, self.layer, self.page)
     }
 }
 